@@ -0,0 +1,205 @@
+use crate::database::storage::Backend;
+use crate::ratelimit::{self, RateLimiter};
+use crate::{audit, logging, metrics, openapi, routes, stream, versioning};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, App, Error};
+use std::time::Instant;
+use tracing::Instrument;
+
+/// The routes of API version 1. A new version gets its own function, which
+/// can reuse these routes and swap the handlers that change.
+pub fn v1(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(routes::health_check))
+        .service(
+            web::scope("/health")
+                .route("/live", web::get().to(routes::health_live))
+                .route("/ready", web::get().to(routes::health_ready)),
+        )
+        .service(
+            web::scope("/reconciliation")
+                .route("", web::get().to(routes::get_reconciliation))
+                .route("", web::put().to(routes::correct_reconciliation)),
+        )
+        .service(
+            web::scope("/backups")
+                .route("", web::post().to(routes::create_backup))
+                .route("", web::get().to(routes::get_backups))
+                .route("/restore", web::post().to(routes::restore_backup)),
+        )
+        .service(
+            web::scope("/webhooks")
+                .route("", web::post().to(routes::create_webhook))
+                .route("", web::get().to(routes::get_all_webhooks))
+                .route("/{id}", web::get().to(routes::get_webhook))
+                .route("/{id}", web::delete().to(routes::delete_webhook))
+                .route(
+                    "/{id}/deliveries",
+                    web::get().to(routes::get_webhook_deliveries),
+                ),
+        )
+        .service(
+            web::scope("/audit")
+                .route("", web::get().to(routes::get_audit_events))
+                .route("/verify", web::get().to(routes::verify_audit_chain)),
+        )
+        .route("token", web::get().to(routes::get_jwt))
+        .route("/stream", web::get().to(routes::stream_all))
+        .service(
+            web::scope("/transfers")
+                .route("", web::get().to(routes::get_all_transfers))
+                .route("/{id}/reversals", web::post().to(routes::reverse_transfer)),
+        )
+        .service(
+            web::scope("/payments")
+                .route("/{id}/reversals", web::post().to(routes::reverse_payment)),
+        )
+        .service(
+            web::scope("/batches")
+                .route("", web::post().to(routes::create_batch))
+                .route("/{id}", web::get().to(routes::get_batch)),
+        )
+        .service(web::scope("/reversals").route("", web::get().to(routes::get_all_reversals)))
+        .service(
+            web::scope("/customers")
+                .route("/transfers", web::put().to(routes::transfer_amount))
+                .route("", web::post().to(routes::create_customer))
+                .route("", web::get().to(routes::get_all_customers))
+                .route("/{id}", web::get().to(routes::get_customer))
+                .route("/{id}", web::put().to(routes::edit_customer))
+                .route("/{id}", web::patch().to(routes::patch_customer))
+                .route("/{id}/kyc", web::put().to(routes::verify_customer))
+                .route("/{id}/stream", web::get().to(routes::stream_customer))
+                .route(
+                    "/{id}/documents",
+                    web::post().to(routes::create_kyc_document),
+                )
+                .route(
+                    "/{id}/documents",
+                    web::get().to(routes::get_kyc_documents_by_customer),
+                )
+                .route(
+                    "/{id}/transfers",
+                    web::get().to(routes::get_transfers_by_customer),
+                )
+                .route("/{id}/payments", web::post().to(routes::create_payment))
+                .route(
+                    "/{id}/payments",
+                    web::get().to(routes::get_payments_by_customer),
+                )
+                .route("/{id}/pain.001", web::post().to(routes::import_pain001))
+                .route("/{id}/camt.053", web::get().to(routes::export_camt053))
+                .route("/{id}/payees", web::post().to(routes::create_payee))
+                .route(
+                    "/{id}/payees",
+                    web::get().to(routes::get_payees_by_customer),
+                )
+                .route("/{id}/payees/{payee_id}", web::get().to(routes::get_payee))
+                .route("/{id}/payees/{payee_id}", web::put().to(routes::edit_payee))
+                .route(
+                    "/{id}/payees/{payee_id}",
+                    web::delete().to(routes::delete_payee),
+                )
+                .route("/{id}/freeze", web::put().to(routes::freeze_customer))
+                .route("/{id}/unfreeze", web::put().to(routes::unfreeze_customer))
+                .route("/{id}/close", web::put().to(routes::close_customer))
+                .route("/{id}/deposits", web::put().to(routes::deposit))
+                .route("/{id}/withdrawals", web::put().to(routes::withdraw)),
+        );
+}
+
+/// The whole service, as served by `main`, working on `backend` and limited
+/// by `limiter`, which every worker of a server shares.
+pub fn new(
+    backend: &Backend,
+    limiter: &web::Data<RateLimiter>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    let storage = backend.storage.clone();
+    let streams = backend.streams.clone();
+
+    App::new()
+        .app_data(web::Data::from(backend.storage.clone()))
+        .app_data(web::Data::from(backend.streams.clone()))
+        .app_data(web::Data::new(backend.clone()))
+        .app_data(web::Data::new(routes::SigningKeys::default()))
+        .app_data(limiter.clone())
+        .wrap_fn(ratelimit::limit)
+        .wrap_fn(|req, srv| {
+            let method = req.method().to_string();
+            let started = Instant::now();
+            let fut = srv.call(req);
+
+            async move {
+                let res = fut.await?;
+                metrics::observe_request(
+                    &method,
+                    res.request().match_pattern().as_deref(),
+                    res.status().as_u16(),
+                    started.elapsed(),
+                );
+                Ok(res)
+            }
+        })
+        .wrap_fn(move |req, srv| {
+            let storage = storage.clone();
+            let context = audit::AuditContext::from_request(&req);
+            let request_id = context.request_id.clone().unwrap_or_default();
+            let method = req.method().clone();
+            let path = req.path().to_string();
+            let span = tracing::info_span!(
+                "request",
+                request_id = %request_id,
+                method = %method,
+                path = %logging::redact(&req.uri().to_string()),
+                actor = %context.actor,
+            );
+            let started = Instant::now();
+            let fut = span.in_scope(|| srv.call(req));
+
+            let future = async move {
+                let mut res = fut.await?;
+                if audit::is_state_changing(&method) {
+                    audit::record_request(&*storage, &method, &path, res.status().as_u16());
+                }
+                tracing::info!(
+                    status = res.status().as_u16(),
+                    latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+                    "request finished"
+                );
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static("x-request-id"), value);
+                }
+                Ok(res)
+            };
+            audit::CONTEXT.scope(context, future.instrument(span))
+        })
+        // outermost, so that every middleware and handler publishes to the
+        // backend's streams
+        .wrap_fn(move |req, srv| {
+            let fut = stream::STREAMS.sync_scope(streams.clone(), || srv.call(req));
+            stream::STREAMS.scope(streams.clone(), fut)
+        })
+        .route("/openapi.json", web::get().to(openapi::openapi_json))
+        .route("/docs", web::get().to(openapi::docs))
+        .route("/metrics", web::get().to(metrics::metrics))
+        .service(
+            web::scope("/v1")
+                .wrap_fn(|req, srv| versioning::track(&versioning::V1, req, srv))
+                .configure(v1),
+        )
+        .service(
+            web::scope("")
+                .wrap_fn(|req, srv| versioning::track(&versioning::UNVERSIONED, req, srv))
+                .configure(v1),
+        )
+}
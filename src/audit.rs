@@ -0,0 +1,90 @@
+use crate::database::storage::Storage;
+use crate::routes;
+use actix_web::dev::ServiceRequest;
+use actix_web::http::Method;
+use sha2::{Digest, Sha256};
+
+/// Who is behind the request currently being served. The middleware in
+/// `app.rs` sets it for every request so that storage can attach it to the
+/// audit events it writes.
+#[derive(Clone)]
+pub struct AuditContext {
+    pub actor: String,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+}
+
+tokio::task_local! {
+    pub static CONTEXT: AuditContext;
+}
+
+impl AuditContext {
+    pub fn from_request(req: &ServiceRequest) -> AuditContext {
+        let request_id = req
+            .headers()
+            .get("x-request-id")
+            .and_then(|x| x.to_str().ok())
+            .filter(|x| is_valid_request_id(x))
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+        AuditContext {
+            actor: routes::validate_token(req.request())
+                .map(|claims| claims.sub)
+                .unwrap_or_else(|| "anonymous".to_string()),
+            ip: req
+                .connection_info()
+                .realip_remote_addr()
+                .map(|x| x.to_string()),
+            request_id: Some(request_id),
+        }
+    }
+}
+
+/// Client supplied ids end up in logs and response headers, so only short
+/// ids made of safe characters are kept; anything else gets a fresh one.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Outside of a request (e.g. when the database is set up) changes are
+/// attributed to the system.
+pub fn current() -> AuditContext {
+    CONTEXT
+        .try_with(|context| context.clone())
+        .unwrap_or(AuditContext {
+            actor: "system".to_string(),
+            ip: None,
+            request_id: None,
+        })
+}
+
+pub fn is_state_changing(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Records the outcome of a state-changing request, including the ones that
+/// were rejected before reaching the database.
+pub fn record_request(storage: &dyn Storage, method: &Method, path: &str, status: u16) {
+    let _ = storage.create_audit_event(
+        &format!("{} {}", method, path),
+        "request",
+        None,
+        None,
+        Some(serde_json::json!({ "status": status })),
+    );
+}
+
+/// `prev_hash` of the first row in the chain.
+pub static GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// SHA-256 over the previous row's hash and this row's fields. The fields
+/// are encoded as a JSON array so that no two rows can produce the same input.
+pub fn chain_hash(prev_hash: &str, fields: &[Option<String>]) -> String {
+    let content = serde_json::json!([prev_hash, fields]).to_string();
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
@@ -0,0 +1,168 @@
+use crate::database::{crud, models};
+use crate::health;
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+static DEFAULT_DIRECTORY: &str = "backups";
+static DEFAULT_INTERVAL_MINUTES: u64 = 60;
+static DEFAULT_KEEP: usize = 24;
+static PREFIX: &str = "mydb-";
+static EXTENSION: &str = ".sqlite";
+
+/// `BANK_BACKUP_DIR`, where snapshots are written.
+pub fn directory() -> PathBuf {
+    PathBuf::from(std::env::var("BANK_BACKUP_DIR").unwrap_or(DEFAULT_DIRECTORY.to_string()))
+}
+
+/// `BANK_BACKUP_INTERVAL_MINUTES` between scheduled snapshots; 0 disables them.
+fn interval() -> Duration {
+    let minutes = std::env::var("BANK_BACKUP_INTERVAL_MINUTES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_MINUTES);
+    Duration::from_secs(minutes * 60)
+}
+
+/// `BANK_BACKUP_KEEP`, how many of the newest snapshots are kept.
+fn keep() -> usize {
+    std::env::var("BANK_BACKUP_KEEP")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(DEFAULT_KEEP)
+}
+
+fn describe(path: &Path, integrity: Option<String>) -> models::Backup {
+    models::Backup {
+        file: path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        path: path.to_string_lossy().to_string(),
+        bytes: fs::metadata(path).map(|x| x.len()).unwrap_or_default(),
+        integrity,
+    }
+}
+
+/// Snapshots in the backup directory, oldest first. Their names carry the
+/// UTC time they were taken, so sorting by name sorts by age.
+pub fn list() -> Result<Vec<models::Backup>, String> {
+    let directory = directory();
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+        .map_err(|e| e.to_string())?
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| {
+            let name = x.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with(PREFIX) && name.ends_with(EXTENSION)
+        })
+        .collect();
+    paths.sort();
+
+    Ok(paths.iter().map(|x| describe(x, None)).collect())
+}
+
+/// Writes a snapshot of the live database, checks it and prunes the oldest
+/// ones. A snapshot that fails the check is deleted rather than kept as a
+/// false sense of safety.
+pub fn create() -> Result<models::Backup, String> {
+    let directory = directory();
+    fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+    let path = directory.join(format!(
+        "{}{}{}",
+        PREFIX,
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+        EXTENSION
+    ));
+    crud::backup(&path).map_err(|e| e.to_string())?;
+
+    let integrity = crud::integrity_check(&path).map_err(|e| e.to_string())?;
+    if integrity != "ok" {
+        let _ = fs::remove_file(&path);
+        return Err(format!("backup failed the integrity check: {}", integrity));
+    }
+    prune()?;
+    Ok(describe(&path, Some(integrity)))
+}
+
+/// Deletes all but the newest `BANK_BACKUP_KEEP` snapshots.
+fn prune() -> Result<usize, String> {
+    let backups = list()?;
+    let excess = backups.len().saturating_sub(keep());
+
+    for backup in &backups[..excess] {
+        fs::remove_file(&backup.path).map_err(|e| e.to_string())?;
+    }
+    Ok(excess)
+}
+
+/// Restores `source` into `destination`, which must not exist yet, and checks
+/// the result. Putting it in place of the live database is left to the
+/// operator, with the server stopped.
+pub fn restore(source: &Path, destination: &Path) -> Result<models::Backup, String> {
+    if !source.is_file() {
+        return Err(format!("{} does not exist", source.display()));
+    }
+    if destination.exists() {
+        return Err(format!("{} already exists", destination.display()));
+    }
+
+    // a damaged or foreign file would otherwise fail halfway with an opaque error
+    match crud::integrity_check(source) {
+        Ok(x) if x == "ok" => {}
+        Ok(x) => return Err(format!("backup failed the integrity check: {}", x)),
+        Err(e) => {
+            return Err(format!(
+                "{} is not a usable database: {}",
+                source.display(),
+                e
+            ))
+        }
+    }
+
+    if let Err(e) = crud::restore(source, destination) {
+        let _ = fs::remove_file(destination);
+        return Err(e.to_string());
+    }
+    let integrity = crud::integrity_check(destination).map_err(|e| e.to_string())?;
+    Ok(describe(destination, Some(integrity)))
+}
+
+/// Restores a snapshot from the backup directory, by file name, into a new
+/// file next to it.
+pub fn restore_by_name(file: &str) -> Result<models::Backup, String> {
+    let backup = list()?
+        .into_iter()
+        .find(|x| x.file == file)
+        .ok_or_else(|| format!("no backup named {}", file))?;
+    let destination = directory().join(format!(
+        "restored-{}{}",
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+        EXTENSION
+    ));
+    restore(Path::new(&backup.path), &destination)
+}
+
+/// Takes a snapshot of `database` every `BANK_BACKUP_INTERVAL_MINUTES` on its
+/// own thread and reports to the readiness check.
+pub fn spawn(database: String) {
+    let interval = interval();
+    if interval.is_zero() {
+        return;
+    }
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let result = crud::DATABASE.sync_scope(database.clone(), create);
+        if let Err(e) = &result {
+            tracing::error!(error = %e, "scheduled backup failed");
+        }
+        health::report_job("backups", interval, result.map(|_| ()));
+    });
+}
@@ -0,0 +1,385 @@
+use crate::database::models::{Batch, BatchLine, BatchLineResult, BatchMode, Payment, Transfer};
+use crate::database::storage::{BatchItem, Storage, StorageError};
+use crate::metrics;
+use crate::routes::{balance_refusal, check_payment, check_transfer, Refusal};
+use actix_web::http::StatusCode;
+use chrono::Utc;
+use serde_json::{Map, Value};
+
+/// Larger uploads are turned away before any line is looked at.
+pub static MAX_LINES: usize = 1000;
+
+/// CSV columns that hold numbers in the JSON form of a line.
+static NUMERIC_COLUMNS: &[&str] = &["customerId", "idTo", "amount", "payeeId"];
+
+/// Reads a CSV upload whose first row names the columns, with the same names
+/// as the JSON form. Each row becomes the JSON object of a line; empty cells
+/// are left out, so that they read as missing.
+pub fn parse_csv(text: &str) -> Result<Vec<Value>, String> {
+    let mut records = csv_records(text)?.into_iter();
+    let header = records.next().ok_or("the upload is empty")?;
+    let header: Vec<String> = header.iter().map(|x| x.trim().to_string()).collect();
+
+    records
+        .enumerate()
+        .map(|(i, record)| {
+            if record.len() != header.len() {
+                return Err(format!(
+                    "row {} has {} cells, the header has {}",
+                    i + 1,
+                    record.len(),
+                    header.len()
+                ));
+            }
+            let mut line = Map::new();
+            for (column, cell) in header.iter().zip(record) {
+                let cell = cell.trim();
+                if cell.is_empty() {
+                    continue;
+                }
+                let numeric = NUMERIC_COLUMNS.contains(&column.as_str());
+                let value = match (cell.parse::<i64>(), cell.parse::<f64>()) {
+                    (Ok(x), _) if numeric => Value::from(x),
+                    (_, Ok(x)) if numeric => Value::from(x),
+                    _ => Value::from(cell),
+                };
+                line.insert(column.clone(), value);
+            }
+            Ok(Value::Object(line))
+        })
+        .collect()
+}
+
+/// Splits CSV text into rows of cells. Quoted cells may hold commas, line
+/// breaks and `""` for a quote; blank rows are skipped.
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.trim().is_empty() => {
+                cell.clear();
+                quoted = true;
+            }
+            _ if quoted => cell.push(c),
+            ',' => record.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut cell));
+                if record.iter().any(|x| !x.trim().is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            _ => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err("a quoted cell is not closed".to_string());
+    }
+    record.push(cell);
+    if record.iter().any(|x| !x.trim().is_empty()) {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// A line that passed every check, ready to be made.
+struct Ready {
+    kind: String,
+    item: BatchItem,
+}
+
+/// Reads one line and runs the checks of its route on it.
+fn prepare(storage: &dyn Storage, value: &Value) -> Result<Ready, Refusal> {
+    let line: BatchLine = serde_json::from_value(value.clone())
+        .map_err(|e| Refusal::Message(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+
+    let item = match line.kind.as_str() {
+        "transfer" => {
+            let transfer = Transfer {
+                id: None,
+                id_from: line.customer_id,
+                id_to: line.id_to.ok_or_else(|| {
+                    Refusal::Message(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "idTo is required for transfers".to_string(),
+                    )
+                })?,
+                amount: line.amount,
+                created_at: None,
+            };
+            check_transfer(storage, &transfer)?;
+            BatchItem::Transfer {
+                id_from: transfer.id_from,
+                id_to: transfer.id_to,
+                amount: transfer.amount,
+            }
+        }
+        "payment" => {
+            let mut payment = Payment {
+                id: None,
+                created_at: Some(Utc::now().to_rfc2822()),
+                customer_id: None,
+                amount: line.amount,
+                receiver_code: line.receiver_code,
+                reference: line.reference,
+                note: line.note,
+                payee_id: line.payee_id,
+            };
+            check_payment(storage, line.customer_id, &mut payment)?;
+            BatchItem::Payment(payment)
+        }
+        _ => {
+            return Err(Refusal::Message(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "kind must be payment or transfer".to_string(),
+            ))
+        }
+    };
+    Ok(Ready {
+        kind: line.kind,
+        item,
+    })
+}
+
+fn succeeded(number: usize, ready: &Ready) -> BatchLineResult {
+    let message = match &ready.item {
+        BatchItem::Transfer { .. } => "transfer successfull",
+        BatchItem::Payment(_) => "payment successfull",
+    };
+    BatchLineResult {
+        line: number as u32 + 1,
+        kind: Some(ready.kind.clone()),
+        status: "succeeded".to_string(),
+        code: Some(200),
+        message: message.to_string(),
+    }
+}
+
+/// Counts an item that was made in the metrics of its route.
+fn count(item: &BatchItem) {
+    match item {
+        BatchItem::Transfer { amount, .. } => {
+            metrics::TRANSFERS.inc();
+            metrics::TRANSFER_VOLUME.inc_by(*amount);
+        }
+        BatchItem::Payment(payment) => {
+            metrics::PAYMENTS.inc();
+            metrics::PAYMENT_VOLUME.inc_by(payment.amount);
+        }
+    }
+}
+
+fn failed(number: usize, kind: Option<String>, refusal: Refusal) -> BatchLineResult {
+    let (code, message) = match refusal {
+        Refusal::Invalid(errors) => (StatusCode::UNPROCESSABLE_ENTITY, errors.to_string()),
+        Refusal::Message(status, message) => (status, message),
+    };
+    BatchLineResult {
+        line: number as u32 + 1,
+        kind,
+        status: "failed".to_string(),
+        code: Some(code.as_u16()),
+        message,
+    }
+}
+
+fn skipped(number: usize, kind: Option<String>) -> BatchLineResult {
+    BatchLineResult {
+        line: number as u32 + 1,
+        kind,
+        status: "skipped".to_string(),
+        code: None,
+        message: "not made, the batch was rejected".to_string(),
+    }
+}
+
+fn kind_of(value: &Value) -> Option<String> {
+    value["kind"].as_str().map(|x| x.to_string())
+}
+
+fn not_made(item: &BatchItem) -> String {
+    match item {
+        BatchItem::Transfer { .. } => "could not process the transfer",
+        BatchItem::Payment(_) => "payment not created",
+    }
+    .to_string()
+}
+
+/// Why `run` could not keep the report of a batch.
+pub enum Unsaved {
+    /// The batch could not be started, so none of its lines were made.
+    NotStarted(String),
+    /// The lines were run, but their report could not be saved and is only in
+    /// the answer.
+    Lost(Box<Batch>, String),
+}
+
+/// Checks every line, then makes them and keeps the report: all in one
+/// transaction with the report when `Atomic`, each on its own when
+/// `BestEffort`, under a batch kept as "running" until its report is saved.
+pub fn run(
+    storage: &dyn Storage,
+    lines: &[Value],
+    mode: BatchMode,
+    format: &str,
+) -> Result<Batch, Unsaved> {
+    let report = Batch {
+        id: None,
+        created_at: Some(Utc::now().to_rfc2822()),
+        format: format.to_string(),
+        mode: mode.as_str().to_string(),
+        status: "running".to_string(),
+        succeeded: 0,
+        failed: 0,
+        lines: Vec::new(),
+    };
+    match mode {
+        BatchMode::BestEffort => run_best_effort(storage, lines, report),
+        BatchMode::Atomic => run_atomic(storage, lines, report),
+    }
+}
+
+/// Fills in the line results of `report` and the status they add up to.
+fn finish(report: &mut Batch, results: Vec<BatchLineResult>) {
+    report.succeeded = results.iter().filter(|x| x.status == "succeeded").count() as u32;
+    report.failed = results.iter().filter(|x| x.status == "failed").count() as u32;
+    report.status = match (report.succeeded, report.failed) {
+        (_, 0) => "completed",
+        (0, _) => "rejected",
+        _ => "partial",
+    }
+    .to_string();
+    report.lines = results;
+}
+
+/// Keeps the report of a batch that made nothing.
+fn save(storage: &dyn Storage, mut report: Batch) -> Result<Batch, Unsaved> {
+    match storage.create_batch_report(&report) {
+        Ok(id) => {
+            report.id = Some(id);
+            Ok(report)
+        }
+        Err(e) => Err(Unsaved::Lost(Box::new(report), e.to_string())),
+    }
+}
+
+fn run_best_effort(
+    storage: &dyn Storage,
+    lines: &[Value],
+    mut report: Batch,
+) -> Result<Batch, Unsaved> {
+    let id = storage
+        .start_batch_report(&report)
+        .map_err(|e| Unsaved::NotStarted(e.to_string()))?;
+    report.id = Some(id);
+
+    let results = lines
+        .iter()
+        .enumerate()
+        .map(|(i, value)| match prepare(storage, value) {
+            Err(refusal) => failed(i, kind_of(value), refusal),
+            Ok(ready) => {
+                let made = match &ready.item {
+                    BatchItem::Transfer {
+                        id_from,
+                        id_to,
+                        amount,
+                    } => storage.create_transfer(*id_from, *id_to, *amount),
+                    BatchItem::Payment(payment) => storage.create_payment(payment),
+                };
+                match made {
+                    Ok(_) => {
+                        count(&ready.item);
+                        succeeded(i, &ready)
+                    }
+                    Err(e) => failed(
+                        i,
+                        Some(ready.kind),
+                        balance_refusal(e, not_made(&ready.item)),
+                    ),
+                }
+            }
+        })
+        .collect();
+    finish(&mut report, results);
+
+    match storage.finish_batch_report(id, &report) {
+        Ok(_) => Ok(report),
+        Err(e) => Err(Unsaved::Lost(Box::new(report), e.to_string())),
+    }
+}
+
+fn run_atomic(storage: &dyn Storage, lines: &[Value], mut report: Batch) -> Result<Batch, Unsaved> {
+    let prepared: Vec<Result<Ready, Refusal>> = lines.iter().map(|x| prepare(storage, x)).collect();
+
+    if prepared.iter().any(|x| x.is_err()) {
+        let results = prepared
+            .into_iter()
+            .zip(lines)
+            .enumerate()
+            .map(|(i, (x, value))| match x {
+                Ok(ready) => skipped(i, Some(ready.kind)),
+                Err(refusal) => failed(i, kind_of(value), refusal),
+            })
+            .collect();
+        finish(&mut report, results);
+        return save(storage, report);
+    }
+
+    let ready: Vec<Ready> = prepared.into_iter().flatten().collect();
+    let items: Vec<BatchItem> = ready.iter().map(|x| x.item.clone()).collect();
+    let results = ready
+        .iter()
+        .enumerate()
+        .map(|(i, x)| succeeded(i, x))
+        .collect();
+    finish(&mut report, results);
+
+    // each line was checked against the balance before the batch, so lines
+    // that add up to more than it only fail here
+    let results = match storage.create_batch(&items, &report) {
+        Ok(id) => {
+            items.iter().for_each(count);
+            report.id = Some(id);
+            return Ok(report);
+        }
+        Err(StorageError::InBatch(failed_at, e)) => {
+            let mut error = Some(*e);
+            ready
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| match error.take_if(|_| i == failed_at) {
+                    Some(e) => {
+                        let refusal = balance_refusal(e, not_made(&x.item));
+                        failed(i, Some(x.kind), refusal)
+                    }
+                    None => skipped(i, Some(x.kind)),
+                })
+                .collect()
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "could not run the batch");
+            ready
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| {
+                    let refusal = Refusal::Message(StatusCode::BAD_REQUEST, not_made(&x.item));
+                    failed(i, Some(x.kind), refusal)
+                })
+                .collect()
+        }
+    };
+    finish(&mut report, results);
+    save(storage, report)
+}
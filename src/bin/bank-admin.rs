@@ -0,0 +1,335 @@
+use bank::database::{crud, models};
+use bank::{audit, backup};
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use std::path::Path;
+use validator::Validate;
+
+static USAGE: &str = "usage: bank-admin [--json] <command>
+
+commands:
+  customers                                       list customers
+  create-customer <name> [--balance <amount>]     open an account
+  deposit <customer id> <amount>                  credit an active account
+  freeze <customer id> <reason>                   freeze an account
+  unfreeze <customer id> <reason>                 reactivate a frozen account
+  reconcile [--correct]                           compare balances with transactions
+  statement <customer id> [--from <YYYY-MM-DD>] [--to <YYYY-MM-DD>]
+                                                  list the transactions of an account
+  rotate-jwt-secret                               sign new tokens with a fresh key
+  backup                                          snapshot the database into BANK_BACKUP_DIR
+  backups                                         list the snapshots
+  restore <backup file> <new database file>       restore a snapshot into a new file
+
+Works on mydb.sqlite in the current directory; the server does not need to run.";
+
+enum Format {
+    Table,
+    Json,
+}
+
+/// Either a value to print or a message explaining why the command failed.
+type Outcome = Result<Output, String>;
+
+enum Output {
+    Message(String),
+    Table {
+        json: serde_json::Value,
+        headers: Vec<&'static str>,
+        rows: Vec<Vec<String>>,
+    },
+}
+
+fn table<T: Serialize>(value: &T, headers: Vec<&'static str>, rows: Vec<Vec<String>>) -> Output {
+    Output::Table {
+        json: serde_json::json!(value),
+        headers,
+        rows,
+    }
+}
+
+fn print(output: Output, format: Format) {
+    match (output, format) {
+        (Output::Message(message), Format::Table) => println!("{}", message),
+        (Output::Message(message), Format::Json) => {
+            println!("{}", serde_json::json!({ "message": message }))
+        }
+        (Output::Table { json, .. }, Format::Json) => {
+            println!("{}", serde_json::to_string_pretty(&json).unwrap())
+        }
+        (Output::Table { headers, rows, .. }, Format::Table) => {
+            let mut widths: Vec<usize> = headers.iter().map(|x| x.len()).collect();
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+            let line = |cells: Vec<String>| {
+                cells
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                    .collect::<Vec<String>>()
+                    .join("  ")
+                    .trim_end()
+                    .to_string()
+            };
+            println!("{}", line(headers.iter().map(|x| x.to_string()).collect()));
+            println!("{}", line(widths.iter().map(|x| "-".repeat(*x)).collect()));
+            for row in rows {
+                println!("{}", line(row));
+            }
+        }
+    }
+}
+
+/// The value following `flag`, if the flag was given.
+fn option<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|x| x == flag) {
+        None => Ok(None),
+        Some(i) => match args.get(i + 1) {
+            Some(x) => Ok(Some(x)),
+            None => Err(format!("{} needs a value", flag)),
+        },
+    }
+}
+
+fn argument<'a>(args: &'a [String], index: usize, name: &str) -> Result<&'a str, String> {
+    args.get(index)
+        .map(|x| x.as_str())
+        .ok_or_else(|| format!("missing <{}>\n\n{}", name, USAGE))
+}
+
+fn parse<T: std::str::FromStr>(text: &str, name: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("invalid {}: {}", name, text))
+}
+
+fn customers() -> Outcome {
+    let customers = crud::get_all_customers().map_err(|e| e.to_string())?;
+    let rows = customers
+        .iter()
+        .map(|x| {
+            vec![
+                x.id.unwrap_or_default().to_string(),
+                x.name.clone(),
+                format!("{:.2}", x.balance.unwrap_or_default()),
+                x.status.clone().unwrap_or_default(),
+                x.kyc_level.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    Ok(table(
+        &customers,
+        vec!["ID", "NAME", "BALANCE", "STATUS", "KYC"],
+        rows,
+    ))
+}
+
+fn create_customer(args: &[String]) -> Outcome {
+    let name = argument(args, 1, "name")?;
+    let balance = match option(args, "--balance")? {
+        Some(x) => parse(x, "amount")?,
+        None => 0.0,
+    };
+
+    let customer: models::Customer = serde_json::from_value(serde_json::json!({
+        "name": name,
+        "balance": balance,
+        "created_at": Utc::now().to_rfc2822(),
+    }))
+    .map_err(|e| e.to_string())?;
+    customer.validate().map_err(|e| e.to_string())?;
+
+    crud::create_customer(&customer).map_err(|e| e.to_string())?;
+    Ok(Output::Message("customer created".to_string()))
+}
+
+fn find_customer(args: &[String]) -> Result<models::Customer, String> {
+    let id = parse(argument(args, 1, "customer id")?, "customer id")?;
+    crud::get_customer(id).map_err(|_| format!("could not find customer {}", id))
+}
+
+fn deposit(args: &[String]) -> Outcome {
+    let customer = find_customer(args)?;
+    let amount: f64 = parse(argument(args, 2, "amount")?, "amount")?;
+
+    if amount <= 0.0 {
+        return Err("amount must be positive".to_string());
+    }
+    if customer.status.as_deref() != Some(models::CustomerStatus::Active.as_str()) {
+        return Err("customer account is not active".to_string());
+    }
+
+    crud::create_movement(customer.id.unwrap(), "deposit", amount).map_err(|e| e.to_string())?;
+    Ok(Output::Message("deposit successfull".to_string()))
+}
+
+fn change_status(args: &[String], next: models::CustomerStatus) -> Outcome {
+    let customer = find_customer(args)?;
+    let status_change = models::StatusChange {
+        reason: args.get(2..).unwrap_or_default().join(" "),
+        payout_receiver_code: None,
+    };
+    status_change.validate().map_err(|e| e.to_string())?;
+
+    let current = customer
+        .status
+        .as_deref()
+        .and_then(models::CustomerStatus::parse);
+    if !matches!(current, Some(x) if x.can_become(next)) {
+        return Err(format!(
+            "customer account cannot go from {} to {}",
+            customer.status.as_deref().unwrap_or("unknown"),
+            next.as_str()
+        ));
+    }
+
+    crud::change_status(&customer, next, &status_change.reason, None).map_err(|e| e.to_string())?;
+    Ok(Output::Message(format!("customer {}", next.as_str())))
+}
+
+fn reconcile(args: &[String]) -> Outcome {
+    let correct = args.iter().any(|x| x == "--correct");
+    let report = crud::reconcile(correct).map_err(|e| e.to_string())?;
+    let rows = report
+        .discrepancies
+        .iter()
+        .map(|x| {
+            vec![
+                x.customer_id.to_string(),
+                format!("{:.2}", x.balance),
+                format!("{:.2}", x.expected),
+                format!("{:.2}", x.difference),
+            ]
+        })
+        .collect();
+    Ok(table(
+        &report,
+        vec!["CUSTOMER", "BALANCE", "EXPECTED", "DIFFERENCE"],
+        rows,
+    ))
+}
+
+fn statement(args: &[String]) -> Outcome {
+    let customer = find_customer(args)?;
+    let date = |flag| -> Result<Option<NaiveDate>, String> {
+        option(args, flag)?
+            .map(|x| NaiveDate::parse_from_str(x, "%Y-%m-%d"))
+            .transpose()
+            .map_err(|_| format!("{} must be YYYY-MM-DD", flag))
+    };
+
+    let statement = crud::get_statement(customer.id.unwrap(), date("--from")?, date("--to")?)
+        .map_err(|e| e.to_string())?;
+    let mut rows = vec![vec![
+        String::new(),
+        "opening".to_string(),
+        String::new(),
+        String::new(),
+        format!("{:.2}", statement.opening_balance),
+    ]];
+    rows.extend(statement.lines.iter().map(|x| {
+        vec![
+            x.created_at.clone(),
+            x.kind.clone(),
+            x.description.clone(),
+            format!("{:.2}", x.amount),
+            format!("{:.2}", x.balance),
+        ]
+    }));
+    Ok(table(
+        &statement,
+        vec!["DATE", "KIND", "DESCRIPTION", "AMOUNT", "BALANCE"],
+        rows,
+    ))
+}
+
+fn rotate_jwt_secret() -> Outcome {
+    let secret = crud::new_jwt_secret().map_err(|_| "could not generate a secret".to_string())?;
+
+    let id = crud::rotate_jwt_secret(&secret).map_err(|e| e.to_string())?;
+    Ok(Output::Message(format!(
+        "new tokens are signed with key {}; tokens signed with earlier keys are accepted until they expire",
+        id
+    )))
+}
+
+fn backups(backups: Vec<models::Backup>) -> Output {
+    let rows = backups
+        .iter()
+        .map(|x| {
+            vec![
+                x.path.clone(),
+                x.bytes.to_string(),
+                x.integrity.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    table(&backups, vec!["PATH", "BYTES", "INTEGRITY"], rows)
+}
+
+fn restore(args: &[String]) -> Outcome {
+    let source = argument(args, 1, "backup file")?;
+    let destination = argument(args, 2, "new database file")?;
+    let restored = backup::restore(Path::new(source), Path::new(destination))?;
+
+    if restored.integrity.as_deref() != Some("ok") {
+        return Err(format!(
+            "restored database failed the integrity check: {}",
+            restored.integrity.unwrap_or_default()
+        ));
+    }
+    Ok(backups(vec![restored]))
+}
+
+fn run(args: &[String]) -> Outcome {
+    match args.first().map(|x| x.as_str()) {
+        Some("customers") => customers(),
+        Some("create-customer") => create_customer(args),
+        Some("deposit") => deposit(args),
+        Some("freeze") => change_status(args, models::CustomerStatus::Frozen),
+        Some("unfreeze") => change_status(args, models::CustomerStatus::Active),
+        Some("reconcile") => reconcile(args),
+        Some("statement") => statement(args),
+        Some("rotate-jwt-secret") => rotate_jwt_secret(),
+        Some("backup") => backup::create().map(|x| backups(vec![x])),
+        Some("backups") => backup::list().map(backups),
+        Some("restore") => restore(args),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let format = match args.iter().position(|x| x == "--json") {
+        Some(i) => {
+            args.remove(i);
+            Format::Json
+        }
+        None => Format::Table,
+    };
+
+    if let Err(e) = crud::check_db() {
+        eprintln!("could not open the database: {}", e);
+        std::process::exit(1);
+    }
+
+    // changes made from here are audited under the operator's name
+    let context = audit::AuditContext {
+        actor: format!(
+            "bank-admin:{}",
+            std::env::var("USER").unwrap_or_else(|_| "unknown".to_string())
+        ),
+        ip: None,
+        request_id: Some(uuid::Uuid::new_v4().to_string()),
+    };
+
+    match audit::CONTEXT.sync_scope(context, || run(&args)) {
+        Ok(output) => print(output, format),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,2 +1,4 @@
 pub mod crud;
 pub mod models;
+pub mod postgres;
+pub mod storage;
@@ -1,29 +1,108 @@
-use super::models::{Customer, Payment, TransferHuman};
+use super::models::{
+    AuditEvent, ChainReport, Customer, Discrepancy, JwtSecret, KycDocument, Payee, Payment,
+    Reconciliation, Reversal, Statement, StatementLine, Transfer, TransferHuman, Webhook,
+    WebhookDelivery, WebhookDispatch,
+};
+use crate::audit;
 use crate::database::models;
+use crate::database::storage::{BatchItem, StorageError};
+use crate::metrics;
+use crate::stream;
+use crate::webhooks;
 use chrono::Utc;
-use rusqlite::{params, Connection, Result};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Result, TransactionBehavior};
 use std::path::Path;
+use std::time::Duration;
 
-static DATABASE_FILE: &str = "mydb.sqlite";
-enum Table {
+pub static DATABASE_FILE: &str = "mydb.sqlite";
+/// How long a connection waits for another one to finish writing.
+static BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub(crate) enum Table {
     CUSTOMER,
     TRANSFER,
     PAYMENT,
+    REVERSAL,
+    PAYEE,
+    STATUS_CHANGE,
+    KYC_DOCUMENT,
+    AUDIT_EVENT,
+    MOVEMENT,
+    WEBHOOK,
+    WEBHOOK_EVENT,
+    WEBHOOK_DELIVERY,
+    JWT_SECRET,
+    BATCH,
+    BATCH_LINE,
 }
 impl Table {
-    fn as_str(&self) -> &str {
+    pub(crate) fn as_str(&self) -> &str {
         match self {
             Table::CUSTOMER => "customers",
             Table::TRANSFER => "transfers",
             Table::PAYMENT => "payments",
+            Table::REVERSAL => "reversals",
+            Table::PAYEE => "payees",
+            Table::STATUS_CHANGE => "status_changes",
+            Table::KYC_DOCUMENT => "kyc_documents",
+            Table::AUDIT_EVENT => "audit_events",
+            Table::MOVEMENT => "movements",
+            Table::WEBHOOK => "webhooks",
+            Table::WEBHOOK_EVENT => "webhook_events",
+            Table::WEBHOOK_DELIVERY => "webhook_deliveries",
+            Table::JWT_SECRET => "jwt_secrets",
+            Table::BATCH => "batches",
+            Table::BATCH_LINE => "batch_lines",
+        }
+    }
+
+    /// The columns a row's hash covers, for the tables whose rows are
+    /// hash-chained like audit events.
+    pub(crate) fn journal_columns(&self) -> Option<&'static str> {
+        match self {
+            Table::TRANSFER => Some("id, created_at, from_id, to_id, amount"),
+            Table::PAYMENT => Some(
+                "id, created_at, customer_id, amount, receiver_code, reference, note, payee_id",
+            ),
+            Table::MOVEMENT => Some("id, created_at, customer_id, kind, amount"),
+            Table::REVERSAL => Some("id, created_at, kind, original_id, amount"),
+            _ => None,
         }
     }
 }
 
+/// Tables that record money moving, each chained on its own.
+pub(crate) const JOURNALS: [Table; 4] = [
+    Table::TRANSFER,
+    Table::PAYMENT,
+    Table::MOVEMENT,
+    Table::REVERSAL,
+];
+
+tokio::task_local! {
+    /// Overrides `DATABASE_FILE` for the calls made inside a scope, so that a
+    /// `SqliteStorage` can point at a file of its own.
+    pub static DATABASE: String;
+}
+
+/// The database that calls in the current scope work on.
+pub fn database() -> String {
+    DATABASE
+        .try_with(|x| x.clone())
+        .unwrap_or_else(|_| DATABASE_FILE.to_string())
+}
+
 fn get_connection() -> Result<Connection> {
-    Connection::open(DATABASE_FILE)
+    let mut conn = Connection::open(database())?;
+    // concurrent requests wait for each other's writes instead of failing
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.profile(Some(metrics::observe_query));
+    Ok(conn)
 }
 
+#[tracing::instrument(level = "debug", err(level = "warn"))]
 pub fn create_db() -> Result<()> {
     let query = format!("BEGIN;
     CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, name TEXT NOT NULL, balance INTEGER NOT NULL DEFAULT 0, created_at TEXT NOT NULL);
@@ -35,47 +114,270 @@ pub fn create_db() -> Result<()> {
     Ok(())
 }
 
-pub fn check_db() -> Result<()> {
-    if !Path::new(DATABASE_FILE).exists() {
-        create_db()?;
+/// Schema changes that cannot be expressed with `CREATE TABLE IF NOT EXISTS`.
+/// They run in order on top of `create_db`; how many have been applied is kept
+/// in `PRAGMA user_version`.
+fn migrations() -> Vec<String> {
+    vec![
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, kind TEXT NOT NULL, original_id INTEGER NOT NULL, amount REAL NOT NULL);
+            CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, name TEXT NOT NULL, receiver_code TEXT NOT NULL, nickname TEXT NULL);",
+            Table::REVERSAL.as_str(),
+            Table::PAYEE.as_str()
+        ),
+        format!(
+            "ALTER TABLE {} ADD COLUMN status TEXT NOT NULL DEFAULT 'active';
+            CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, from_status TEXT NOT NULL, to_status TEXT NOT NULL, reason TEXT NOT NULL);",
+            Table::CUSTOMER.as_str(),
+            Table::STATUS_CHANGE.as_str()
+        ),
+        format!(
+            "ALTER TABLE {0} ADD COLUMN date_of_birth TEXT NULL;
+            ALTER TABLE {0} ADD COLUMN national_id TEXT NULL;
+            ALTER TABLE {0} ADD COLUMN address TEXT NULL;
+            ALTER TABLE {0} ADD COLUMN email TEXT NULL;
+            ALTER TABLE {0} ADD COLUMN phone TEXT NULL;
+            ALTER TABLE {0} ADD COLUMN kyc_level TEXT NOT NULL DEFAULT 'unverified';
+            CREATE TABLE IF NOT EXISTS {1} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, kind TEXT NOT NULL, number TEXT NOT NULL, issuing_country TEXT NOT NULL, expires_at TEXT NULL);",
+            Table::CUSTOMER.as_str(),
+            Table::KYC_DOCUMENT.as_str()
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {0} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, actor TEXT NOT NULL, action TEXT NOT NULL, entity TEXT NOT NULL, entity_id INTEGER NULL, before TEXT NULL, after TEXT NULL, ip TEXT NULL, request_id TEXT NULL);
+            CREATE TRIGGER IF NOT EXISTS {0}_no_update BEFORE UPDATE ON {0} BEGIN SELECT RAISE(ABORT, '{0} is append-only'); END;
+            CREATE TRIGGER IF NOT EXISTS {0}_no_delete BEFORE DELETE ON {0} BEGIN SELECT RAISE(ABORT, '{0} is append-only'); END;",
+            Table::AUDIT_EVENT.as_str()
+        ),
+        format!(
+            "ALTER TABLE {0} ADD COLUMN prev_hash TEXT NULL;
+            ALTER TABLE {0} ADD COLUMN hash TEXT NULL;",
+            Table::AUDIT_EVENT.as_str()
+        ),
+        // deposits, withdrawals and opening balances were not recorded before
+        // this point, so whatever part of a balance the recorded transactions
+        // do not explain is carried over; reconciliation keeps reporting it
+        format!(
+            "CREATE TABLE IF NOT EXISTS {0} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, kind TEXT NOT NULL, amount REAL NOT NULL);
+            INSERT INTO {0} (created_at, customer_id, kind, amount)
+            SELECT c.created_at, c.id, 'carried_over', c.balance - ({1}) FROM {2} AS c
+            WHERE c.balance - ({1}) != 0;",
+            Table::MOVEMENT.as_str(),
+            expected_balance_sql(),
+            Table::CUSTOMER.as_str()
+        ),
+        // webhook_events is the outbox: rows are written in the transaction
+        // that produces the event and fanned out to deliveries by the dispatcher
+        format!(
+            "CREATE TABLE IF NOT EXISTS {0} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, url TEXT NOT NULL, secret TEXT NOT NULL, event_types TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS {1} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, event_type TEXT NOT NULL, payload TEXT NOT NULL, fanned_out INTEGER NOT NULL DEFAULT 0);
+            CREATE TABLE IF NOT EXISTS {2} (id INTEGER PRIMARY KEY, event_id INTEGER NOT NULL, webhook_id INTEGER NOT NULL, status TEXT NOT NULL, attempts INTEGER NOT NULL DEFAULT 0, next_attempt_at INTEGER NULL, last_status_code INTEGER NULL, last_error TEXT NULL, updated_at TEXT NOT NULL);
+            CREATE INDEX IF NOT EXISTS {1}_fanned_out ON {1} (fanned_out);
+            CREATE INDEX IF NOT EXISTS {2}_due ON {2} (status, next_attempt_at);",
+            Table::WEBHOOK.as_str(),
+            Table::WEBHOOK_EVENT.as_str(),
+            Table::WEBHOOK_DELIVERY.as_str()
+        ),
+        // the first signing key is generated by `seed_jwt_secret`
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, secret TEXT NOT NULL, retired_at INTEGER NULL);",
+            Table::JWT_SECRET.as_str()
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {0} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, format TEXT NOT NULL, mode TEXT NOT NULL, status TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS {1} (id INTEGER PRIMARY KEY, batch_id INTEGER NOT NULL, line INTEGER NOT NULL, kind TEXT NULL, status TEXT NOT NULL, code INTEGER NULL, message TEXT NOT NULL);
+            CREATE INDEX IF NOT EXISTS {1}_batch_id ON {1} (batch_id);",
+            Table::BATCH.as_str(),
+            Table::BATCH_LINE.as_str()
+        ),
+        format!(
+            "ALTER TABLE {} ADD COLUMN account_number TEXT NULL;
+            ALTER TABLE {} ADD COLUMN payee_id INTEGER NULL;",
+            Table::PAYEE.as_str(),
+            Table::PAYMENT.as_str()
+        ),
+        // rows written before this point stay unchained
+        JOURNALS
+            .iter()
+            .map(|x| {
+                format!(
+                    "ALTER TABLE {0} ADD COLUMN prev_hash TEXT NULL;
+                    ALTER TABLE {0} ADD COLUMN hash TEXT NULL;",
+                    x.as_str()
+                )
+            })
+            .collect(),
+    ]
+}
+
+fn migrate() -> Result<()> {
+    let mut conn = get_connection()?;
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, migration) in migrations().iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+        tx.commit()?;
+    }
+    seed_jwt_secret(&conn)
+}
+
+/// A random signing key: 32 bytes, hex encoded.
+pub fn new_jwt_secret() -> std::result::Result<String, getrandom::Error> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes.iter().map(|x| format!("{:02x}", x)).collect())
+}
+
+/// Gives a database without signing keys a random one to start with.
+fn seed_jwt_secret(conn: &Connection) -> Result<()> {
+    let query = format!("SELECT COUNT(*) FROM {}", Table::JWT_SECRET.as_str());
+    if conn.query_row(&query, [], |row| row.get::<_, u32>(0))? > 0 {
+        return Ok(());
     }
+
+    let secret =
+        new_jwt_secret().map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let query = format!(
+        "INSERT INTO {} (created_at, secret) VALUES (?1, ?2)",
+        Table::JWT_SECRET.as_str()
+    );
+    conn.execute(&query, params![Utc::now().to_rfc2822(), secret])?;
     Ok(())
 }
 
-pub fn create_customer(customer: &models::Customer) -> Result<()> {
-    let mut starting_balance: f64 = 0.0;
+/// Copies the live database to `destination` with SQLite's online backup
+/// API. Pages are copied in small steps so that writers are only held up
+/// briefly, and a step restarts if a write lands in the middle of it.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn backup(destination: &Path) -> Result<()> {
+    let conn = get_connection()?;
+    let mut target = Connection::open(destination)?;
+    let backup = rusqlite::backup::Backup::new(&conn, &mut target)?;
+    backup.run_to_completion(100, Duration::from_millis(10), None)?;
+    Ok(())
+}
+
+/// Copies the database file at `source` into a new database at
+/// `destination`; the live database is not touched.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn restore(source: &Path, destination: &Path) -> Result<()> {
+    let conn = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut target = Connection::open(destination)?;
+    let backup = rusqlite::backup::Backup::new(&conn, &mut target)?;
+    backup.run_to_completion(100, Duration::from_millis(10), None)?;
+    Ok(())
+}
+
+/// `PRAGMA integrity_check` on the database at `path`: "ok", or the problems
+/// found.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn integrity_check(path: &Path) -> Result<String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    Ok(rows.collect::<Result<Vec<String>>>()?.join("; "))
+}
+
+/// The migration the database file is at, next to the number of migrations
+/// this build knows about.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn schema_version() -> Result<(usize, usize)> {
+    let conn = get_connection()?;
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok((version, migrations().len()))
+}
 
-    if customer.balance.is_some() {
-        starting_balance = customer.balance.unwrap();
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn check_db() -> Result<()> {
+    if !Path::new(&database()).exists() {
+        create_db()?;
     }
+    migrate()
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(customer))]
+pub fn create_customer(customer: &models::Customer) -> Result<()> {
+    let starting_balance = customer.balance.unwrap_or(0.0);
 
     let query = format!(
-        "INSERT INTO {} (name, balance, created_at) VALUES (?1, ?2, ?3)",
+        "INSERT INTO {} (name, balance, created_at, date_of_birth, national_id, address, email, phone) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
         Table::CUSTOMER.as_str()
     );
-    get_connection().unwrap().execute(
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
         &query,
-        params![customer.name, starting_balance, customer.created_at],
+        params![
+            customer.name,
+            starting_balance,
+            customer.created_at,
+            customer.date_of_birth,
+            customer.national_id,
+            customer.address,
+            customer.email,
+            customer.phone
+        ],
     )?;
-    Ok(())
+
+    let id = tx.last_insert_rowid() as u16;
+
+    if starting_balance != 0.0 {
+        let query = format!(
+            "INSERT INTO {} (created_at, customer_id, kind, amount) VALUES (?1, ?2, ?3, ?4)",
+            Table::MOVEMENT.as_str()
+        );
+        tx.execute(
+            &query,
+            params![customer.created_at, id, "opening", starting_balance],
+        )?;
+        chain_journal_row(&tx, Table::MOVEMENT, tx.last_insert_rowid())?;
+    }
+
+    let after = read_customer(&tx, id).ok().map(|x| serde_json::json!(x));
+    insert_audit_event(
+        &tx,
+        "create",
+        Table::CUSTOMER.as_str(),
+        Some(id),
+        None,
+        after,
+    )?;
+    tx.commit()
+}
+
+fn customer_from_row(row: &rusqlite::Row) -> Result<Customer> {
+    Ok(Customer {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        balance: row.get(2)?,
+        created_at: row.get(3)?,
+        status: row.get(4)?,
+        date_of_birth: row.get(5)?,
+        national_id: row.get(6)?,
+        address: row.get(7)?,
+        email: row.get(8)?,
+        phone: row.get(9)?,
+        kyc_level: row.get(10)?,
+    })
 }
 
+#[tracing::instrument(level = "debug", err(level = "warn"))]
 pub fn get_customer(id: u16) -> Result<Customer> {
-    let conn = get_connection().unwrap();
+    read_customer(&get_connection().unwrap(), id)
+}
+
+/// Reads the customer on `conn`, so that a write can snapshot it for the
+/// audit log inside its own transaction.
+fn read_customer(conn: &Connection, id: u16) -> Result<Customer> {
     let query = format!("SELECT * FROM {} WHERE id = ?", Table::CUSTOMER.as_str());
     let mut stmt = conn.prepare(&query)?;
 
-    let user = stmt.query_row(params![id], |row| {
-        Ok(Customer {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            balance: row.get(2)?,
-            created_at: row.get(3)?,
-        })
-    })?;
+    let user = stmt.query_row(params![id], customer_from_row)?;
     Ok(user)
 }
 
+#[tracing::instrument(level = "debug", err(level = "warn"))]
 pub fn get_transfers_by_customer(id: u16) -> Result<Vec<TransferHuman>> {
     let conn = get_connection().unwrap();
     let mut transfer_list: Vec<TransferHuman> = Vec::new();
@@ -110,6 +412,7 @@ pub fn get_transfers_by_customer(id: u16) -> Result<Vec<TransferHuman>> {
     Ok(transfer_list)
 }
 
+#[tracing::instrument(level = "debug", err(level = "warn"))]
 pub fn get_payments_by_customer(id: u16) -> Result<Vec<Payment>> {
     let conn = get_connection().unwrap();
     let mut record_list: Vec<Payment> = Vec::new();
@@ -131,6 +434,7 @@ pub fn get_payments_by_customer(id: u16) -> Result<Vec<Payment>> {
             receiver_code: row.get(4)?,
             reference: row.get(5)?,
             note: row.get(6)?,
+            payee_id: row.get(7)?,
         })
     })?
     .for_each(|i| record_list.push(i.unwrap()));
@@ -138,27 +442,69 @@ pub fn get_payments_by_customer(id: u16) -> Result<Vec<Payment>> {
     Ok(record_list)
 }
 
-pub fn update_balance(id: u16, balance: f64) -> Result<()> {
-    let conn = get_connection().unwrap();
+/// Deposits (positive `amount`) and withdrawals (negative `amount`) are kept
+/// as movements so that balances can be reconciled later.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn create_movement(id: u16, kind: &str, amount: f64) -> Result<()> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
 
     let query = format!(
-        "UPDATE {} SET balance = ?1 WHERE id = ?2",
-        Table::CUSTOMER.as_str()
+        "INSERT INTO {} (created_at, customer_id, kind, amount) VALUES (?1, ?2, ?3, ?4)",
+        Table::MOVEMENT.as_str()
     );
+    tx.execute(&query, params![Utc::now().to_rfc2822(), id, kind, amount])?;
+    let movement_id = tx.last_insert_rowid();
+    chain_journal_row(&tx, Table::MOVEMENT, movement_id)?;
+
+    let balance = add_to_balance(&tx, id, amount)?;
+    let previous = balance - amount;
+
+    insert_audit_event(
+        &tx,
+        "update",
+        Table::CUSTOMER.as_str(),
+        Some(id),
+        Some(serde_json::json!({ "balance": previous })),
+        Some(serde_json::json!({ "balance": balance, kind: amount, "movementId": movement_id })),
+    )?;
+    enqueue_balance_low(&tx, id, Some(previous), balance)?;
 
-    conn.execute(&query, params![balance, id]).unwrap();
+    tx.commit()?;
+    stream::publish(
+        "balance.changed",
+        &[id],
+        serde_json::json!({ "customerId": id, "balance": balance, kind: amount }),
+    );
     Ok(())
 }
 
-pub fn create_payment(payment: &models::Payment, balance: f64) -> Result<()> {
-    let conn = get_connection().unwrap();
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(payment))]
+pub fn create_payment(payment: &models::Payment) -> Result<()> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let events = insert_payment(&tx, payment)?;
+    tx.commit()?;
+    stream::publish_all(events);
+    Ok(())
+}
+
+/// Records the payment and debits the customer within `tx`, and returns the
+/// live updates to publish once it commits.
+fn insert_payment(
+    tx: &rusqlite::Transaction,
+    payment: &models::Payment,
+) -> Result<Vec<stream::Pending>> {
+    let id = payment
+        .customer_id
+        .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
 
     let query = format!(
-        "INSERT INTO {} (created_at, customer_id, amount, receiver_code, reference, note) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "INSERT INTO {} (created_at, customer_id, amount, receiver_code, reference, note, payee_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         Table::PAYMENT.as_str()
     );
 
-    conn.execute(
+    tx.execute(
         &query,
         params![
             payment.created_at,
@@ -166,59 +512,287 @@ pub fn create_payment(payment: &models::Payment, balance: f64) -> Result<()> {
             payment.amount,
             payment.receiver_code,
             payment.reference,
-            payment.note
+            payment.note,
+            payment.payee_id
         ],
     )?;
+    let payment_id = tx.last_insert_rowid();
+    chain_journal_row(tx, Table::PAYMENT, payment_id)?;
 
-    let query_2 = format!(
-        "UPDATE {} SET balance = ?1 WHERE id = ?2",
-        Table::CUSTOMER.as_str()
-    );
+    let balance = add_to_balance(tx, id, -payment.amount)?;
 
-    conn.execute(&query_2, params![balance, payment.customer_id])?;
+    insert_audit_event(
+        tx,
+        "create",
+        Table::PAYMENT.as_str(),
+        Some(payment_id as u16),
+        None,
+        Some(serde_json::json!(payment)),
+    )?;
+    let event = serde_json::json!({
+        "paymentId": payment_id,
+        "customerId": payment.customer_id,
+        "amount": payment.amount,
+        "receiverCode": payment.receiver_code,
+        "reference": payment.reference,
+    });
+    enqueue_webhook_event(tx, models::WebhookEventType::PaymentCreated, event.clone())?;
+    enqueue_balance_low(tx, id, Some(balance + payment.amount), balance)?;
 
-    Ok(())
+    Ok(vec![
+        ("payment.created", vec![id], event),
+        (
+            "balance.changed",
+            vec![id],
+            serde_json::json!({ "customerId": id, "balance": balance }),
+        ),
+    ])
 }
 
+/// Moves `amount` from one customer to the other and records the transfer in
+/// a single transaction, so that no step is left behind when another fails.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
 pub fn create_transfer(id_from: u16, id_to: u16, amount: f64) -> Result<()> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let events = insert_transfer(&tx, id_from, id_to, amount)?;
+    tx.commit()?;
+    stream::publish_all(events);
+    Ok(())
+}
+
+/// The steps of `create_transfer` within `tx`; returns the live updates to
+/// publish once it commits.
+fn insert_transfer(
+    tx: &rusqlite::Transaction,
+    id_from: u16,
+    id_to: u16,
+    amount: f64,
+) -> Result<Vec<stream::Pending>> {
     let created_at = Utc::now().to_rfc2822();
+    let balances = [
+        (id_from, add_to_balance(tx, id_from, -amount)?, -amount),
+        (id_to, add_to_balance(tx, id_to, amount)?, amount),
+    ];
+    for (id, balance, change) in balances {
+        insert_audit_event(
+            tx,
+            "update",
+            Table::CUSTOMER.as_str(),
+            Some(id),
+            Some(serde_json::json!({ "balance": balance - change })),
+            Some(serde_json::json!({ "balance": balance })),
+        )?;
+        enqueue_balance_low(tx, id, Some(balance - change), balance)?;
+    }
 
     let query = format!(
         "INSERT INTO {} (created_at, from_id, to_id, amount) VALUES (?1, ?2, ?3, ?4)",
         Table::TRANSFER.as_str()
     );
+    tx.execute(&query, params![created_at, id_from, id_to, amount])?;
+    let transfer_id = tx.last_insert_rowid();
+    chain_journal_row(tx, Table::TRANSFER, transfer_id)?;
 
-    get_connection()
-        .unwrap()
-        .execute(&query, params![created_at, id_from, id_to, amount])?;
-    Ok(())
+    insert_audit_event(
+        tx,
+        "create",
+        Table::TRANSFER.as_str(),
+        Some(transfer_id as u16),
+        None,
+        Some(serde_json::json!({ "idFrom": id_from, "idTo": id_to, "amount": amount })),
+    )?;
+    let event = serde_json::json!({
+        "transferId": transfer_id,
+        "idFrom": id_from,
+        "idTo": id_to,
+        "amount": amount,
+    });
+    enqueue_webhook_event(tx, models::WebhookEventType::TransferCreated, event.clone())?;
+
+    let mut events: Vec<stream::Pending> = balances
+        .iter()
+        .map(|&(id, balance, _)| {
+            (
+                "balance.changed",
+                vec![id],
+                serde_json::json!({ "customerId": id, "balance": balance }),
+            )
+        })
+        .collect();
+    events.push(("transfer.created", vec![id_from, id_to], event));
+    Ok(events)
 }
 
-pub fn edit_customer(id: u16, customer: models::CustomerEdit) -> Result<()> {
-    let conn = get_connection().unwrap();
+/// Makes every transfer and payment and keeps `report` in one transaction, so
+/// that the batch either happens in full, with its report, or not at all. The
+/// error names the item that failed.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(items, report))]
+pub fn create_batch(
+    items: &[BatchItem],
+    report: &models::Batch,
+) -> std::result::Result<u32, StorageError> {
+    let mut conn = get_connection()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let mut events = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let result = match item {
+            BatchItem::Transfer {
+                id_from,
+                id_to,
+                amount,
+            } => insert_transfer(&tx, *id_from, *id_to, *amount),
+            BatchItem::Payment(payment) => insert_payment(&tx, payment),
+        };
+        events.extend(result.map_err(|e| StorageError::InBatch(i, Box::new(e.into())))?);
+    }
+    let id = insert_batch_report(&tx, report)?;
+
+    tx.commit()?;
+    stream::publish_all(events);
+    Ok(id)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(customer))]
+pub fn edit_customer(id: u16, customer: &models::CustomerEdit) -> Result<()> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let before = read_customer(&tx, id).ok().map(|x| serde_json::json!(x));
 
     let query = format!(
         "UPDATE {} SET name = ?1 WHERE id = ?2",
         Table::CUSTOMER.as_str()
     );
 
-    conn.execute(&query, params![customer.name, id]).unwrap();
-    Ok(())
+    tx.execute(&query, params![customer.name, id])?;
+
+    let after = read_customer(&tx, id).ok().map(|x| serde_json::json!(x));
+    insert_audit_event(
+        &tx,
+        "update",
+        Table::CUSTOMER.as_str(),
+        Some(id),
+        before,
+        after,
+    )?;
+    tx.commit()
 }
 
-pub fn get_all_customers() -> Result<Vec<Customer>> {
-    let mut record_list: Vec<Customer> = Vec::new();
+/// Only the fields present in `customer` are changed.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(customer))]
+pub fn patch_customer(id: u16, customer: &models::CustomerPatch) -> Result<()> {
+    let query = format!(
+        "UPDATE {} SET name = COALESCE(?1, name), date_of_birth = COALESCE(?2, date_of_birth),
+        national_id = COALESCE(?3, national_id), address = COALESCE(?4, address),
+        email = COALESCE(?5, email), phone = COALESCE(?6, phone) WHERE id = ?7",
+        Table::CUSTOMER.as_str()
+    );
 
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let before = read_customer(&tx, id).ok().map(|x| serde_json::json!(x));
+    tx.execute(
+        &query,
+        params![
+            customer.name,
+            customer.date_of_birth,
+            customer.national_id,
+            customer.address,
+            customer.email,
+            customer.phone,
+            id
+        ],
+    )?;
+
+    let after = read_customer(&tx, id).ok().map(|x| serde_json::json!(x));
+    insert_audit_event(
+        &tx,
+        "update",
+        Table::CUSTOMER.as_str(),
+        Some(id),
+        before,
+        after,
+    )?;
+    tx.commit()
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn update_kyc_level(id: u16, level: models::KycLevel) -> Result<()> {
+    let query = format!(
+        "UPDATE {} SET kyc_level = ?1 WHERE id = ?2",
+        Table::CUSTOMER.as_str()
+    );
+
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let before = read_customer(&tx, id)
+        .ok()
+        .map(|x| serde_json::json!({ "kycLevel": x.kyc_level }));
+    tx.execute(&query, params![level.as_str(), id])?;
+
+    insert_audit_event(
+        &tx,
+        "update",
+        Table::CUSTOMER.as_str(),
+        Some(id),
+        before,
+        Some(serde_json::json!({ "kycLevel": level.as_str() })),
+    )?;
+    tx.commit()
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(document))]
+pub fn create_kyc_document(document: &models::KycDocument) -> Result<()> {
+    let query = format!(
+        "INSERT INTO {} (created_at, customer_id, kind, number, issuing_country, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        Table::KYC_DOCUMENT.as_str()
+    );
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
+        &query,
+        params![
+            document.created_at,
+            document.customer_id,
+            document.kind,
+            document.number,
+            document.issuing_country,
+            document.expires_at
+        ],
+    )?;
+
+    insert_audit_event(
+        &tx,
+        "create",
+        Table::KYC_DOCUMENT.as_str(),
+        Some(tx.last_insert_rowid() as u16),
+        None,
+        Some(serde_json::json!(document)),
+    )?;
+    tx.commit()
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_kyc_documents_by_customer(customer_id: u16) -> Result<Vec<KycDocument>> {
     let conn = get_connection().unwrap();
-    let query = format!("SELECT * FROM {}", Table::CUSTOMER.as_str());
+    let mut record_list: Vec<KycDocument> = Vec::new();
+
+    let query = format!(
+        "SELECT * FROM {} WHERE customer_id = ?1",
+        Table::KYC_DOCUMENT.as_str()
+    );
     let mut stmt = conn.prepare(&query)?;
 
-    stmt.query_map(params![], |row| {
-        Ok(Customer {
+    stmt.query_map(params![customer_id], |row| {
+        Ok(KycDocument {
             id: row.get(0)?,
-            name: row.get(1)?,
-            balance: row.get(2)?,
-            created_at: row.get(3)?,
+            created_at: row.get(1)?,
+            customer_id: row.get(2)?,
+            kind: row.get(3)?,
+            number: row.get(4)?,
+            issuing_country: row.get(5)?,
+            expires_at: row.get(6)?,
         })
     })?
     .for_each(|i| record_list.push(i.unwrap()));
@@ -226,6 +800,21 @@ pub fn get_all_customers() -> Result<Vec<Customer>> {
     Ok(record_list)
 }
 
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_all_customers() -> Result<Vec<Customer>> {
+    let mut record_list: Vec<Customer> = Vec::new();
+
+    let conn = get_connection().unwrap();
+    let query = format!("SELECT * FROM {}", Table::CUSTOMER.as_str());
+    let mut stmt = conn.prepare(&query)?;
+
+    stmt.query_map(params![], customer_from_row)?
+        .for_each(|i| record_list.push(i.unwrap()));
+
+    Ok(record_list)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
 pub fn get_all_transfers() -> Result<Vec<TransferHuman>> {
     let mut record_list: Vec<TransferHuman> = Vec::new();
 
@@ -261,3 +850,1457 @@ pub fn get_all_transfers() -> Result<Vec<TransferHuman>> {
 
     Ok(record_list)
 }
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_transfer(id: u16) -> Result<Transfer> {
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT id, from_id, to_id, amount, created_at FROM {} WHERE id = ?",
+        Table::TRANSFER.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    let transfer = stmt.query_row(params![id], |row| {
+        Ok(Transfer {
+            id: row.get(0)?,
+            id_from: row.get(1)?,
+            id_to: row.get(2)?,
+            amount: row.get(3)?,
+            created_at: row.get(4)?,
+        })
+    })?;
+    Ok(transfer)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_payment(id: u16) -> Result<Payment> {
+    let conn = get_connection().unwrap();
+    let query = format!("SELECT * FROM {} WHERE id = ?", Table::PAYMENT.as_str());
+    let mut stmt = conn.prepare(&query)?;
+
+    let payment = stmt.query_row(params![id], |row| {
+        Ok(Payment {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            customer_id: row.get(2)?,
+            amount: row.get(3)?,
+            receiver_code: row.get(4)?,
+            reference: row.get(5)?,
+            note: row.get(6)?,
+            payee_id: row.get(7)?,
+        })
+    })?;
+    Ok(payment)
+}
+
+/// Records a reversal of `amount` (or whatever is left to reverse when `None`)
+/// against the row `original_id` of `table`, returning the reversed amount.
+/// Fails with `StatementChangedRows(0)` when nothing is left to reverse or the
+/// amount exceeds what is left.
+fn insert_reversal(
+    tx: &rusqlite::Transaction,
+    table: Table,
+    original_id: u16,
+    original_amount: f64,
+    amount: Option<f64>,
+) -> Result<f64> {
+    let query = format!(
+        "SELECT COALESCE(SUM(amount), 0) FROM {} WHERE kind = ?1 AND original_id = ?2",
+        Table::REVERSAL.as_str()
+    );
+    let reversed: f64 = tx.query_row(&query, params![table.as_str(), original_id], |row| {
+        row.get(0)
+    })?;
+
+    let remaining = original_amount - reversed;
+    let amount = amount.unwrap_or(remaining);
+
+    if remaining <= 0.0 || amount <= 0.0 || amount > remaining {
+        return Err(rusqlite::Error::StatementChangedRows(0));
+    }
+
+    let query = format!(
+        "INSERT INTO {} (created_at, kind, original_id, amount) VALUES (?1, ?2, ?3, ?4)",
+        Table::REVERSAL.as_str()
+    );
+    tx.execute(
+        &query,
+        params![Utc::now().to_rfc2822(), table.as_str(), original_id, amount],
+    )?;
+    chain_journal_row(tx, Table::REVERSAL, tx.last_insert_rowid())?;
+    Ok(amount)
+}
+
+/// Adds `amount` to the stored balance and returns the new one. A debit only
+/// goes through when the customer can cover it at that moment, whatever was
+/// read before; otherwise no row changes and `StatementChangedRows(0)` is
+/// returned.
+fn add_to_balance(tx: &rusqlite::Transaction, id: u16, amount: f64) -> Result<f64> {
+    let query = format!(
+        "UPDATE {} SET balance = balance + ?1 WHERE id = ?2 AND balance + ?1 >= 0 RETURNING balance",
+        Table::CUSTOMER.as_str()
+    );
+    tx.query_row(&query, params![amount, id], |row| row.get(0))
+        .optional()?
+        .ok_or(rusqlite::Error::StatementChangedRows(0))
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn reverse_transfer(id: u16, amount: Option<f64>) -> Result<f64> {
+    let transfer = get_transfer(id)?;
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let amount = insert_reversal(&tx, Table::TRANSFER, id, transfer.amount, amount)?;
+    let balances = [
+        (
+            transfer.id_to,
+            add_to_balance(&tx, transfer.id_to, -amount)?,
+        ),
+        (
+            transfer.id_from,
+            add_to_balance(&tx, transfer.id_from, amount)?,
+        ),
+    ];
+
+    insert_audit_event(
+        &tx,
+        "reverse",
+        Table::TRANSFER.as_str(),
+        Some(id),
+        None,
+        Some(serde_json::json!({ "amount": amount })),
+    )?;
+
+    tx.commit()?;
+    for (id, balance) in balances {
+        stream::publish(
+            "balance.changed",
+            &[id],
+            serde_json::json!({ "customerId": id, "balance": balance }),
+        );
+    }
+    Ok(amount)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn reverse_payment(id: u16, amount: Option<f64>) -> Result<f64> {
+    let payment = get_payment(id)?;
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let amount = insert_reversal(&tx, Table::PAYMENT, id, payment.amount, amount)?;
+    let customer_id = payment.customer_id.unwrap();
+    let balance = add_to_balance(&tx, customer_id, amount)?;
+
+    insert_audit_event(
+        &tx,
+        "reverse",
+        Table::PAYMENT.as_str(),
+        Some(id),
+        None,
+        Some(serde_json::json!({ "amount": amount })),
+    )?;
+
+    tx.commit()?;
+    stream::publish(
+        "balance.changed",
+        &[customer_id],
+        serde_json::json!({ "customerId": customer_id, "balance": balance }),
+    );
+    Ok(amount)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_reversals() -> Result<Vec<Reversal>> {
+    let mut record_list: Vec<Reversal> = Vec::new();
+
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT id, created_at, kind, original_id, amount FROM {}",
+        Table::REVERSAL.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    stmt.query_map(params![], |row| {
+        Ok(Reversal {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            kind: row.get(2)?,
+            original_id: row.get(3)?,
+            amount: row.get(4)?,
+        })
+    })?
+    .for_each(|i| record_list.push(i.unwrap()));
+
+    Ok(record_list)
+}
+
+fn payee_from_row(row: &rusqlite::Row) -> Result<Payee> {
+    Ok(Payee {
+        id: row.get(0)?,
+        created_at: row.get(1)?,
+        customer_id: row.get(2)?,
+        name: row.get(3)?,
+        receiver_code: row.get(4)?,
+        nickname: row.get(5)?,
+        account_number: row.get(6)?,
+    })
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(payee))]
+pub fn create_payee(payee: &models::Payee) -> Result<()> {
+    let query = format!(
+        "INSERT INTO {} (created_at, customer_id, name, receiver_code, nickname, account_number) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        Table::PAYEE.as_str()
+    );
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
+        &query,
+        params![
+            payee.created_at,
+            payee.customer_id,
+            payee.name,
+            payee.receiver_code,
+            payee.nickname,
+            payee.account_number
+        ],
+    )?;
+
+    insert_audit_event(
+        &tx,
+        "create",
+        Table::PAYEE.as_str(),
+        Some(tx.last_insert_rowid() as u16),
+        None,
+        Some(serde_json::json!(payee)),
+    )?;
+    tx.commit()
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_payee(customer_id: u16, id: u16) -> Result<Payee> {
+    read_payee(&get_connection().unwrap(), customer_id, id)
+}
+
+fn read_payee(conn: &Connection, customer_id: u16, id: u16) -> Result<Payee> {
+    let query = format!(
+        "SELECT * FROM {} WHERE customer_id = ?1 AND id = ?2",
+        Table::PAYEE.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    stmt.query_row(params![customer_id, id], payee_from_row)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_payees_by_customer(customer_id: u16) -> Result<Vec<Payee>> {
+    let conn = get_connection().unwrap();
+    let mut record_list: Vec<Payee> = Vec::new();
+
+    let query = format!(
+        "SELECT * FROM {} WHERE customer_id = ?1",
+        Table::PAYEE.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    stmt.query_map(params![customer_id], payee_from_row)?
+        .for_each(|i| record_list.push(i.unwrap()));
+
+    Ok(record_list)
+}
+
+/// Changing the receiver code or account number restarts the payee's
+/// cooling-off period.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(payee))]
+pub fn edit_payee(customer_id: u16, id: u16, payee: &models::Payee) -> Result<()> {
+    let query = format!(
+        "UPDATE {} SET name = ?1, nickname = ?2,
+        created_at = CASE WHEN receiver_code = ?3 AND account_number IS ?7 THEN created_at ELSE ?4 END,
+        receiver_code = ?3, account_number = ?7
+        WHERE customer_id = ?5 AND id = ?6",
+        Table::PAYEE.as_str()
+    );
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let before = read_payee(&tx, customer_id, id)
+        .ok()
+        .map(|x| serde_json::json!(x));
+    tx.execute(
+        &query,
+        params![
+            payee.name,
+            payee.nickname,
+            payee.receiver_code,
+            Utc::now().to_rfc2822(),
+            customer_id,
+            id,
+            payee.account_number
+        ],
+    )?;
+
+    let after = read_payee(&tx, customer_id, id)
+        .ok()
+        .map(|x| serde_json::json!(x));
+    insert_audit_event(
+        &tx,
+        "update",
+        Table::PAYEE.as_str(),
+        Some(id),
+        before,
+        after,
+    )?;
+    tx.commit()
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn delete_payee(customer_id: u16, id: u16) -> Result<()> {
+    let query = format!(
+        "DELETE FROM {} WHERE customer_id = ?1 AND id = ?2",
+        Table::PAYEE.as_str()
+    );
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let before = read_payee(&tx, customer_id, id)
+        .ok()
+        .map(|x| serde_json::json!(x));
+    tx.execute(&query, params![customer_id, id])?;
+
+    insert_audit_event(&tx, "delete", Table::PAYEE.as_str(), Some(id), before, None)?;
+    tx.commit()
+}
+
+/// Moves a customer to `status`, recording the change and its reason. When
+/// `payout_receiver_code` is given, the remaining balance is first paid out to
+/// it, so that closing leaves the account at zero.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(customer))]
+pub fn change_status(
+    customer: &Customer,
+    status: models::CustomerStatus,
+    reason: &str,
+    payout_receiver_code: Option<&str>,
+) -> Result<()> {
+    let created_at = Utc::now().to_rfc2822();
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut events: Vec<stream::Pending> = Vec::new();
+
+    if let Some(receiver_code) = payout_receiver_code {
+        let query = format!(
+            "INSERT INTO {} (created_at, customer_id, amount, receiver_code, reference, note) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            Table::PAYMENT.as_str()
+        );
+        tx.execute(
+            &query,
+            params![
+                created_at,
+                customer.id,
+                customer.balance,
+                receiver_code,
+                "account payout",
+                reason
+            ],
+        )?;
+        let payment_id = tx.last_insert_rowid();
+        chain_journal_row(&tx, Table::PAYMENT, payment_id)?;
+
+        // the payout is the balance that was read; a change since then fails
+        // the close instead of being lost
+        let id = customer.id.unwrap();
+        if add_to_balance(&tx, id, -customer.balance.unwrap())? != 0.0 {
+            return Err(rusqlite::Error::StatementChangedRows(0));
+        }
+
+        events.push((
+            "payment.created",
+            vec![id],
+            serde_json::json!({
+                "paymentId": payment_id,
+                "customerId": id,
+                "amount": customer.balance,
+                "receiverCode": receiver_code,
+                "reference": "account payout",
+            }),
+        ));
+        events.push((
+            "balance.changed",
+            vec![id],
+            serde_json::json!({ "customerId": id, "balance": 0.0 }),
+        ));
+    }
+
+    let query = format!(
+        "UPDATE {} SET status = ?1 WHERE id = ?2",
+        Table::CUSTOMER.as_str()
+    );
+    tx.execute(&query, params![status.as_str(), customer.id])?;
+
+    let query = format!(
+        "INSERT INTO {} (created_at, customer_id, from_status, to_status, reason) VALUES (?1, ?2, ?3, ?4, ?5)",
+        Table::STATUS_CHANGE.as_str()
+    );
+    tx.execute(
+        &query,
+        params![
+            created_at,
+            customer.id,
+            customer.status,
+            status.as_str(),
+            reason
+        ],
+    )?;
+
+    insert_audit_event(
+        &tx,
+        "update",
+        Table::CUSTOMER.as_str(),
+        customer.id,
+        Some(serde_json::json!({ "status": customer.status, "balance": customer.balance })),
+        Some(serde_json::json!({
+            "status": status.as_str(),
+            "reason": reason,
+            "payoutReceiverCode": payout_receiver_code,
+        })),
+    )?;
+
+    tx.commit()?;
+    stream::publish_all(events);
+    Ok(())
+}
+
+/// Audit events form a hash chain: every row stores the hash of the row before
+/// it, so editing or deleting a row in the database file breaks the chain.
+fn insert_audit_event(
+    conn: &Connection,
+    action: &str,
+    entity: &str,
+    entity_id: Option<u16>,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+) -> Result<()> {
+    // reading the previous hash and appending must not interleave with
+    // another writer, so take the write lock up front when not in a transaction
+    let own_transaction = conn.is_autocommit();
+    if own_transaction {
+        conn.execute_batch("BEGIN IMMEDIATE")?;
+    }
+
+    let result = append_audit_event(conn, action, entity, entity_id, before, after);
+
+    if own_transaction {
+        match result {
+            Ok(_) => conn.execute_batch("COMMIT")?,
+            Err(_) => conn.execute_batch("ROLLBACK")?,
+        }
+    }
+    result
+}
+
+fn append_audit_event(
+    conn: &Connection,
+    action: &str,
+    entity: &str,
+    entity_id: Option<u16>,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+) -> Result<()> {
+    let context = audit::current();
+
+    let query = format!(
+        "SELECT hash FROM {} WHERE hash IS NOT NULL ORDER BY id DESC LIMIT 1",
+        Table::AUDIT_EVENT.as_str()
+    );
+    let prev_hash = match conn.query_row(&query, [], |row| row.get::<_, String>(0)) {
+        Ok(x) => x,
+        Err(rusqlite::Error::QueryReturnedNoRows) => audit::GENESIS_HASH.to_string(),
+        Err(e) => return Err(e),
+    };
+
+    let fields = [
+        Some(Utc::now().to_rfc2822()),
+        Some(context.actor),
+        Some(action.to_string()),
+        Some(entity.to_string()),
+        entity_id.map(|x| x.to_string()),
+        before.map(|x| x.to_string()),
+        after.map(|x| x.to_string()),
+        context.ip,
+        context.request_id,
+    ];
+    let hash = audit::chain_hash(&prev_hash, &fields);
+
+    let query = format!(
+        "INSERT INTO {} (created_at, actor, action, entity, entity_id, before, after, ip, request_id, prev_hash, hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        Table::AUDIT_EVENT.as_str()
+    );
+    let [created_at, actor, action, entity, _, before, after, ip, request_id] = fields;
+
+    conn.execute(
+        &query,
+        params![
+            created_at, actor, action, entity, entity_id, before, after, ip, request_id, prev_hash,
+            hash
+        ],
+    )?;
+    Ok(())
+}
+
+/// Links row `id` of one of the `JOURNALS` to the row chained before it in
+/// the same table. Called within the transaction that inserts the row, so
+/// rows are chained in the order they are written.
+fn chain_journal_row(tx: &rusqlite::Transaction, table: Table, id: i64) -> Result<()> {
+    let columns = table.journal_columns().unwrap();
+
+    let query = format!(
+        "SELECT hash FROM {} WHERE hash IS NOT NULL ORDER BY id DESC LIMIT 1",
+        table.as_str()
+    );
+    let prev_hash = match tx.query_row(&query, [], |row| row.get::<_, String>(0)) {
+        Ok(x) => x,
+        Err(rusqlite::Error::QueryReturnedNoRows) => audit::GENESIS_HASH.to_string(),
+        Err(e) => return Err(e),
+    };
+
+    let query = format!("SELECT {} FROM {} WHERE id = ?1", columns, table.as_str());
+    let fields = tx.query_row(&query, [id], |row| {
+        journal_fields(row, columns.split(", ").count())
+    })?;
+    let hash = audit::chain_hash(&prev_hash, &fields);
+
+    let query = format!(
+        "UPDATE {} SET prev_hash = ?1, hash = ?2 WHERE id = ?3",
+        table.as_str()
+    );
+    tx.execute(&query, params![prev_hash, hash, id])?;
+    Ok(())
+}
+
+/// The first `count` columns of a journal row, as the text its hash is
+/// computed over.
+fn journal_fields(row: &rusqlite::Row, count: usize) -> Result<Vec<Option<String>>> {
+    (0..count)
+        .map(|i| {
+            Ok(match row.get_ref(i)? {
+                ValueRef::Null => None,
+                ValueRef::Integer(x) => Some(x.to_string()),
+                ValueRef::Real(x) => Some(x.to_string()),
+                ValueRef::Text(x) | ValueRef::Blob(x) => {
+                    Some(String::from_utf8_lossy(x).into_owned())
+                }
+            })
+        })
+        .collect()
+}
+
+/// Records an event that is not tied to a table, such as an HTTP request.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn create_audit_event(
+    action: &str,
+    entity: &str,
+    entity_id: Option<u16>,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+) -> Result<()> {
+    insert_audit_event(&get_connection()?, action, entity, entity_id, before, after)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(filter))]
+pub fn get_audit_events(filter: &models::AuditFilter) -> Result<Vec<AuditEvent>> {
+    let mut record_list: Vec<AuditEvent> = Vec::new();
+
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT id, created_at, actor, action, entity, entity_id, before, after, ip, request_id FROM {}
+        WHERE (?1 IS NULL OR actor = ?1) AND (?2 IS NULL OR action = ?2)
+        AND (?3 IS NULL OR entity = ?3) AND (?4 IS NULL OR entity_id = ?4)
+        AND (?5 IS NULL OR request_id = ?5) AND (?6 IS NULL OR id > ?6)
+        ORDER BY id LIMIT ?7",
+        Table::AUDIT_EVENT.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    stmt.query_map(
+        params![
+            filter.actor,
+            filter.action,
+            filter.entity,
+            filter.entity_id,
+            filter.request_id,
+            filter.after_id,
+            filter.limit.unwrap_or(100)
+        ],
+        |row| {
+            let before: Option<String> = row.get(6)?;
+            let after: Option<String> = row.get(7)?;
+            Ok(AuditEvent {
+                id: row.get(0)?,
+                created_at: row.get(1)?,
+                actor: row.get(2)?,
+                action: row.get(3)?,
+                entity: row.get(4)?,
+                entity_id: row.get(5)?,
+                before: before.and_then(|x| serde_json::from_str(&x).ok()),
+                after: after.and_then(|x| serde_json::from_str(&x).ok()),
+                ip: row.get(8)?,
+                request_id: row.get(9)?,
+            })
+        },
+    )?
+    .for_each(|i| record_list.push(i.unwrap()));
+
+    Ok(record_list)
+}
+
+/// Walks the audit chain and then the chain of each of the `JOURNALS` from the
+/// first row, and stops at the first row whose hash or link to the previous
+/// row does not hold. Rows written before a chain existed carry no hash and
+/// are only counted.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn verify_audit_chain() -> Result<ChainReport> {
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT id, created_at, actor, action, entity, entity_id, before, after, ip, request_id, prev_hash, hash FROM {} ORDER BY id",
+        Table::AUDIT_EVENT.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let mut rows = stmt.query([])?;
+
+    let mut report = ChainReport {
+        valid: true,
+        checked: 0,
+        unchained: 0,
+        head: None,
+        table: None,
+        broken_at: None,
+        reason: None,
+    };
+    let mut expected_prev = audit::GENESIS_HASH.to_string();
+
+    while let Some(row) = rows.next()? {
+        let id: u32 = row.get(0)?;
+        let prev_hash: Option<String> = row.get(10)?;
+        let hash: Option<String> = row.get(11)?;
+
+        let reason = match (prev_hash, hash) {
+            (None, None) if report.head.is_none() => {
+                report.unchained += 1;
+                continue;
+            }
+            (Some(prev_hash), Some(hash)) => {
+                let entity_id: Option<u16> = row.get(5)?;
+                let fields = [
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    entity_id.map(|x| x.to_string()),
+                    row.get(6)?,
+                    row.get(7)?,
+                    row.get(8)?,
+                    row.get(9)?,
+                ];
+
+                if prev_hash != expected_prev {
+                    Some("does not link to the previous row".to_string())
+                } else if audit::chain_hash(&prev_hash, &fields) != hash {
+                    Some("content does not match its hash".to_string())
+                } else {
+                    expected_prev = hash;
+                    None
+                }
+            }
+            _ => Some("is missing its hash".to_string()),
+        };
+
+        if reason.is_some() {
+            report.valid = false;
+            report.table = Some(Table::AUDIT_EVENT.as_str().to_string());
+            report.broken_at = Some(id);
+            report.reason = reason;
+            return Ok(report);
+        }
+        report.checked += 1;
+        report.head = Some(expected_prev.clone());
+    }
+
+    for table in JOURNALS {
+        if !verify_journal(&conn, table, &mut report)? {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+/// The chain of one of the `JOURNALS`, added to `report`; false when it is
+/// broken.
+fn verify_journal(conn: &Connection, table: Table, report: &mut ChainReport) -> Result<bool> {
+    let columns = table.journal_columns().unwrap();
+    let count = columns.split(", ").count();
+    let query = format!(
+        "SELECT {}, prev_hash, hash FROM {} ORDER BY id",
+        columns,
+        table.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let mut rows = stmt.query([])?;
+
+    let mut expected_prev: Option<String> = None;
+    while let Some(row) = rows.next()? {
+        let id: u32 = row.get(0)?;
+        let prev_hash: Option<String> = row.get(count)?;
+        let hash: Option<String> = row.get(count + 1)?;
+
+        let reason = match (prev_hash, hash) {
+            (None, None) if expected_prev.is_none() => {
+                report.unchained += 1;
+                continue;
+            }
+            (Some(prev_hash), Some(hash)) => {
+                let fields = journal_fields(row, count)?;
+                let expected = expected_prev
+                    .take()
+                    .unwrap_or_else(|| audit::GENESIS_HASH.to_string());
+
+                if prev_hash != expected {
+                    Some("does not link to the previous row")
+                } else if audit::chain_hash(&prev_hash, &fields) != hash {
+                    Some("content does not match its hash")
+                } else {
+                    expected_prev = Some(hash);
+                    None
+                }
+            }
+            _ => Some("is missing its hash"),
+        };
+
+        if let Some(reason) = reason {
+            report.valid = false;
+            report.table = Some(table.as_str().to_string());
+            report.broken_at = Some(id);
+            report.reason = Some(reason.to_string());
+            return Ok(false);
+        }
+        report.checked += 1;
+    }
+    Ok(true)
+}
+
+/// What a customer's balance should be according to the recorded movements,
+/// transfers, payments and reversals, for a customer aliased as `c`.
+pub(crate) fn expected_balance_sql() -> String {
+    format!(
+        "(SELECT COALESCE(SUM(m.amount), 0) FROM {movements} AS m WHERE m.customer_id = c.id)
+        + (SELECT COALESCE(SUM(t.amount), 0) FROM {transfers} AS t WHERE t.to_id = c.id)
+        - (SELECT COALESCE(SUM(t.amount), 0) FROM {transfers} AS t WHERE t.from_id = c.id)
+        - (SELECT COALESCE(SUM(p.amount), 0) FROM {payments} AS p WHERE p.customer_id = c.id)
+        + (SELECT COALESCE(SUM(r.amount), 0) FROM {reversals} AS r JOIN {transfers} AS t
+            ON r.kind = '{transfers}' AND r.original_id = t.id WHERE t.from_id = c.id)
+        - (SELECT COALESCE(SUM(r.amount), 0) FROM {reversals} AS r JOIN {transfers} AS t
+            ON r.kind = '{transfers}' AND r.original_id = t.id WHERE t.to_id = c.id)
+        + (SELECT COALESCE(SUM(r.amount), 0) FROM {reversals} AS r JOIN {payments} AS p
+            ON r.kind = '{payments}' AND r.original_id = p.id WHERE p.customer_id = c.id)",
+        movements = Table::MOVEMENT.as_str(),
+        transfers = Table::TRANSFER.as_str(),
+        payments = Table::PAYMENT.as_str(),
+        reversals = Table::REVERSAL.as_str(),
+    )
+}
+
+/// Compares every balance with the one expected from the recorded
+/// transactions. With `correct`, the recorded transactions are taken as the
+/// truth: the unrecorded change is booked as a `discrepancy` movement and a
+/// `correction` movement takes it back out of the balance.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn reconcile(correct: bool) -> Result<Reconciliation> {
+    let mut conn = get_connection().unwrap();
+    // no balance may change between reading and correcting it
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let query = format!(
+        "SELECT c.id, c.balance, {} FROM {} AS c ORDER BY c.id",
+        expected_balance_sql(),
+        Table::CUSTOMER.as_str()
+    );
+    let balances: Vec<(u16, f64, f64)> = tx
+        .prepare(&query)?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_>>()?;
+
+    let mut report = Reconciliation {
+        total_balance: balances.iter().map(|x| x.1).sum(),
+        expected_total: balances.iter().map(|x| x.2).sum(),
+        invariant_holds: true,
+        discrepancies: Vec::new(),
+        carried_over: Vec::new(),
+        corrected: correct,
+    };
+    report.invariant_holds = (report.total_balance - report.expected_total).abs() < 0.005;
+    let mut events: Vec<stream::Pending> = Vec::new();
+
+    let query = format!(
+        "SELECT customer_id, SUM(amount) FROM {} WHERE kind = 'carried_over' GROUP BY customer_id ORDER BY customer_id",
+        Table::MOVEMENT.as_str()
+    );
+    report.carried_over = tx
+        .prepare(&query)?
+        .query_map([], |row| {
+            Ok(models::CarriedOver {
+                customer_id: row.get(0)?,
+                amount: row.get(1)?,
+            })
+        })?
+        .collect::<Result<_>>()?;
+
+    for (id, balance, expected) in balances {
+        if (balance - expected).abs() < 0.005 {
+            continue;
+        }
+
+        if correct {
+            let created_at = Utc::now().to_rfc2822();
+            let query = format!(
+                "INSERT INTO {} (created_at, customer_id, kind, amount) VALUES (?1, ?2, ?3, ?4)",
+                Table::MOVEMENT.as_str()
+            );
+            let mut movement_ids = Vec::new();
+            for (kind, amount) in [
+                ("discrepancy", balance - expected),
+                ("correction", expected - balance),
+            ] {
+                tx.execute(&query, params![created_at, id, kind, amount])?;
+                let movement_id = tx.last_insert_rowid();
+                chain_journal_row(&tx, Table::MOVEMENT, movement_id)?;
+                movement_ids.push(movement_id);
+            }
+            let corrected = add_to_balance(&tx, id, expected - balance)?;
+
+            insert_audit_event(
+                &tx,
+                "reconcile",
+                Table::CUSTOMER.as_str(),
+                Some(id),
+                Some(serde_json::json!({ "balance": balance })),
+                Some(serde_json::json!({ "balance": corrected, "movementIds": movement_ids })),
+            )?;
+            enqueue_balance_low(&tx, id, Some(balance), corrected)?;
+            events.push((
+                "balance.changed",
+                vec![id],
+                serde_json::json!({ "customerId": id, "balance": corrected }),
+            ));
+        }
+
+        report.discrepancies.push(Discrepancy {
+            customer_id: id,
+            balance,
+            expected,
+            difference: balance - expected,
+        });
+    }
+
+    tx.commit()?;
+    stream::publish_all(events);
+    Ok(report)
+}
+
+/// Writes an event to the webhook outbox. It is given the transaction that
+/// makes the change, so the event is stored if and only if the change is.
+fn enqueue_webhook_event(
+    conn: &Connection,
+    event_type: models::WebhookEventType,
+    data: serde_json::Value,
+) -> Result<()> {
+    let query = format!(
+        "INSERT INTO {} (created_at, event_type, payload) VALUES (?1, ?2, ?3)",
+        Table::WEBHOOK_EVENT.as_str()
+    );
+    conn.execute(
+        &query,
+        params![
+            Utc::now().to_rfc2822(),
+            event_type.as_str(),
+            data.to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+/// `balance.low` is sent when a balance drops below the threshold, not on
+/// every change while it stays there.
+fn enqueue_balance_low(
+    conn: &Connection,
+    id: u16,
+    previous: Option<f64>,
+    balance: f64,
+) -> Result<()> {
+    let threshold = webhooks::LOW_BALANCE_THRESHOLD;
+
+    if balance >= threshold || previous.is_some_and(|x| x < threshold) {
+        return Ok(());
+    }
+    enqueue_webhook_event(
+        conn,
+        models::WebhookEventType::BalanceLow,
+        serde_json::json!({ "customerId": id, "balance": balance, "threshold": threshold }),
+    )
+}
+
+fn webhook_from_row(row: &rusqlite::Row) -> Result<Webhook> {
+    let event_types: String = row.get(4)?;
+    Ok(Webhook {
+        id: row.get(0)?,
+        created_at: row.get(1)?,
+        url: row.get(2)?,
+        secret: row.get(3)?,
+        event_types: event_types.split(',').map(|x| x.to_string()).collect(),
+    })
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(webhook))]
+pub fn create_webhook(webhook: &models::Webhook) -> Result<u16> {
+    let query = format!(
+        "INSERT INTO {} (created_at, url, secret, event_types) VALUES (?1, ?2, ?3, ?4)",
+        Table::WEBHOOK.as_str()
+    );
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
+        &query,
+        params![
+            webhook.created_at,
+            webhook.url,
+            webhook.secret,
+            webhook.event_types.join(",")
+        ],
+    )?;
+    let id = tx.last_insert_rowid() as u16;
+
+    insert_audit_event(
+        &tx,
+        "create",
+        Table::WEBHOOK.as_str(),
+        Some(id),
+        None,
+        Some(serde_json::json!(webhook)),
+    )?;
+    tx.commit()?;
+    Ok(id)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_webhook(id: u16) -> Result<Webhook> {
+    read_webhook(&get_connection().unwrap(), id)
+}
+
+fn read_webhook(conn: &Connection, id: u16) -> Result<Webhook> {
+    let query = format!(
+        "SELECT id, created_at, url, secret, event_types FROM {} WHERE id = ?1",
+        Table::WEBHOOK.as_str()
+    );
+    conn.query_row(&query, params![id], webhook_from_row)
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_webhooks() -> Result<Vec<Webhook>> {
+    let mut record_list: Vec<Webhook> = Vec::new();
+
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT id, created_at, url, secret, event_types FROM {}",
+        Table::WEBHOOK.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    stmt.query_map(params![], webhook_from_row)?
+        .for_each(|i| record_list.push(i.unwrap()));
+
+    Ok(record_list)
+}
+
+/// Removes the subscription together with its deliveries, including the ones
+/// still pending.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn delete_webhook(id: u16) -> Result<()> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let before = read_webhook(&tx, id).ok().map(|x| serde_json::json!(x));
+
+    let query = format!(
+        "DELETE FROM {} WHERE webhook_id = ?1",
+        Table::WEBHOOK_DELIVERY.as_str()
+    );
+    tx.execute(&query, params![id])?;
+
+    let query = format!("DELETE FROM {} WHERE id = ?1", Table::WEBHOOK.as_str());
+    if tx.execute(&query, params![id])? == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+
+    insert_audit_event(
+        &tx,
+        "delete",
+        Table::WEBHOOK.as_str(),
+        Some(id),
+        before,
+        None,
+    )?;
+
+    tx.commit()?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_webhook_deliveries(webhook_id: u16) -> Result<Vec<WebhookDelivery>> {
+    let mut record_list: Vec<WebhookDelivery> = Vec::new();
+
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT d.id, d.event_id, d.webhook_id, e.event_type, d.status, d.attempts, d.next_attempt_at, d.last_status_code, d.last_error, d.updated_at
+        FROM {} AS d JOIN {} AS e ON e.id = d.event_id WHERE d.webhook_id = ?1 ORDER BY d.id DESC",
+        Table::WEBHOOK_DELIVERY.as_str(),
+        Table::WEBHOOK_EVENT.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    stmt.query_map(params![webhook_id], |row| {
+        Ok(WebhookDelivery {
+            id: row.get(0)?,
+            event_id: row.get(1)?,
+            webhook_id: row.get(2)?,
+            event_type: row.get(3)?,
+            status: row.get(4)?,
+            attempts: row.get(5)?,
+            next_attempt_at: row.get(6)?,
+            last_status_code: row.get(7)?,
+            last_error: row.get(8)?,
+            updated_at: row.get(9)?,
+        })
+    })?
+    .for_each(|i| record_list.push(i.unwrap()));
+
+    Ok(record_list)
+}
+
+/// Creates a pending delivery for every subscription to each new outbox
+/// event. Returns the number of events processed.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn fan_out_webhook_events() -> Result<usize> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    let webhooks = {
+        let query = format!(
+            "SELECT id, created_at, url, secret, event_types FROM {}",
+            Table::WEBHOOK.as_str()
+        );
+        let mut stmt = tx.prepare(&query)?;
+        let rows = stmt.query_map(params![], webhook_from_row)?;
+        rows.collect::<Result<Vec<Webhook>>>()?
+    };
+    let events = {
+        let query = format!(
+            "SELECT id, event_type FROM {} WHERE fanned_out = 0 ORDER BY id",
+            Table::WEBHOOK_EVENT.as_str()
+        );
+        let mut stmt = tx.prepare(&query)?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?;
+        rows.collect::<Result<Vec<(u32, String)>>>()?
+    };
+
+    let now = Utc::now();
+    for (event_id, event_type) in &events {
+        for webhook in webhooks
+            .iter()
+            .filter(|x| x.event_types.contains(event_type))
+        {
+            let query = format!(
+                "INSERT INTO {} (event_id, webhook_id, status, next_attempt_at, updated_at) VALUES (?1, ?2, 'pending', ?3, ?4)",
+                Table::WEBHOOK_DELIVERY.as_str()
+            );
+            tx.execute(
+                &query,
+                params![event_id, webhook.id, now.timestamp(), now.to_rfc2822()],
+            )?;
+        }
+        let query = format!(
+            "UPDATE {} SET fanned_out = 1 WHERE id = ?1",
+            Table::WEBHOOK_EVENT.as_str()
+        );
+        tx.execute(&query, params![event_id])?;
+    }
+
+    tx.commit()?;
+    Ok(events.len())
+}
+
+/// Pending deliveries whose next attempt is at or before `now` (Unix time).
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_due_webhook_deliveries(now: i64, limit: u32) -> Result<Vec<WebhookDispatch>> {
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT d.id, d.attempts, w.url, w.secret, e.id, e.event_type, e.created_at, e.payload
+        FROM {} AS d JOIN {} AS w ON w.id = d.webhook_id JOIN {} AS e ON e.id = d.event_id
+        WHERE d.status = 'pending' AND d.next_attempt_at <= ?1 ORDER BY d.next_attempt_at, d.id LIMIT ?2",
+        Table::WEBHOOK_DELIVERY.as_str(),
+        Table::WEBHOOK.as_str(),
+        Table::WEBHOOK_EVENT.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    let rows = stmt.query_map(params![now, limit], |row| {
+        Ok(WebhookDispatch {
+            id: row.get(0)?,
+            attempts: row.get(1)?,
+            url: row.get(2)?,
+            secret: row.get(3)?,
+            event_id: row.get(4)?,
+            event_type: row.get(5)?,
+            event_created_at: row.get(6)?,
+            payload: row.get(7)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Records the outcome of an attempt. `next_attempt_at` is only kept while
+/// the delivery is still `pending`.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn record_webhook_attempt(
+    id: u32,
+    status: &str,
+    next_attempt_at: Option<i64>,
+    status_code: Option<u16>,
+    error: Option<&str>,
+) -> Result<()> {
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "UPDATE {} SET status = ?1, attempts = attempts + 1, next_attempt_at = ?2, last_status_code = ?3, last_error = ?4, updated_at = ?5 WHERE id = ?6",
+        Table::WEBHOOK_DELIVERY.as_str()
+    );
+    conn.execute(
+        &query,
+        params![
+            status,
+            next_attempt_at,
+            status_code,
+            error,
+            Utc::now().to_rfc2822(),
+            id
+        ],
+    )?;
+    Ok(())
+}
+
+/// Keeps the report of a batch that has been run, so that it can be looked up
+/// by the id returned.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(batch))]
+pub fn create_batch_report(batch: &models::Batch) -> Result<u32> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction()?;
+    let id = insert_batch_report(&tx, batch)?;
+    tx.commit()?;
+    Ok(id)
+}
+
+/// Keeps a batch as "running" before its lines are made one by one, so that
+/// lines are never made without a batch to show for them.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(batch))]
+pub fn start_batch_report(batch: &models::Batch) -> Result<u32> {
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "INSERT INTO {} (created_at, format, mode, status) VALUES (?1, ?2, ?3, 'running')",
+        Table::BATCH.as_str()
+    );
+    conn.execute(&query, params![batch.created_at, batch.format, batch.mode])?;
+    Ok(conn.last_insert_rowid() as u32)
+}
+
+/// Saves the outcome of a batch started with `start_batch_report`.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(batch))]
+pub fn finish_batch_report(id: u32, batch: &models::Batch) -> Result<()> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction()?;
+
+    let query = format!(
+        "UPDATE {} SET status = ?1 WHERE id = ?2",
+        Table::BATCH.as_str()
+    );
+    tx.execute(&query, params![batch.status, id])?;
+    insert_batch_lines(&tx, id, batch)?;
+
+    tx.commit()
+}
+
+fn insert_batch_report(tx: &rusqlite::Transaction, batch: &models::Batch) -> Result<u32> {
+    let query = format!(
+        "INSERT INTO {} (created_at, format, mode, status) VALUES (?1, ?2, ?3, ?4)",
+        Table::BATCH.as_str()
+    );
+    tx.execute(
+        &query,
+        params![batch.created_at, batch.format, batch.mode, batch.status],
+    )?;
+    let id = tx.last_insert_rowid() as u32;
+    insert_batch_lines(tx, id, batch)?;
+    Ok(id)
+}
+
+/// The line results of the batch, and the audit event that records it.
+fn insert_batch_lines(tx: &rusqlite::Transaction, id: u32, batch: &models::Batch) -> Result<()> {
+    let query = format!(
+        "INSERT INTO {} (batch_id, line, kind, status, code, message) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        Table::BATCH_LINE.as_str()
+    );
+    for line in &batch.lines {
+        tx.execute(
+            &query,
+            params![
+                id,
+                line.line,
+                line.kind,
+                line.status,
+                line.code,
+                line.message
+            ],
+        )?;
+    }
+
+    insert_audit_event(
+        tx,
+        "create",
+        Table::BATCH.as_str(),
+        Some(id as u16),
+        None,
+        Some(serde_json::json!({
+            "format": batch.format,
+            "mode": batch.mode,
+            "status": batch.status,
+            "succeeded": batch.succeeded,
+            "failed": batch.failed,
+        })),
+    )?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_batch_report(id: u32) -> Result<models::Batch> {
+    let conn = get_connection().unwrap();
+
+    let query = format!(
+        "SELECT line, kind, status, code, message FROM {} WHERE batch_id = ?1 ORDER BY line",
+        Table::BATCH_LINE.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let lines = stmt
+        .query_map(params![id], |row| {
+            Ok(models::BatchLineResult {
+                line: row.get(0)?,
+                kind: row.get(1)?,
+                status: row.get(2)?,
+                code: row.get(3)?,
+                message: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    let query = format!(
+        "SELECT id, created_at, format, mode, status FROM {} WHERE id = ?1",
+        Table::BATCH.as_str()
+    );
+    conn.query_row(&query, params![id], |row| {
+        Ok(models::Batch {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            format: row.get(2)?,
+            mode: row.get(3)?,
+            status: row.get(4)?,
+            succeeded: lines.iter().filter(|x| x.status == "succeeded").count() as u32,
+            failed: lines.iter().filter(|x| x.status == "failed").count() as u32,
+            lines,
+        })
+    })
+}
+
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_jwt_secrets() -> Result<Vec<JwtSecret>> {
+    let conn = get_connection().unwrap();
+    let query = format!(
+        "SELECT id, created_at, secret, retired_at FROM {} ORDER BY id DESC",
+        Table::JWT_SECRET.as_str()
+    );
+    let mut stmt = conn.prepare(&query)?;
+
+    let rows = stmt.query_map(params![], |row| {
+        Ok(JwtSecret {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            secret: row.get(2)?,
+            retired_at: row.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Stores `secret` as the signing key and retires the previous ones, which
+/// stay valid for verification until the tokens they signed have expired.
+#[tracing::instrument(level = "debug", err(level = "warn"), skip(secret))]
+pub fn rotate_jwt_secret(secret: &str) -> Result<u32> {
+    let mut conn = get_connection().unwrap();
+    let tx = conn.transaction()?;
+    let now = Utc::now();
+
+    let query = format!(
+        "UPDATE {} SET retired_at = ?1 WHERE retired_at IS NULL",
+        Table::JWT_SECRET.as_str()
+    );
+    tx.execute(&query, params![now.timestamp()])?;
+
+    let query = format!(
+        "INSERT INTO {} (created_at, secret) VALUES (?1, ?2)",
+        Table::JWT_SECRET.as_str()
+    );
+    tx.execute(&query, params![now.to_rfc2822(), secret])?;
+    let id = tx.last_insert_rowid() as u32;
+
+    insert_audit_event(
+        &tx,
+        "rotate",
+        Table::JWT_SECRET.as_str(),
+        Some(id as u16),
+        None,
+        None,
+    )?;
+
+    tx.commit()?;
+    Ok(id)
+}
+
+/// Every transfer sent or received by the customer and every payment they
+/// made, in no particular order.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_bookings(id: u16) -> Result<Vec<models::Booking>> {
+    let conn = get_connection().unwrap();
+
+    let query = format!(
+        "SELECT t.id, t.created_at, -t.amount, c.id, c.name FROM {transfers} AS t JOIN {customers} AS c ON c.id = t.to_id WHERE t.from_id = ?1
+        UNION ALL SELECT t.id, t.created_at, t.amount, c.id, c.name FROM {transfers} AS t JOIN {customers} AS c ON c.id = t.from_id WHERE t.to_id = ?1",
+        transfers = Table::TRANSFER.as_str(),
+        customers = Table::CUSTOMER.as_str(),
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let mut bookings = stmt
+        .query_map(params![id], |row| {
+            Ok(models::Booking::Transfer {
+                id: row.get(0)?,
+                created_at: row.get(1)?,
+                amount: row.get(2)?,
+                counterparty_id: row.get(3)?,
+                counterparty_name: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    bookings.extend(
+        get_payments_by_customer(id)?
+            .into_iter()
+            .map(models::Booking::Payment),
+    );
+    Ok(bookings)
+}
+
+/// Every transaction that moved the customer's balance, oldest first, with
+/// the running balance. Lines before `from` are folded into the opening
+/// balance and lines after `to` are left out.
+#[tracing::instrument(level = "debug", err(level = "warn"))]
+pub fn get_statement(
+    id: u16,
+    from: Option<chrono::NaiveDate>,
+    to: Option<chrono::NaiveDate>,
+) -> Result<Statement> {
+    let customer = get_customer(id)?;
+    let conn = get_connection().unwrap();
+
+    let mut stmt = conn.prepare(&statement_sql())?;
+    let lines = stmt
+        .query_map(params![id], |row| {
+            Ok(StatementLine {
+                created_at: row.get(0)?,
+                kind: row.get(1)?,
+                description: row.get(2)?,
+                amount: row.get(3)?,
+                balance: 0.0,
+            })
+        })?
+        .collect::<Result<Vec<StatementLine>>>()?;
+
+    Ok(fold_statement(id, customer.name, lines, from, to))
+}
+
+/// The lines of the customer `$1`'s statement, unsorted; mirrors
+/// `expected_balance_sql`, one line per term.
+pub(crate) fn statement_sql() -> String {
+    format!(
+        "SELECT m.created_at, m.kind, m.kind, m.amount FROM {movements} AS m WHERE m.customer_id = $1
+        UNION ALL SELECT t.created_at, 'transfer', 'to ' || c.name, -t.amount
+            FROM {transfers} AS t JOIN {customers} AS c ON c.id = t.to_id WHERE t.from_id = $1
+        UNION ALL SELECT t.created_at, 'transfer', 'from ' || c.name, t.amount
+            FROM {transfers} AS t JOIN {customers} AS c ON c.id = t.from_id WHERE t.to_id = $1
+        UNION ALL SELECT p.created_at, 'payment', p.receiver_code, -p.amount FROM {payments} AS p WHERE p.customer_id = $1
+        UNION ALL SELECT r.created_at, 'reversal', 'transfer ' || t.id, r.amount FROM {reversals} AS r JOIN {transfers} AS t
+            ON r.kind = '{transfers}' AND r.original_id = t.id WHERE t.from_id = $1
+        UNION ALL SELECT r.created_at, 'reversal', 'transfer ' || t.id, -r.amount FROM {reversals} AS r JOIN {transfers} AS t
+            ON r.kind = '{transfers}' AND r.original_id = t.id WHERE t.to_id = $1
+        UNION ALL SELECT r.created_at, 'reversal', 'payment ' || p.id, r.amount FROM {reversals} AS r JOIN {payments} AS p
+            ON r.kind = '{payments}' AND r.original_id = p.id WHERE p.customer_id = $1",
+        movements = Table::MOVEMENT.as_str(),
+        transfers = Table::TRANSFER.as_str(),
+        payments = Table::PAYMENT.as_str(),
+        reversals = Table::REVERSAL.as_str(),
+        customers = Table::CUSTOMER.as_str(),
+    )
+}
+
+/// Sorts the lines and runs the balance over them, for `get_statement`.
+pub(crate) fn fold_statement(
+    id: u16,
+    name: String,
+    mut lines: Vec<StatementLine>,
+    from: Option<chrono::NaiveDate>,
+    to: Option<chrono::NaiveDate>,
+) -> Statement {
+    // dates are stored as RFC 2822 text, which does not sort
+    let date_of = |line: &StatementLine| {
+        chrono::DateTime::parse_from_rfc2822(&line.created_at)
+            .map(|x| x.with_timezone(&Utc))
+            .ok()
+    };
+    lines.sort_by_key(date_of);
+
+    let mut statement = Statement {
+        customer_id: id,
+        name,
+        from: from.map(|x| x.to_string()),
+        to: to.map(|x| x.to_string()),
+        opening_balance: 0.0,
+        closing_balance: 0.0,
+        lines: Vec::new(),
+    };
+    let mut balance = 0.0;
+    for mut line in lines {
+        let day = date_of(&line).map(|x| x.date_naive());
+        if matches!((day, to), (Some(day), Some(to)) if day > to) {
+            continue;
+        }
+        balance += line.amount;
+        if matches!((day, from), (Some(day), Some(from)) if day < from) {
+            statement.opening_balance = balance;
+            continue;
+        }
+        line.balance = balance;
+        statement.lines.push(line);
+    }
+    statement.closing_balance = balance;
+    statement
+}
@@ -1,19 +1,44 @@
+use chrono::{NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
 
-#[derive(Serialize, Deserialize, Validate)]
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct Customer {
     pub id: Option<u16>,
     #[validate(length(min = 3))]
+    #[schema(min_length = 3)]
     pub name: String,
     #[validate(range(min = 0))]
+    #[schema(minimum = 0)]
     pub balance: Option<f64>,
     pub created_at: Option<String>,
+    pub status: Option<String>,
+    #[serde(rename = "dateOfBirth")]
+    #[validate(custom = "validate_date_of_birth")]
+    #[schema(format = Date)]
+    pub date_of_birth: Option<String>,
+    #[serde(rename = "nationalId")]
+    #[validate(length(min = 5, max = 20))]
+    #[schema(min_length = 5, max_length = 20)]
+    pub national_id: Option<String>,
+    #[validate(length(min = 5))]
+    #[schema(min_length = 5)]
+    pub address: Option<String>,
+    #[validate(email)]
+    #[schema(format = Email)]
+    pub email: Option<String>,
+    #[validate(custom = "validate_phone")]
+    #[schema(pattern = r"^\+?[0-9]{7,15}$")]
+    pub phone: Option<String>,
+    #[serde(rename = "kycLevel")]
+    pub kyc_level: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Validate)]
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct CustomerEdit {
     #[validate(length(min = 3))]
+    #[schema(min_length = 3)]
     pub name: String,
 }
 
@@ -22,6 +47,30 @@ pub struct Token {
     pub text: String,
 }
 
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CustomerPatch {
+    #[validate(length(min = 3))]
+    #[schema(min_length = 3)]
+    pub name: Option<String>,
+    #[serde(rename = "dateOfBirth")]
+    #[validate(custom = "validate_date_of_birth")]
+    #[schema(format = Date)]
+    pub date_of_birth: Option<String>,
+    #[serde(rename = "nationalId")]
+    #[validate(length(min = 5, max = 20))]
+    #[schema(min_length = 5, max_length = 20)]
+    pub national_id: Option<String>,
+    #[validate(length(min = 5))]
+    #[schema(min_length = 5)]
+    pub address: Option<String>,
+    #[validate(email)]
+    #[schema(format = Email)]
+    pub email: Option<String>,
+    #[validate(custom = "validate_phone")]
+    #[schema(pattern = r"^\+?[0-9]{7,15}$")]
+    pub phone: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Validate)]
 pub struct Claims {
     pub sub: String,
@@ -30,20 +79,30 @@ pub struct Claims {
     pub role: String,
 }
 
-#[derive(Serialize, Deserialize, Validate)]
+#[derive(Deserialize, IntoParams)]
+pub struct StreamAuth {
+    /// Alternative to the `authorization` header, for clients that cannot set it.
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct Payment {
     pub id: Option<u16>,
     pub created_at: Option<String>,
     #[serde(rename = "customerId")]
     pub customer_id: Option<u16>,
+    #[validate(custom = "validate_positive")]
+    #[schema(exclusive_minimum = 0)]
     pub amount: f64,
-    #[serde(rename = "receiverCode")]
+    #[serde(rename = "receiverCode", default)]
     pub receiver_code: String,
     pub reference: String,
-    pub note: String,
+    pub note: Option<String>,
+    #[serde(rename = "payeeId")]
+    pub payee_id: Option<u16>,
 }
 
-#[derive(Serialize, Deserialize, Validate)]
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
 pub struct Transfer {
     pub id: Option<u16>,
     #[serde(rename = "idFrom")]
@@ -51,11 +110,12 @@ pub struct Transfer {
     #[serde(rename = "idTo")]
     pub id_to: u16,
     #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
     pub amount: f64,
     pub created_at: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Validate)]
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
 pub struct TransferHuman {
     pub id: u16,
     pub name_from: String,
@@ -64,13 +124,571 @@ pub struct TransferHuman {
     pub created_at: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct APIResponse {
     pub message: String,
 }
 
-#[derive(Serialize, Deserialize, Validate)]
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
 pub struct Money {
     #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub amount: f64,
+}
+
+/// A compensating entry for the transfer or payment `original_id`, `kind`
+/// telling which. Reversals are kept apart from transfers and payments, so
+/// that a refund is never listed as money sent, and what is left to reverse
+/// is the original amount less its reversals.
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
+pub struct Reversal {
+    pub id: Option<u16>,
+    pub created_at: Option<String>,
+    pub kind: Option<String>,
+    #[serde(rename = "originalId")]
+    pub original_id: Option<u16>,
+    /// What is left to reverse when left out.
+    #[validate(custom = "validate_positive")]
+    #[schema(exclusive_minimum = 0)]
+    pub amount: Option<f64>,
+}
+
+/// A saved beneficiary, paid to its `receiverCode`, or its `accountNumber`
+/// when it has no receiver code. At least one of the two is required.
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "validate_payee_account"))]
+pub struct Payee {
+    pub id: Option<u16>,
+    pub created_at: Option<String>,
+    #[serde(rename = "customerId")]
+    pub customer_id: Option<u16>,
+    #[validate(length(min = 3))]
+    #[schema(min_length = 3)]
+    pub name: String,
+    #[serde(rename = "receiverCode", default)]
+    pub receiver_code: String,
+    #[serde(rename = "accountNumber")]
+    pub account_number: Option<String>,
+    pub nickname: Option<String>,
+}
+
+impl Payee {
+    /// What payments to this payee are sent to.
+    pub fn account(&self) -> &str {
+        match self.account_number.as_deref() {
+            Some(x) if self.receiver_code.trim().is_empty() => x,
+            _ => &self.receiver_code,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CustomerStatus {
+    Active,
+    Frozen,
+    Closed,
+}
+
+impl CustomerStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            CustomerStatus::Active => "active",
+            CustomerStatus::Frozen => "frozen",
+            CustomerStatus::Closed => "closed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<CustomerStatus> {
+        match status {
+            "active" => Some(CustomerStatus::Active),
+            "frozen" => Some(CustomerStatus::Frozen),
+            "closed" => Some(CustomerStatus::Closed),
+            _ => None,
+        }
+    }
+
+    /// Frozen accounts can be unfrozen; closed accounts stay closed.
+    pub fn can_become(&self, next: CustomerStatus) -> bool {
+        matches!(
+            (self, next),
+            (CustomerStatus::Active, CustomerStatus::Frozen)
+                | (CustomerStatus::Frozen, CustomerStatus::Active)
+                | (CustomerStatus::Active, CustomerStatus::Closed)
+                | (CustomerStatus::Frozen, CustomerStatus::Closed)
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
+pub struct StatusChange {
+    #[validate(length(min = 3))]
+    #[schema(min_length = 3)]
+    pub reason: String,
+    #[serde(rename = "payoutReceiverCode")]
+    #[validate(length(min = 1))]
+    #[schema(min_length = 1)]
+    pub payout_receiver_code: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KycLevel {
+    Unverified,
+    Basic,
+    Full,
+}
+
+impl KycLevel {
+    pub fn as_str(&self) -> &str {
+        match self {
+            KycLevel::Unverified => "unverified",
+            KycLevel::Basic => "basic",
+            KycLevel::Full => "full",
+        }
+    }
+
+    pub fn parse(level: &str) -> Option<KycLevel> {
+        match level {
+            "unverified" => Some(KycLevel::Unverified),
+            "basic" => Some(KycLevel::Basic),
+            "full" => Some(KycLevel::Full),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
+pub struct KycVerification {
+    #[validate(custom = "validate_kyc_level")]
+    #[schema(pattern = "^(unverified|basic|full)$")]
+    pub level: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct KycDocument {
+    pub id: Option<u16>,
+    pub created_at: Option<String>,
+    #[serde(rename = "customerId")]
+    pub customer_id: Option<u16>,
+    #[validate(length(min = 2))]
+    #[schema(min_length = 2)]
+    pub kind: String,
+    #[validate(length(min = 3))]
+    #[schema(min_length = 3)]
+    pub number: String,
+    #[serde(rename = "issuingCountry")]
+    #[validate(length(equal = 2))]
+    #[schema(min_length = 2, max_length = 2)]
+    pub issuing_country: String,
+    #[serde(rename = "expiresAt")]
+    #[validate(custom = "validate_date")]
+    #[schema(format = Date)]
+    pub expires_at: Option<String>,
+}
+
+fn validate_positive(amount: f64) -> Result<(), ValidationError> {
+    if amount > 0.0 {
+        Ok(())
+    } else {
+        Err(ValidationError::new("positive"))
+    }
+}
+
+fn validate_payee_account(payee: &Payee) -> Result<(), ValidationError> {
+    if payee.account().trim().is_empty() {
+        return Err(ValidationError::new("receiver_code_or_account_number"));
+    }
+    Ok(())
+}
+
+fn validate_date(date: &str) -> Result<(), ValidationError> {
+    match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("date_format")),
+    }
+}
+
+/// Customers must be adults, with a date of birth formatted as `YYYY-MM-DD`.
+fn validate_date_of_birth(date: &str) -> Result<(), ValidationError> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| ValidationError::new("date_format"))?;
+
+    match Utc::now().date_naive().years_since(date) {
+        Some(age) if age >= 18 => Ok(()),
+        _ => Err(ValidationError::new("underage")),
+    }
+}
+
+/// International format: an optional `+` followed by 7 to 15 digits.
+fn validate_phone(phone: &str) -> Result<(), ValidationError> {
+    let digits = phone.strip_prefix('+').unwrap_or(phone);
+
+    if (7..=15).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("phone"))
+    }
+}
+
+fn validate_kyc_level(level: &str) -> Result<(), ValidationError> {
+    match KycLevel::parse(level) {
+        Some(_) => Ok(()),
+        None => Err(ValidationError::new("kyc_level")),
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AuditEvent {
+    pub id: u32,
+    pub created_at: String,
+    pub actor: String,
+    pub action: String,
+    pub entity: String,
+    #[serde(rename = "entityId")]
+    pub entity_id: Option<u16>,
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<serde_json::Value>,
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<serde_json::Value>,
+    pub ip: Option<String>,
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub entity: Option<String>,
+    #[serde(rename = "entityId")]
+    pub entity_id: Option<u16>,
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+    #[serde(rename = "afterId")]
+    pub after_id: Option<u32>,
+    #[validate(range(min = 1, max = 1000))]
+    #[param(minimum = 1, maximum = 1000)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChainReport {
+    pub valid: bool,
+    pub checked: u32,
+    pub unchained: u32,
+    pub head: Option<String>,
+    /// The table of the row the chain breaks at.
+    pub table: Option<String>,
+    #[serde(rename = "brokenAt")]
+    pub broken_at: Option<u32>,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Discrepancy {
+    #[serde(rename = "customerId")]
+    pub customer_id: u16,
+    pub balance: f64,
+    pub expected: f64,
+    pub difference: f64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Reconciliation {
+    #[serde(rename = "totalBalance")]
+    pub total_balance: f64,
+    #[serde(rename = "expectedTotal")]
+    pub expected_total: f64,
+    #[serde(rename = "invariantHolds")]
+    pub invariant_holds: bool,
+    pub discrepancies: Vec<Discrepancy>,
+    /// Balances that were carried over without a recorded history when
+    /// movements started being kept.
+    #[serde(rename = "carriedOver")]
+    pub carried_over: Vec<CarriedOver>,
+    pub corrected: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CarriedOver {
+    #[serde(rename = "customerId")]
+    pub customer_id: u16,
     pub amount: f64,
 }
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ComponentHealth {
+    pub name: String,
+    /// "ok" or "fail"
+    pub status: String,
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct HealthReport {
+    /// "ok" when every component is ok, "fail" otherwise
+    pub status: String,
+    pub components: Vec<ComponentHealth>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WebhookEventType {
+    TransferCreated,
+    PaymentCreated,
+    BalanceLow,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            WebhookEventType::TransferCreated => "transfer.created",
+            WebhookEventType::PaymentCreated => "payment.created",
+            WebhookEventType::BalanceLow => "balance.low",
+        }
+    }
+
+    pub fn parse(event_type: &str) -> Option<WebhookEventType> {
+        match event_type {
+            "transfer.created" => Some(WebhookEventType::TransferCreated),
+            "payment.created" => Some(WebhookEventType::PaymentCreated),
+            "balance.low" => Some(WebhookEventType::BalanceLow),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct Webhook {
+    pub id: Option<u16>,
+    pub created_at: Option<String>,
+    #[validate(custom = "validate_webhook_url")]
+    #[schema(example = "http://localhost:9000/hooks")]
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent with every delivery. It is never
+    /// returned by the API.
+    #[serde(skip_serializing)]
+    #[validate(length(min = 16))]
+    #[schema(write_only, min_length = 16)]
+    pub secret: String,
+    #[serde(rename = "eventTypes")]
+    #[validate(custom = "validate_webhook_event_types")]
+    #[schema(example = json!(["transfer.created", "payment.created", "balance.low"]))]
+    pub event_types: Vec<String>,
+}
+
+/// Deliveries are made over plain HTTP, so subscriptions must use an `http`
+/// URL (behind a TLS-terminating proxy when leaving the host).
+fn validate_webhook_url(url: &str) -> Result<(), ValidationError> {
+    match url::Url::parse(url) {
+        Ok(x) if x.scheme() == "http" && x.host_str().is_some() => Ok(()),
+        _ => Err(ValidationError::new("webhook_url")),
+    }
+}
+
+fn validate_webhook_event_types(event_types: &[String]) -> Result<(), ValidationError> {
+    if event_types.is_empty()
+        || event_types
+            .iter()
+            .any(|x| WebhookEventType::parse(x).is_none())
+    {
+        return Err(ValidationError::new("webhook_event_types"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: u32,
+    #[serde(rename = "eventId")]
+    pub event_id: u32,
+    #[serde(rename = "webhookId")]
+    pub webhook_id: u16,
+    #[serde(rename = "eventType")]
+    pub event_type: String,
+    /// "pending", "delivered" or "failed"
+    pub status: String,
+    pub attempts: u32,
+    /// Unix time of the next attempt while the delivery is pending.
+    #[serde(rename = "nextAttemptAt")]
+    pub next_attempt_at: Option<i64>,
+    #[serde(rename = "lastStatusCode")]
+    pub last_status_code: Option<u16>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    pub updated_at: String,
+}
+
+/// A delivery that is due, with what is needed to send it.
+pub struct WebhookDispatch {
+    pub id: u32,
+    pub attempts: u32,
+    pub url: String,
+    pub secret: String,
+    pub event_id: u32,
+    pub event_type: String,
+    pub event_created_at: String,
+    pub payload: String,
+}
+
+/// A key used to sign and verify tokens. Only the newest one, which has no
+/// `retired_at`, signs new tokens.
+#[derive(Clone)]
+pub struct JwtSecret {
+    pub id: u32,
+    pub created_at: String,
+    pub secret: String,
+    /// Unix time at which a newer secret replaced this one.
+    pub retired_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct StatementLine {
+    pub created_at: String,
+    /// "opening", "carried_over", "deposit", "withdrawal", "discrepancy",
+    /// "correction", "transfer", "payment" or "reversal"
+    pub kind: String,
+    pub description: String,
+    pub amount: f64,
+    /// Balance after this line.
+    pub balance: f64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Statement {
+    #[serde(rename = "customerId")]
+    pub customer_id: u16,
+    pub name: String,
+    /// YYYY-MM-DD
+    pub from: Option<String>,
+    /// YYYY-MM-DD
+    pub to: Option<String>,
+    #[serde(rename = "openingBalance")]
+    pub opening_balance: f64,
+    #[serde(rename = "closingBalance")]
+    pub closing_balance: f64,
+    pub lines: Vec<StatementLine>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Backup {
+    /// File name inside the backup directory.
+    pub file: String,
+    pub path: String,
+    pub bytes: u64,
+    /// Result of `PRAGMA integrity_check` when the file was written or
+    /// restored; absent in listings.
+    pub integrity: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Validate, ToSchema)]
+pub struct BackupRestore {
+    /// A backup file name as listed by `GET /backups`.
+    #[validate(length(min = 1))]
+    #[schema(min_length = 1)]
+    pub file: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatchMode {
+    /// Nothing is done unless every line can be.
+    Atomic,
+    /// Lines run one by one, and the ones that fail are left out.
+    BestEffort,
+}
+
+impl BatchMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            BatchMode::Atomic => "atomic",
+            BatchMode::BestEffort => "best-effort",
+        }
+    }
+
+    pub fn parse(mode: &str) -> Option<BatchMode> {
+        match mode {
+            "atomic" => Some(BatchMode::Atomic),
+            "best-effort" => Some(BatchMode::BestEffort),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct BatchOptions {
+    /// "atomic" (the default) or "best-effort"
+    pub mode: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct StatementDay {
+    /// YYYY-MM-DD, today (UTC) when left out
+    pub date: Option<String>,
+}
+
+/// A payment by `customerId`, or a transfer from `customerId` to `idTo`.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct BatchLine {
+    /// "payment" or "transfer"
+    pub kind: String,
+    #[serde(rename = "customerId")]
+    pub customer_id: u16,
+    /// Transfers only.
+    #[serde(rename = "idTo")]
+    pub id_to: Option<u16>,
+    pub amount: f64,
+    #[serde(rename = "receiverCode", default)]
+    pub receiver_code: String,
+    #[serde(default)]
+    pub reference: String,
+    pub note: Option<String>,
+    #[serde(rename = "payeeId")]
+    pub payee_id: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BatchUpload {
+    #[schema(value_type = Vec<BatchLine>)]
+    pub lines: Vec<serde_json::Value>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct BatchLineResult {
+    /// Position in the upload, from 1.
+    pub line: u32,
+    pub kind: Option<String>,
+    /// "succeeded", "failed", or "skipped" when an atomic batch did not run
+    pub status: String,
+    /// What the single payment or transfer route would have answered; absent
+    /// for skipped lines.
+    pub code: Option<u16>,
+    pub message: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Batch {
+    pub id: Option<u32>,
+    pub created_at: Option<String>,
+    /// "json" or "csv"
+    pub format: String,
+    pub mode: String,
+    /// "completed", "partial" (best-effort with failed lines), "rejected"
+    /// (nothing made), or "running" while a best-effort batch is being made
+    pub status: String,
+    pub succeeded: u32,
+    pub failed: u32,
+    pub lines: Vec<BatchLineResult>,
+}
+
+/// A transfer or payment of one customer, as booked on a statement.
+pub enum Booking {
+    Transfer {
+        id: u16,
+        created_at: String,
+        /// Negative when the customer sent it.
+        amount: f64,
+        /// The other customer.
+        counterparty_id: u16,
+        counterparty_name: String,
+    },
+    Payment(Payment),
+}
@@ -0,0 +1,1965 @@
+use super::crud::{self, Table, JOURNALS};
+use super::models::{
+    self, AuditEvent, AuditFilter, Batch, Booking, ChainReport, Customer, CustomerEdit,
+    CustomerPatch, CustomerStatus, Discrepancy, JwtSecret, KycDocument, KycLevel, Payee, Payment,
+    Reconciliation, Reversal, Statement, StatementLine, Transfer, TransferHuman, Webhook,
+    WebhookDelivery, WebhookDispatch,
+};
+use super::storage::{BatchItem, Result, Storage, StorageError};
+use crate::{audit, stream, webhooks};
+use ::postgres::types::Type;
+use ::postgres::{Client, Config, GenericClient, NoTls, Row, Transaction};
+use chrono::{NaiveDate, Utc};
+use std::sync::mpsc;
+
+type Job = Box<dyn FnOnce(&mut Client) + Send>;
+
+/// PostgreSQL through a single connection owned by a worker thread. The
+/// `postgres` client drives its own runtime, which cannot be started from
+/// inside actix's, so calls are handed to the worker and waited on.
+///
+/// The tables mirror the SQLite schema in `crud`, and every operation writes
+/// the same audit events, hash chains and webhook outbox rows.
+pub struct PostgresStorage {
+    jobs: mpsc::Sender<Job>,
+}
+
+fn schema() -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {customers} (id SERIAL PRIMARY KEY, name TEXT NOT NULL, balance DOUBLE PRECISION NOT NULL DEFAULT 0, created_at TEXT NOT NULL, status TEXT NOT NULL DEFAULT 'active', date_of_birth TEXT NULL, national_id TEXT NULL, address TEXT NULL, email TEXT NULL, phone TEXT NULL, kyc_level TEXT NOT NULL DEFAULT 'unverified');
+        CREATE TABLE IF NOT EXISTS {transfers} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, from_id INTEGER NOT NULL, to_id INTEGER NOT NULL, amount DOUBLE PRECISION NOT NULL);
+        CREATE TABLE IF NOT EXISTS {payments} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, amount DOUBLE PRECISION NOT NULL, receiver_code TEXT NOT NULL, reference TEXT NOT NULL, note TEXT NULL);
+        CREATE TABLE IF NOT EXISTS {movements} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, kind TEXT NOT NULL, amount DOUBLE PRECISION NOT NULL);
+        ALTER TABLE {payments} ADD COLUMN IF NOT EXISTS payee_id INTEGER NULL;
+        CREATE TABLE IF NOT EXISTS {batches} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, format TEXT NOT NULL, mode TEXT NOT NULL, status TEXT NOT NULL);
+        CREATE TABLE IF NOT EXISTS {batch_lines} (id SERIAL PRIMARY KEY, batch_id INTEGER NOT NULL, line INTEGER NOT NULL, kind TEXT NULL, status TEXT NOT NULL, code INTEGER NULL, message TEXT NOT NULL);
+        CREATE INDEX IF NOT EXISTS {batch_lines}_batch_id ON {batch_lines} (batch_id);
+        CREATE TABLE IF NOT EXISTS {reversals} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, kind TEXT NOT NULL, original_id INTEGER NOT NULL, amount DOUBLE PRECISION NOT NULL);
+        CREATE TABLE IF NOT EXISTS {payees} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, name TEXT NOT NULL, receiver_code TEXT NOT NULL, nickname TEXT NULL, account_number TEXT NULL);
+        CREATE TABLE IF NOT EXISTS {status_changes} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, from_status TEXT NOT NULL, to_status TEXT NOT NULL, reason TEXT NOT NULL);
+        CREATE TABLE IF NOT EXISTS {kyc_documents} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, customer_id INTEGER NOT NULL, kind TEXT NOT NULL, number TEXT NOT NULL, issuing_country TEXT NOT NULL, expires_at TEXT NULL);
+        CREATE TABLE IF NOT EXISTS {audit_events} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, actor TEXT NOT NULL, action TEXT NOT NULL, entity TEXT NOT NULL, entity_id INTEGER NULL, before TEXT NULL, after TEXT NULL, ip TEXT NULL, request_id TEXT NULL, prev_hash TEXT NULL, hash TEXT NULL);
+        CREATE OR REPLACE FUNCTION {audit_events}_append_only() RETURNS trigger AS $$ BEGIN RAISE EXCEPTION '{audit_events} is append-only'; END; $$ LANGUAGE plpgsql;
+        CREATE OR REPLACE TRIGGER {audit_events}_append_only BEFORE UPDATE OR DELETE ON {audit_events} FOR EACH ROW EXECUTE FUNCTION {audit_events}_append_only();
+        ALTER TABLE {transfers} ADD COLUMN IF NOT EXISTS prev_hash TEXT NULL, ADD COLUMN IF NOT EXISTS hash TEXT NULL;
+        ALTER TABLE {payments} ADD COLUMN IF NOT EXISTS prev_hash TEXT NULL, ADD COLUMN IF NOT EXISTS hash TEXT NULL;
+        ALTER TABLE {movements} ADD COLUMN IF NOT EXISTS prev_hash TEXT NULL, ADD COLUMN IF NOT EXISTS hash TEXT NULL;
+        ALTER TABLE {reversals} ADD COLUMN IF NOT EXISTS prev_hash TEXT NULL, ADD COLUMN IF NOT EXISTS hash TEXT NULL;
+        CREATE TABLE IF NOT EXISTS {webhooks} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, url TEXT NOT NULL, secret TEXT NOT NULL, event_types TEXT NOT NULL);
+        CREATE TABLE IF NOT EXISTS {webhook_events} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, event_type TEXT NOT NULL, payload TEXT NOT NULL, fanned_out BOOLEAN NOT NULL DEFAULT FALSE);
+        CREATE TABLE IF NOT EXISTS {webhook_deliveries} (id SERIAL PRIMARY KEY, event_id INTEGER NOT NULL, webhook_id INTEGER NOT NULL, status TEXT NOT NULL, attempts INTEGER NOT NULL DEFAULT 0, next_attempt_at BIGINT NULL, last_status_code INTEGER NULL, last_error TEXT NULL, updated_at TEXT NOT NULL);
+        CREATE INDEX IF NOT EXISTS {webhook_events}_fanned_out ON {webhook_events} (fanned_out);
+        CREATE INDEX IF NOT EXISTS {webhook_deliveries}_due ON {webhook_deliveries} (status, next_attempt_at);
+        CREATE TABLE IF NOT EXISTS {jwt_secrets} (id SERIAL PRIMARY KEY, created_at TEXT NOT NULL, secret TEXT NOT NULL, retired_at BIGINT NULL);",
+        customers = Table::CUSTOMER.as_str(),
+        transfers = Table::TRANSFER.as_str(),
+        payments = Table::PAYMENT.as_str(),
+        movements = Table::MOVEMENT.as_str(),
+        batches = Table::BATCH.as_str(),
+        batch_lines = Table::BATCH_LINE.as_str(),
+        reversals = Table::REVERSAL.as_str(),
+        payees = Table::PAYEE.as_str(),
+        status_changes = Table::STATUS_CHANGE.as_str(),
+        kyc_documents = Table::KYC_DOCUMENT.as_str(),
+        audit_events = Table::AUDIT_EVENT.as_str(),
+        webhooks = Table::WEBHOOK.as_str(),
+        webhook_events = Table::WEBHOOK_EVENT.as_str(),
+        webhook_deliveries = Table::WEBHOOK_DELIVERY.as_str(),
+        jwt_secrets = Table::JWT_SECRET.as_str(),
+    )
+}
+
+fn open(config: &Config) -> Result<Client> {
+    let mut client = config.connect(NoTls)?;
+    client.batch_execute(&schema())?;
+    seed_jwt_secret(&mut client)?;
+    Ok(client)
+}
+
+/// Gives a database without signing keys a random one to start with.
+fn seed_jwt_secret(client: &mut Client) -> Result<()> {
+    let query = format!("SELECT COUNT(*) FROM {}", Table::JWT_SECRET.as_str());
+    if client.query_one(&query, &[])?.get::<_, i64>(0) > 0 {
+        return Ok(());
+    }
+
+    let secret = crud::new_jwt_secret().map_err(|e| StorageError::Backend(e.to_string()))?;
+    let query = format!(
+        "INSERT INTO {} (created_at, secret) VALUES ($1, $2)",
+        Table::JWT_SECRET.as_str()
+    );
+    client.execute(&query, &[&Utc::now().to_rfc2822(), &secret])?;
+    Ok(())
+}
+
+fn lost() -> StorageError {
+    StorageError::Backend("lost the connection to postgres".to_string())
+}
+
+/// Adds `amount` to the stored balance and returns the new one. The row is
+/// locked and checked again by the update itself, so a debit the balance no
+/// longer covers changes nothing.
+fn add_to_balance(tx: &mut Transaction, id: u16, amount: f64) -> Result<f64> {
+    let query = format!(
+        "UPDATE {} SET balance = balance + $1 WHERE id = $2 AND balance + $1 >= 0 RETURNING balance",
+        Table::CUSTOMER.as_str()
+    );
+    Ok(tx
+        .query_opt(&query, &[&amount, &i32::from(id)])?
+        .ok_or(StorageError::InsufficientFunds)?
+        .get(0))
+}
+
+/// The hash of the newest chained row of `table`, after locking the table so
+/// that no other writer appends in between.
+fn last_hash(tx: &mut Transaction, table: &str) -> Result<String> {
+    tx.batch_execute(&format!("LOCK TABLE {} IN EXCLUSIVE MODE", table))?;
+
+    let query = format!(
+        "SELECT hash FROM {} WHERE hash IS NOT NULL ORDER BY id DESC LIMIT 1",
+        table
+    );
+    Ok(tx
+        .query_opt(&query, &[])?
+        .map(|row| row.get(0))
+        .unwrap_or_else(|| audit::GENESIS_HASH.to_string()))
+}
+
+/// Appends to the audit chain within `tx`, like `crud::insert_audit_event`.
+fn insert_audit_event(
+    tx: &mut Transaction,
+    action: &str,
+    entity: &str,
+    entity_id: Option<u16>,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+) -> Result<()> {
+    let context = audit::current();
+    let prev_hash = last_hash(tx, Table::AUDIT_EVENT.as_str())?;
+
+    let fields = [
+        Some(Utc::now().to_rfc2822()),
+        Some(context.actor),
+        Some(action.to_string()),
+        Some(entity.to_string()),
+        entity_id.map(|x| x.to_string()),
+        before.map(|x| x.to_string()),
+        after.map(|x| x.to_string()),
+        context.ip,
+        context.request_id,
+    ];
+    let hash = audit::chain_hash(&prev_hash, &fields);
+
+    let query = format!(
+        "INSERT INTO {} (created_at, actor, action, entity, entity_id, before, after, ip, request_id, prev_hash, hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+        Table::AUDIT_EVENT.as_str()
+    );
+    let [created_at, actor, action, entity, _, before, after, ip, request_id] = fields;
+
+    tx.execute(
+        &query,
+        &[
+            &created_at,
+            &actor,
+            &action,
+            &entity,
+            &entity_id.map(i32::from),
+            &before,
+            &after,
+            &ip,
+            &request_id,
+            &prev_hash,
+            &hash,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Links row `id` of one of the `JOURNALS` to the row chained before it, like
+/// `crud::chain_journal_row`.
+fn chain_journal_row(tx: &mut Transaction, table: Table, id: i32) -> Result<()> {
+    let columns = table.journal_columns().unwrap();
+    let prev_hash = last_hash(tx, table.as_str())?;
+
+    let query = format!("SELECT {} FROM {} WHERE id = $1", columns, table.as_str());
+    let row = tx.query_one(&query, &[&id])?;
+    let hash = audit::chain_hash(&prev_hash, &journal_fields(&row, row.len()));
+
+    let query = format!(
+        "UPDATE {} SET prev_hash = $1, hash = $2 WHERE id = $3",
+        table.as_str()
+    );
+    tx.execute(&query, &[&prev_hash, &hash, &id])?;
+    Ok(())
+}
+
+/// The first `count` columns of a journal row, as the text its hash is
+/// computed over; numbers are written the way SQLite hands them out.
+fn journal_fields(row: &Row, count: usize) -> Vec<Option<String>> {
+    (0..count)
+        .map(|i| {
+            let kind = row.columns()[i].type_();
+            if kind == &Type::INT4 {
+                row.get::<_, Option<i32>>(i).map(|x| x.to_string())
+            } else if kind == &Type::FLOAT8 {
+                row.get::<_, Option<f64>>(i).map(|x| x.to_string())
+            } else {
+                row.get(i)
+            }
+        })
+        .collect()
+}
+
+/// Writes an event to the webhook outbox within the transaction that makes
+/// the change.
+fn enqueue_webhook_event(
+    tx: &mut Transaction,
+    event_type: models::WebhookEventType,
+    data: serde_json::Value,
+) -> Result<()> {
+    let query = format!(
+        "INSERT INTO {} (created_at, event_type, payload) VALUES ($1, $2, $3)",
+        Table::WEBHOOK_EVENT.as_str()
+    );
+    tx.execute(
+        &query,
+        &[
+            &Utc::now().to_rfc2822(),
+            &event_type.as_str(),
+            &data.to_string(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// `balance.low` is sent when a balance drops below the threshold, not on
+/// every change while it stays there.
+fn enqueue_balance_low(
+    tx: &mut Transaction,
+    id: u16,
+    previous: Option<f64>,
+    balance: f64,
+) -> Result<()> {
+    let threshold = webhooks::LOW_BALANCE_THRESHOLD;
+
+    if balance >= threshold || previous.is_some_and(|x| x < threshold) {
+        return Ok(());
+    }
+    enqueue_webhook_event(
+        tx,
+        models::WebhookEventType::BalanceLow,
+        serde_json::json!({ "customerId": id, "balance": balance, "threshold": threshold }),
+    )
+}
+
+fn insert_batch_report(tx: &mut Transaction, batch: &Batch) -> Result<u32> {
+    let query = format!(
+        "INSERT INTO {} (created_at, format, mode, status) VALUES ($1, $2, $3, $4) RETURNING id",
+        Table::BATCH.as_str()
+    );
+    let id: i32 = tx
+        .query_one(
+            &query,
+            &[&batch.created_at, &batch.format, &batch.mode, &batch.status],
+        )?
+        .get(0);
+    insert_batch_lines(tx, id, batch)?;
+    Ok(id as u32)
+}
+
+/// The line results of the batch, and the audit event that records it.
+fn insert_batch_lines(tx: &mut Transaction, id: i32, batch: &Batch) -> Result<()> {
+    let query = format!(
+        "INSERT INTO {} (batch_id, line, kind, status, code, message) VALUES ($1, $2, $3, $4, $5, $6)",
+        Table::BATCH_LINE.as_str()
+    );
+    for line in &batch.lines {
+        tx.execute(
+            &query,
+            &[
+                &id,
+                &(line.line as i32),
+                &line.kind,
+                &line.status,
+                &line.code.map(i32::from),
+                &line.message,
+            ],
+        )?;
+    }
+
+    insert_audit_event(
+        tx,
+        "create",
+        Table::BATCH.as_str(),
+        Some(id as u16),
+        None,
+        Some(serde_json::json!({
+            "format": batch.format,
+            "mode": batch.mode,
+            "status": batch.status,
+            "succeeded": batch.succeeded,
+            "failed": batch.failed,
+        })),
+    )
+}
+
+/// Moves `amount` and records the transfer within `tx`, and returns the live
+/// updates to publish once it commits.
+fn insert_transfer(
+    tx: &mut Transaction,
+    id_from: u16,
+    id_to: u16,
+    amount: f64,
+) -> Result<Vec<stream::Pending>> {
+    let balances = [
+        (id_from, add_to_balance(tx, id_from, -amount)?, -amount),
+        (id_to, add_to_balance(tx, id_to, amount)?, amount),
+    ];
+    for (id, balance, change) in balances {
+        insert_audit_event(
+            tx,
+            "update",
+            Table::CUSTOMER.as_str(),
+            Some(id),
+            Some(serde_json::json!({ "balance": balance - change })),
+            Some(serde_json::json!({ "balance": balance })),
+        )?;
+        enqueue_balance_low(tx, id, Some(balance - change), balance)?;
+    }
+
+    let query = format!(
+        "INSERT INTO {} (created_at, from_id, to_id, amount) VALUES ($1, $2, $3, $4) RETURNING id",
+        Table::TRANSFER.as_str()
+    );
+    let transfer_id: i32 = tx
+        .query_one(
+            &query,
+            &[
+                &Utc::now().to_rfc2822(),
+                &i32::from(id_from),
+                &i32::from(id_to),
+                &amount,
+            ],
+        )?
+        .get(0);
+    chain_journal_row(tx, Table::TRANSFER, transfer_id)?;
+
+    insert_audit_event(
+        tx,
+        "create",
+        Table::TRANSFER.as_str(),
+        Some(transfer_id as u16),
+        None,
+        Some(serde_json::json!({ "idFrom": id_from, "idTo": id_to, "amount": amount })),
+    )?;
+    let event = serde_json::json!({
+        "transferId": transfer_id,
+        "idFrom": id_from,
+        "idTo": id_to,
+        "amount": amount,
+    });
+    enqueue_webhook_event(tx, models::WebhookEventType::TransferCreated, event.clone())?;
+
+    let mut events: Vec<stream::Pending> = balances
+        .iter()
+        .map(|&(id, balance, _)| {
+            (
+                "balance.changed",
+                vec![id],
+                serde_json::json!({ "customerId": id, "balance": balance }),
+            )
+        })
+        .collect();
+    events.push(("transfer.created", vec![id_from, id_to], event));
+    Ok(events)
+}
+
+/// Records the payment and debits the customer within `tx`, and returns the
+/// live updates to publish once it commits.
+fn insert_payment(tx: &mut Transaction, payment: &Payment) -> Result<Vec<stream::Pending>> {
+    let id = payment.customer_id.ok_or(StorageError::NotFound)?;
+
+    let query = format!(
+        "INSERT INTO {} (created_at, customer_id, amount, receiver_code, reference, note, payee_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        Table::PAYMENT.as_str()
+    );
+    let payment_id: i32 = tx
+        .query_one(
+            &query,
+            &[
+                &payment.created_at,
+                &i32::from(id),
+                &payment.amount,
+                &payment.receiver_code,
+                &payment.reference,
+                &payment.note,
+                &payment.payee_id.map(i32::from),
+            ],
+        )?
+        .get(0);
+    chain_journal_row(tx, Table::PAYMENT, payment_id)?;
+
+    let balance = add_to_balance(tx, id, -payment.amount)?;
+
+    insert_audit_event(
+        tx,
+        "create",
+        Table::PAYMENT.as_str(),
+        Some(payment_id as u16),
+        None,
+        Some(serde_json::json!(payment)),
+    )?;
+    let event = serde_json::json!({
+        "paymentId": payment_id,
+        "customerId": id,
+        "amount": payment.amount,
+        "receiverCode": payment.receiver_code,
+        "reference": payment.reference,
+    });
+    enqueue_webhook_event(tx, models::WebhookEventType::PaymentCreated, event.clone())?;
+    enqueue_balance_low(tx, id, Some(balance + payment.amount), balance)?;
+
+    Ok(vec![
+        ("payment.created", vec![id], event),
+        (
+            "balance.changed",
+            vec![id],
+            serde_json::json!({ "customerId": id, "balance": balance }),
+        ),
+    ])
+}
+
+/// Records a reversal of `amount`, or whatever is left when `None`, against
+/// row `original_id` of `table`, and returns the amount reversed.
+fn insert_reversal(
+    tx: &mut Transaction,
+    table: Table,
+    original_id: u16,
+    original_amount: f64,
+    amount: Option<f64>,
+) -> Result<f64> {
+    let query = format!(
+        "SELECT COALESCE(SUM(amount), 0) FROM {} WHERE kind = $1 AND original_id = $2",
+        Table::REVERSAL.as_str()
+    );
+    let reversed: f64 = tx
+        .query_one(&query, &[&table.as_str(), &i32::from(original_id)])?
+        .get(0);
+
+    let remaining = original_amount - reversed;
+    let amount = amount.unwrap_or(remaining);
+
+    if remaining <= 0.0 || amount <= 0.0 || amount > remaining {
+        return Err(StorageError::InsufficientFunds);
+    }
+
+    let query = format!(
+        "INSERT INTO {} (created_at, kind, original_id, amount) VALUES ($1, $2, $3, $4) RETURNING id",
+        Table::REVERSAL.as_str()
+    );
+    let id: i32 = tx
+        .query_one(
+            &query,
+            &[
+                &Utc::now().to_rfc2822(),
+                &table.as_str(),
+                &i32::from(original_id),
+                &amount,
+            ],
+        )?
+        .get(0);
+    chain_journal_row(tx, Table::REVERSAL, id)?;
+    Ok(amount)
+}
+
+fn customer_columns() -> &'static str {
+    "id, name, balance, created_at, status, date_of_birth, national_id, address, email, phone, kyc_level"
+}
+
+fn customer_from_row(row: &Row) -> Customer {
+    Customer {
+        id: Some(row.get::<_, i32>(0) as u16),
+        name: row.get(1),
+        balance: row.get(2),
+        created_at: row.get(3),
+        status: row.get(4),
+        date_of_birth: row.get(5),
+        national_id: row.get(6),
+        address: row.get(7),
+        email: row.get(8),
+        phone: row.get(9),
+        kyc_level: row.get(10),
+    }
+}
+
+/// Reads the customer on `client`, so that a write can snapshot it for the
+/// audit log inside its own transaction.
+fn read_customer(client: &mut impl GenericClient, id: u16) -> Result<Customer> {
+    let query = format!(
+        "SELECT {} FROM {} WHERE id = $1",
+        customer_columns(),
+        Table::CUSTOMER.as_str()
+    );
+    client
+        .query_opt(&query, &[&i32::from(id)])?
+        .map(|row| customer_from_row(&row))
+        .ok_or(StorageError::NotFound)
+}
+
+fn transfer_from_row(row: &Row) -> TransferHuman {
+    TransferHuman {
+        id: row.get::<_, i32>(0) as u16,
+        created_at: row.get(1),
+        name_from: row.get(2),
+        name_to: row.get(3),
+        amount: row.get(4),
+    }
+}
+
+fn payment_from_row(row: &Row) -> Payment {
+    Payment {
+        id: Some(row.get::<_, i32>(0) as u16),
+        created_at: row.get(1),
+        customer_id: Some(row.get::<_, i32>(2) as u16),
+        amount: row.get(3),
+        receiver_code: row.get(4),
+        reference: row.get(5),
+        note: row.get(6),
+        payee_id: row.get::<_, Option<i32>>(7).map(|x| x as u16),
+    }
+}
+
+fn payments_by_customer(client: &mut impl GenericClient, id: u16) -> Result<Vec<Payment>> {
+    let query = format!(
+        "SELECT id, created_at, customer_id, amount, receiver_code, reference, note, payee_id FROM {} WHERE customer_id = $1 ORDER BY id",
+        Table::PAYMENT.as_str()
+    );
+    Ok(client
+        .query(&query, &[&i32::from(id)])?
+        .iter()
+        .map(payment_from_row)
+        .collect())
+}
+
+fn payee_from_row(row: &Row) -> Payee {
+    Payee {
+        id: Some(row.get::<_, i32>(0) as u16),
+        created_at: row.get(1),
+        customer_id: Some(row.get::<_, i32>(2) as u16),
+        name: row.get(3),
+        receiver_code: row.get(4),
+        nickname: row.get(5),
+        account_number: row.get(6),
+    }
+}
+
+fn read_payee(client: &mut impl GenericClient, customer_id: u16, id: u16) -> Result<Payee> {
+    let query = format!(
+        "SELECT id, created_at, customer_id, name, receiver_code, nickname, account_number FROM {} WHERE customer_id = $1 AND id = $2",
+        Table::PAYEE.as_str()
+    );
+    client
+        .query_opt(&query, &[&i32::from(customer_id), &i32::from(id)])?
+        .map(|row| payee_from_row(&row))
+        .ok_or(StorageError::NotFound)
+}
+
+fn webhook_from_row(row: &Row) -> Webhook {
+    let event_types: String = row.get(4);
+    Webhook {
+        id: Some(row.get::<_, i32>(0) as u16),
+        created_at: row.get(1),
+        url: row.get(2),
+        secret: row.get(3),
+        event_types: event_types.split(',').map(|x| x.to_string()).collect(),
+    }
+}
+
+fn read_webhooks(client: &mut impl GenericClient) -> Result<Vec<Webhook>> {
+    let query = format!(
+        "SELECT id, created_at, url, secret, event_types FROM {} ORDER BY id",
+        Table::WEBHOOK.as_str()
+    );
+    Ok(client
+        .query(&query, &[])?
+        .iter()
+        .map(webhook_from_row)
+        .collect())
+}
+
+fn read_webhook(client: &mut impl GenericClient, id: u16) -> Result<Webhook> {
+    let query = format!(
+        "SELECT id, created_at, url, secret, event_types FROM {} WHERE id = $1",
+        Table::WEBHOOK.as_str()
+    );
+    client
+        .query_opt(&query, &[&i32::from(id)])?
+        .map(|row| webhook_from_row(&row))
+        .ok_or(StorageError::NotFound)
+}
+
+/// A chained row: its id, `prev_hash`, `hash` and the fields the hash covers.
+type ChainRow = (u32, Option<String>, Option<String>, Vec<Option<String>>);
+
+/// Checks the rows of one chain, oldest first, and adds them to `report`,
+/// like `crud::verify_audit_chain`; false when the chain is broken. With
+/// `is_head`, `report.head` follows the last row that holds.
+fn verify_rows(table: &str, rows: Vec<ChainRow>, report: &mut ChainReport, is_head: bool) -> bool {
+    let mut expected_prev: Option<String> = None;
+
+    for (id, prev_hash, hash, fields) in rows {
+        let reason = match (prev_hash, hash) {
+            (None, None) if expected_prev.is_none() => {
+                report.unchained += 1;
+                continue;
+            }
+            (Some(prev_hash), Some(hash)) => {
+                let expected = expected_prev
+                    .take()
+                    .unwrap_or_else(|| audit::GENESIS_HASH.to_string());
+
+                if prev_hash != expected {
+                    Some("does not link to the previous row")
+                } else if audit::chain_hash(&prev_hash, &fields) != hash {
+                    Some("content does not match its hash")
+                } else {
+                    expected_prev = Some(hash);
+                    None
+                }
+            }
+            _ => Some("is missing its hash"),
+        };
+
+        if let Some(reason) = reason {
+            report.valid = false;
+            report.table = Some(table.to_string());
+            report.broken_at = Some(id);
+            report.reason = Some(reason.to_string());
+            return false;
+        }
+        report.checked += 1;
+        if is_head {
+            report.head = expected_prev.clone();
+        }
+    }
+    true
+}
+
+impl PostgresStorage {
+    /// Connects to `url`, either `postgres://...` or `host=... user=...`, and
+    /// creates the tables that are missing.
+    pub fn connect(url: &str) -> Result<PostgresStorage> {
+        PostgresStorage::with_config(url.parse()?)
+    }
+
+    pub fn with_config(config: Config) -> Result<PostgresStorage> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (ready, started) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut client = match open(&config) {
+                Ok(x) => x,
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ready.send(Ok(()));
+
+            // ends when the storage, and with it the sender, is dropped
+            for job in queue {
+                if client.is_closed() {
+                    match open(&config) {
+                        Ok(x) => client = x,
+                        Err(e) => {
+                            // dropping the job reports the lost connection
+                            tracing::warn!(error = %e, "could not reconnect to postgres");
+                            continue;
+                        }
+                    }
+                }
+                job(&mut client);
+            }
+        });
+
+        started.recv().map_err(|_| lost())??;
+        Ok(PostgresStorage { jobs })
+    }
+
+    fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Client) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (done, result) = mpsc::channel();
+        // events are published to the streams of the caller, and audit
+        // events are attributed to its request
+        let streams = stream::current().unwrap_or_default();
+        let context = audit::current();
+        self.jobs
+            .send(Box::new(move |client| {
+                let result = stream::STREAMS
+                    .sync_scope(streams, || audit::CONTEXT.sync_scope(context, || f(client)));
+                let _ = done.send(result);
+            }))
+            .map_err(|_| lost())?;
+        result.recv().map_err(|_| lost())?
+    }
+
+    /// Runs `f` in a transaction and publishes the live updates it returns
+    /// once it has committed.
+    fn write<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Transaction) -> Result<(T, Vec<stream::Pending>)> + Send + 'static,
+    ) -> Result<T> {
+        self.run(move |client| {
+            let mut tx = client.transaction()?;
+            let (value, events) = f(&mut tx)?;
+            tx.commit()?;
+            stream::publish_all(events);
+            Ok(value)
+        })
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn create_customer(&self, customer: &Customer) -> Result<()> {
+        let customer = customer.clone();
+
+        self.write(move |tx| {
+            let starting_balance = customer.balance.unwrap_or(0.0);
+
+            let query = format!(
+                "INSERT INTO {} (name, balance, created_at, date_of_birth, national_id, address, email, phone) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+                Table::CUSTOMER.as_str()
+            );
+            let id: i32 = tx
+                .query_one(
+                    &query,
+                    &[
+                        &customer.name,
+                        &starting_balance,
+                        &customer.created_at,
+                        &customer.date_of_birth,
+                        &customer.national_id,
+                        &customer.address,
+                        &customer.email,
+                        &customer.phone,
+                    ],
+                )?
+                .get(0);
+
+            if starting_balance != 0.0 {
+                let query = format!(
+                    "INSERT INTO {} (created_at, customer_id, kind, amount) VALUES ($1, $2, $3, $4) RETURNING id",
+                    Table::MOVEMENT.as_str()
+                );
+                let movement_id: i32 = tx
+                    .query_one(
+                        &query,
+                        &[&customer.created_at, &id, &"opening", &starting_balance],
+                    )?
+                    .get(0);
+                chain_journal_row(tx, Table::MOVEMENT, movement_id)?;
+            }
+
+            let after = read_customer(tx, id as u16)
+                .ok()
+                .map(|x| serde_json::json!(x));
+            insert_audit_event(
+                tx,
+                "create",
+                Table::CUSTOMER.as_str(),
+                Some(id as u16),
+                None,
+                after,
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn get_customer(&self, id: u16) -> Result<Customer> {
+        self.run(move |client| read_customer(client, id))
+    }
+
+    fn get_all_customers(&self) -> Result<Vec<Customer>> {
+        self.run(|client| {
+            let query = format!(
+                "SELECT {} FROM {} ORDER BY id",
+                customer_columns(),
+                Table::CUSTOMER.as_str()
+            );
+            Ok(client
+                .query(&query, &[])?
+                .iter()
+                .map(customer_from_row)
+                .collect())
+        })
+    }
+
+    fn edit_customer(&self, id: u16, customer: &CustomerEdit) -> Result<()> {
+        let customer = customer.clone();
+
+        self.write(move |tx| {
+            let before = read_customer(tx, id).ok().map(|x| serde_json::json!(x));
+
+            let query = format!(
+                "UPDATE {} SET name = $1 WHERE id = $2",
+                Table::CUSTOMER.as_str()
+            );
+            tx.execute(&query, &[&customer.name, &i32::from(id)])?;
+
+            let after = read_customer(tx, id).ok().map(|x| serde_json::json!(x));
+            insert_audit_event(
+                tx,
+                "update",
+                Table::CUSTOMER.as_str(),
+                Some(id),
+                before,
+                after,
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn patch_customer(&self, id: u16, customer: &CustomerPatch) -> Result<()> {
+        let customer = customer.clone();
+
+        self.write(move |tx| {
+            let before = read_customer(tx, id).ok().map(|x| serde_json::json!(x));
+
+            let query = format!(
+                "UPDATE {} SET name = COALESCE($1, name), date_of_birth = COALESCE($2, date_of_birth),
+                national_id = COALESCE($3, national_id), address = COALESCE($4, address),
+                email = COALESCE($5, email), phone = COALESCE($6, phone) WHERE id = $7",
+                Table::CUSTOMER.as_str()
+            );
+            tx.execute(
+                &query,
+                &[
+                    &customer.name,
+                    &customer.date_of_birth,
+                    &customer.national_id,
+                    &customer.address,
+                    &customer.email,
+                    &customer.phone,
+                    &i32::from(id),
+                ],
+            )?;
+
+            let after = read_customer(tx, id).ok().map(|x| serde_json::json!(x));
+            insert_audit_event(
+                tx,
+                "update",
+                Table::CUSTOMER.as_str(),
+                Some(id),
+                before,
+                after,
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn change_status(
+        &self,
+        customer: &Customer,
+        status: CustomerStatus,
+        reason: &str,
+        payout_receiver_code: Option<&str>,
+    ) -> Result<()> {
+        let customer = customer.clone();
+        let reason = reason.to_string();
+        let payout_receiver_code = payout_receiver_code.map(|x| x.to_string());
+
+        self.write(move |tx| {
+            let created_at = Utc::now().to_rfc2822();
+            let id = customer.id.ok_or(StorageError::NotFound)?;
+            let mut events: Vec<stream::Pending> = Vec::new();
+
+            if let Some(receiver_code) = &payout_receiver_code {
+                let balance = customer.balance.unwrap_or(0.0);
+                let query = format!(
+                    "INSERT INTO {} (created_at, customer_id, amount, receiver_code, reference, note) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                    Table::PAYMENT.as_str()
+                );
+                let payment_id: i32 = tx
+                    .query_one(
+                        &query,
+                        &[
+                            &created_at,
+                            &i32::from(id),
+                            &balance,
+                            receiver_code,
+                            &"account payout",
+                            &reason,
+                        ],
+                    )?
+                    .get(0);
+                chain_journal_row(tx, Table::PAYMENT, payment_id)?;
+
+                // the payout is the balance that was read; a change since then
+                // fails the close instead of being lost
+                if add_to_balance(tx, id, -balance)? != 0.0 {
+                    return Err(StorageError::InsufficientFunds);
+                }
+
+                events.push((
+                    "payment.created",
+                    vec![id],
+                    serde_json::json!({
+                        "paymentId": payment_id,
+                        "customerId": id,
+                        "amount": customer.balance,
+                        "receiverCode": receiver_code,
+                        "reference": "account payout",
+                    }),
+                ));
+                events.push((
+                    "balance.changed",
+                    vec![id],
+                    serde_json::json!({ "customerId": id, "balance": 0.0 }),
+                ));
+            }
+
+            let query = format!(
+                "UPDATE {} SET status = $1 WHERE id = $2",
+                Table::CUSTOMER.as_str()
+            );
+            tx.execute(&query, &[&status.as_str(), &i32::from(id)])?;
+
+            let query = format!(
+                "INSERT INTO {} (created_at, customer_id, from_status, to_status, reason) VALUES ($1, $2, $3, $4, $5)",
+                Table::STATUS_CHANGE.as_str()
+            );
+            tx.execute(
+                &query,
+                &[
+                    &created_at,
+                    &i32::from(id),
+                    &customer.status,
+                    &status.as_str(),
+                    &reason,
+                ],
+            )?;
+
+            insert_audit_event(
+                tx,
+                "update",
+                Table::CUSTOMER.as_str(),
+                Some(id),
+                Some(serde_json::json!({ "status": customer.status, "balance": customer.balance })),
+                Some(serde_json::json!({
+                    "status": status.as_str(),
+                    "reason": reason,
+                    "payoutReceiverCode": payout_receiver_code,
+                })),
+            )?;
+            Ok(((), events))
+        })
+    }
+
+    fn update_kyc_level(&self, id: u16, level: KycLevel) -> Result<()> {
+        self.write(move |tx| {
+            let before = read_customer(tx, id)
+                .ok()
+                .map(|x| serde_json::json!({ "kycLevel": x.kyc_level }));
+
+            let query = format!(
+                "UPDATE {} SET kyc_level = $1 WHERE id = $2",
+                Table::CUSTOMER.as_str()
+            );
+            tx.execute(&query, &[&level.as_str(), &i32::from(id)])?;
+
+            insert_audit_event(
+                tx,
+                "update",
+                Table::CUSTOMER.as_str(),
+                Some(id),
+                before,
+                Some(serde_json::json!({ "kycLevel": level.as_str() })),
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn create_kyc_document(&self, document: &KycDocument) -> Result<()> {
+        let document = document.clone();
+
+        self.write(move |tx| {
+            let query = format!(
+                "INSERT INTO {} (created_at, customer_id, kind, number, issuing_country, expires_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                Table::KYC_DOCUMENT.as_str()
+            );
+            let id: i32 = tx
+                .query_one(
+                    &query,
+                    &[
+                        &document.created_at,
+                        &document.customer_id.map(i32::from),
+                        &document.kind,
+                        &document.number,
+                        &document.issuing_country,
+                        &document.expires_at,
+                    ],
+                )?
+                .get(0);
+
+            insert_audit_event(
+                tx,
+                "create",
+                Table::KYC_DOCUMENT.as_str(),
+                Some(id as u16),
+                None,
+                Some(serde_json::json!(document)),
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn get_kyc_documents_by_customer(&self, customer_id: u16) -> Result<Vec<KycDocument>> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT id, created_at, customer_id, kind, number, issuing_country, expires_at FROM {} WHERE customer_id = $1 ORDER BY id",
+                Table::KYC_DOCUMENT.as_str()
+            );
+            Ok(client
+                .query(&query, &[&i32::from(customer_id)])?
+                .iter()
+                .map(|row| KycDocument {
+                    id: Some(row.get::<_, i32>(0) as u16),
+                    created_at: row.get(1),
+                    customer_id: Some(row.get::<_, i32>(2) as u16),
+                    kind: row.get(3),
+                    number: row.get(4),
+                    issuing_country: row.get(5),
+                    expires_at: row.get(6),
+                })
+                .collect())
+        })
+    }
+
+    fn create_movement(&self, id: u16, kind: &str, amount: f64) -> Result<()> {
+        let kind = kind.to_string();
+
+        self.write(move |tx| {
+            let query = format!(
+                "INSERT INTO {} (created_at, customer_id, kind, amount) VALUES ($1, $2, $3, $4) RETURNING id",
+                Table::MOVEMENT.as_str()
+            );
+            let movement_id: i32 = tx
+                .query_one(
+                    &query,
+                    &[&Utc::now().to_rfc2822(), &i32::from(id), &kind, &amount],
+                )?
+                .get(0);
+            chain_journal_row(tx, Table::MOVEMENT, movement_id)?;
+
+            let balance = add_to_balance(tx, id, amount)?;
+            let previous = balance - amount;
+
+            insert_audit_event(
+                tx,
+                "update",
+                Table::CUSTOMER.as_str(),
+                Some(id),
+                Some(serde_json::json!({ "balance": previous })),
+                Some(
+                    serde_json::json!({ "balance": balance, &kind: amount, "movementId": movement_id }),
+                ),
+            )?;
+            enqueue_balance_low(tx, id, Some(previous), balance)?;
+
+            let event = serde_json::json!({ "customerId": id, "balance": balance, kind: amount });
+            Ok(((), vec![("balance.changed", vec![id], event)]))
+        })
+    }
+
+    fn create_transfer(&self, id_from: u16, id_to: u16, amount: f64) -> Result<()> {
+        self.write(move |tx| Ok(((), insert_transfer(tx, id_from, id_to, amount)?)))
+    }
+
+    fn get_all_transfers(&self) -> Result<Vec<TransferHuman>> {
+        self.run(|client| {
+            let query = format!(
+                "SELECT t.id, t.created_at, from_c.name, to_c.name, t.amount
+                FROM {} AS t
+                JOIN {} AS from_c ON t.from_id = from_c.id
+                JOIN {} AS to_c ON t.to_id = to_c.id
+                ORDER BY t.id",
+                Table::TRANSFER.as_str(),
+                Table::CUSTOMER.as_str(),
+                Table::CUSTOMER.as_str()
+            );
+            Ok(client
+                .query(&query, &[])?
+                .iter()
+                .map(transfer_from_row)
+                .collect())
+        })
+    }
+
+    fn get_transfers_by_customer(&self, id: u16) -> Result<Vec<TransferHuman>> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT t.id, t.created_at, '', to_c.name, t.amount
+                FROM {} AS t
+                JOIN {} AS to_c ON t.to_id = to_c.id
+                WHERE t.from_id = $1
+                ORDER BY t.id",
+                Table::TRANSFER.as_str(),
+                Table::CUSTOMER.as_str()
+            );
+            Ok(client
+                .query(&query, &[&i32::from(id)])?
+                .iter()
+                .map(transfer_from_row)
+                .collect())
+        })
+    }
+
+    fn create_payment(&self, payment: &Payment) -> Result<()> {
+        let payment = payment.clone();
+
+        self.write(move |tx| Ok(((), insert_payment(tx, &payment)?)))
+    }
+
+    fn get_payments_by_customer(&self, id: u16) -> Result<Vec<Payment>> {
+        self.run(move |client| payments_by_customer(client, id))
+    }
+
+    fn create_batch(&self, items: &[BatchItem], report: &Batch) -> Result<u32> {
+        let items: Vec<BatchItem> = items.to_vec();
+        let report = report.clone();
+
+        self.write(move |tx| {
+            let mut events = Vec::new();
+            for (i, item) in items.iter().enumerate() {
+                let result = match item {
+                    BatchItem::Transfer {
+                        id_from,
+                        id_to,
+                        amount,
+                    } => insert_transfer(tx, *id_from, *id_to, *amount),
+                    BatchItem::Payment(payment) => insert_payment(tx, payment),
+                };
+                events.extend(result.map_err(|e| StorageError::InBatch(i, Box::new(e)))?);
+            }
+            Ok((insert_batch_report(tx, &report)?, events))
+        })
+    }
+
+    fn start_batch_report(&self, batch: &Batch) -> Result<u32> {
+        let batch = batch.clone();
+
+        self.run(move |client| {
+            let query = format!(
+                "INSERT INTO {} (created_at, format, mode, status) VALUES ($1, $2, $3, 'running') RETURNING id",
+                Table::BATCH.as_str()
+            );
+            let id: i32 = client
+                .query_one(&query, &[&batch.created_at, &batch.format, &batch.mode])?
+                .get(0);
+            Ok(id as u32)
+        })
+    }
+
+    fn finish_batch_report(&self, id: u32, batch: &Batch) -> Result<()> {
+        let batch = batch.clone();
+
+        self.write(move |tx| {
+            let query = format!(
+                "UPDATE {} SET status = $1 WHERE id = $2",
+                Table::BATCH.as_str()
+            );
+            tx.execute(&query, &[&batch.status, &(id as i32)])?;
+            insert_batch_lines(tx, id as i32, &batch)?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn create_batch_report(&self, batch: &Batch) -> Result<u32> {
+        let batch = batch.clone();
+
+        self.write(move |tx| Ok((insert_batch_report(tx, &batch)?, Vec::new())))
+    }
+
+    fn get_batch_report(&self, id: u32) -> Result<Batch> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT line, kind, status, code, message FROM {} WHERE batch_id = $1 ORDER BY line",
+                Table::BATCH_LINE.as_str()
+            );
+            let lines: Vec<models::BatchLineResult> = client
+                .query(&query, &[&(id as i32)])?
+                .iter()
+                .map(|row| models::BatchLineResult {
+                    line: row.get::<_, i32>(0) as u32,
+                    kind: row.get(1),
+                    status: row.get(2),
+                    code: row.get::<_, Option<i32>>(3).map(|x| x as u16),
+                    message: row.get(4),
+                })
+                .collect();
+
+            let query = format!(
+                "SELECT id, created_at, format, mode, status FROM {} WHERE id = $1",
+                Table::BATCH.as_str()
+            );
+            let row = client
+                .query_opt(&query, &[&(id as i32)])?
+                .ok_or(StorageError::NotFound)?;
+            Ok(Batch {
+                id: Some(row.get::<_, i32>(0) as u32),
+                created_at: row.get(1),
+                format: row.get(2),
+                mode: row.get(3),
+                status: row.get(4),
+                succeeded: lines.iter().filter(|x| x.status == "succeeded").count() as u32,
+                failed: lines.iter().filter(|x| x.status == "failed").count() as u32,
+                lines,
+            })
+        })
+    }
+
+    fn get_transfer(&self, id: u16) -> Result<Transfer> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT id, from_id, to_id, amount, created_at FROM {} WHERE id = $1",
+                Table::TRANSFER.as_str()
+            );
+            client
+                .query_opt(&query, &[&i32::from(id)])?
+                .map(|row| Transfer {
+                    id: Some(row.get::<_, i32>(0) as u16),
+                    id_from: row.get::<_, i32>(1) as u16,
+                    id_to: row.get::<_, i32>(2) as u16,
+                    amount: row.get(3),
+                    created_at: row.get(4),
+                })
+                .ok_or(StorageError::NotFound)
+        })
+    }
+
+    fn get_payment(&self, id: u16) -> Result<Payment> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT id, created_at, customer_id, amount, receiver_code, reference, note, payee_id FROM {} WHERE id = $1",
+                Table::PAYMENT.as_str()
+            );
+            client
+                .query_opt(&query, &[&i32::from(id)])?
+                .map(|row| payment_from_row(&row))
+                .ok_or(StorageError::NotFound)
+        })
+    }
+
+    fn reverse_transfer(&self, id: u16, amount: Option<f64>) -> Result<f64> {
+        let transfer = self.get_transfer(id)?;
+
+        self.write(move |tx| {
+            let amount = insert_reversal(tx, Table::TRANSFER, id, transfer.amount, amount)?;
+            let balances = [
+                (transfer.id_to, add_to_balance(tx, transfer.id_to, -amount)?),
+                (
+                    transfer.id_from,
+                    add_to_balance(tx, transfer.id_from, amount)?,
+                ),
+            ];
+
+            insert_audit_event(
+                tx,
+                "reverse",
+                Table::TRANSFER.as_str(),
+                Some(id),
+                None,
+                Some(serde_json::json!({ "amount": amount })),
+            )?;
+
+            let events = balances
+                .iter()
+                .map(|&(id, balance)| {
+                    (
+                        "balance.changed",
+                        vec![id],
+                        serde_json::json!({ "customerId": id, "balance": balance }),
+                    )
+                })
+                .collect();
+            Ok((amount, events))
+        })
+    }
+
+    fn reverse_payment(&self, id: u16, amount: Option<f64>) -> Result<f64> {
+        let payment = self.get_payment(id)?;
+
+        self.write(move |tx| {
+            let amount = insert_reversal(tx, Table::PAYMENT, id, payment.amount, amount)?;
+            let customer_id = payment.customer_id.ok_or(StorageError::NotFound)?;
+            let balance = add_to_balance(tx, customer_id, amount)?;
+
+            insert_audit_event(
+                tx,
+                "reverse",
+                Table::PAYMENT.as_str(),
+                Some(id),
+                None,
+                Some(serde_json::json!({ "amount": amount })),
+            )?;
+
+            let event = serde_json::json!({ "customerId": customer_id, "balance": balance });
+            Ok((amount, vec![("balance.changed", vec![customer_id], event)]))
+        })
+    }
+
+    fn get_reversals(&self) -> Result<Vec<Reversal>> {
+        self.run(|client| {
+            let query = format!(
+                "SELECT id, created_at, kind, original_id, amount FROM {} ORDER BY id",
+                Table::REVERSAL.as_str()
+            );
+            Ok(client
+                .query(&query, &[])?
+                .iter()
+                .map(|row| Reversal {
+                    id: Some(row.get::<_, i32>(0) as u16),
+                    created_at: row.get(1),
+                    kind: row.get(2),
+                    original_id: Some(row.get::<_, i32>(3) as u16),
+                    amount: row.get(4),
+                })
+                .collect())
+        })
+    }
+
+    fn create_payee(&self, payee: &Payee) -> Result<()> {
+        let payee = payee.clone();
+
+        self.write(move |tx| {
+            let query = format!(
+                "INSERT INTO {} (created_at, customer_id, name, receiver_code, nickname, account_number) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                Table::PAYEE.as_str()
+            );
+            let id: i32 = tx
+                .query_one(
+                    &query,
+                    &[
+                        &payee.created_at,
+                        &payee.customer_id.map(i32::from),
+                        &payee.name,
+                        &payee.receiver_code,
+                        &payee.nickname,
+                        &payee.account_number,
+                    ],
+                )?
+                .get(0);
+
+            insert_audit_event(
+                tx,
+                "create",
+                Table::PAYEE.as_str(),
+                Some(id as u16),
+                None,
+                Some(serde_json::json!(payee)),
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn get_payee(&self, customer_id: u16, id: u16) -> Result<Payee> {
+        self.run(move |client| read_payee(client, customer_id, id))
+    }
+
+    fn get_payees_by_customer(&self, customer_id: u16) -> Result<Vec<Payee>> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT id, created_at, customer_id, name, receiver_code, nickname, account_number FROM {} WHERE customer_id = $1 ORDER BY id",
+                Table::PAYEE.as_str()
+            );
+            Ok(client
+                .query(&query, &[&i32::from(customer_id)])?
+                .iter()
+                .map(payee_from_row)
+                .collect())
+        })
+    }
+
+    fn edit_payee(&self, customer_id: u16, id: u16, payee: &Payee) -> Result<()> {
+        let payee = payee.clone();
+
+        self.write(move |tx| {
+            let before = read_payee(tx, customer_id, id)
+                .ok()
+                .map(|x| serde_json::json!(x));
+
+            let query = format!(
+                "UPDATE {} SET name = $1, nickname = $2,
+                created_at = CASE WHEN receiver_code = $3 AND account_number IS NOT DISTINCT FROM $7 THEN created_at ELSE $4 END,
+                receiver_code = $3, account_number = $7
+                WHERE customer_id = $5 AND id = $6",
+                Table::PAYEE.as_str()
+            );
+            tx.execute(
+                &query,
+                &[
+                    &payee.name,
+                    &payee.nickname,
+                    &payee.receiver_code,
+                    &Utc::now().to_rfc2822(),
+                    &i32::from(customer_id),
+                    &i32::from(id),
+                    &payee.account_number,
+                ],
+            )?;
+
+            let after = read_payee(tx, customer_id, id)
+                .ok()
+                .map(|x| serde_json::json!(x));
+            insert_audit_event(
+                tx,
+                "update",
+                Table::PAYEE.as_str(),
+                Some(id),
+                before,
+                after,
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn delete_payee(&self, customer_id: u16, id: u16) -> Result<()> {
+        self.write(move |tx| {
+            let before = read_payee(tx, customer_id, id)
+                .ok()
+                .map(|x| serde_json::json!(x));
+
+            let query = format!(
+                "DELETE FROM {} WHERE customer_id = $1 AND id = $2",
+                Table::PAYEE.as_str()
+            );
+            tx.execute(&query, &[&i32::from(customer_id), &i32::from(id)])?;
+
+            insert_audit_event(tx, "delete", Table::PAYEE.as_str(), Some(id), before, None)?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn get_bookings(&self, id: u16) -> Result<Vec<Booking>> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT t.id, t.created_at, -t.amount, c.id, c.name FROM {transfers} AS t JOIN {customers} AS c ON c.id = t.to_id WHERE t.from_id = $1
+                UNION ALL SELECT t.id, t.created_at, t.amount, c.id, c.name FROM {transfers} AS t JOIN {customers} AS c ON c.id = t.from_id WHERE t.to_id = $1",
+                transfers = Table::TRANSFER.as_str(),
+                customers = Table::CUSTOMER.as_str(),
+            );
+            let mut bookings: Vec<Booking> = client
+                .query(&query, &[&i32::from(id)])?
+                .iter()
+                .map(|row| Booking::Transfer {
+                    id: row.get::<_, i32>(0) as u16,
+                    created_at: row.get(1),
+                    amount: row.get(2),
+                    counterparty_id: row.get::<_, i32>(3) as u16,
+                    counterparty_name: row.get(4),
+                })
+                .collect();
+
+            bookings.extend(
+                payments_by_customer(client, id)?
+                    .into_iter()
+                    .map(Booking::Payment),
+            );
+            Ok(bookings)
+        })
+    }
+
+    fn get_statement(
+        &self,
+        id: u16,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Statement> {
+        self.run(move |client| {
+            let customer = read_customer(client, id)?;
+            let lines = client
+                .query(&crud::statement_sql(), &[&i32::from(id)])?
+                .iter()
+                .map(|row| StatementLine {
+                    created_at: row.get(0),
+                    kind: row.get(1),
+                    description: row.get(2),
+                    amount: row.get(3),
+                    balance: 0.0,
+                })
+                .collect();
+            Ok(crud::fold_statement(id, customer.name, lines, from, to))
+        })
+    }
+
+    fn create_audit_event(
+        &self,
+        action: &str,
+        entity: &str,
+        entity_id: Option<u16>,
+        before: Option<serde_json::Value>,
+        after: Option<serde_json::Value>,
+    ) -> Result<()> {
+        let action = action.to_string();
+        let entity = entity.to_string();
+
+        self.write(move |tx| {
+            insert_audit_event(tx, &action, &entity, entity_id, before, after)?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn get_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>> {
+        let filter = filter.clone();
+
+        self.run(move |client| {
+            let query = format!(
+                "SELECT id, created_at, actor, action, entity, entity_id, before, after, ip, request_id FROM {}
+                WHERE ($1::TEXT IS NULL OR actor = $1) AND ($2::TEXT IS NULL OR action = $2)
+                AND ($3::TEXT IS NULL OR entity = $3) AND ($4::INTEGER IS NULL OR entity_id = $4)
+                AND ($5::TEXT IS NULL OR request_id = $5) AND ($6::INTEGER IS NULL OR id > $6)
+                ORDER BY id LIMIT $7",
+                Table::AUDIT_EVENT.as_str()
+            );
+            Ok(client
+                .query(
+                    &query,
+                    &[
+                        &filter.actor,
+                        &filter.action,
+                        &filter.entity,
+                        &filter.entity_id.map(i32::from),
+                        &filter.request_id,
+                        &filter.after_id.map(|x| x as i32),
+                        &i64::from(filter.limit.unwrap_or(100)),
+                    ],
+                )?
+                .iter()
+                .map(|row| {
+                    let before: Option<String> = row.get(6);
+                    let after: Option<String> = row.get(7);
+                    AuditEvent {
+                        id: row.get::<_, i32>(0) as u32,
+                        created_at: row.get(1),
+                        actor: row.get(2),
+                        action: row.get(3),
+                        entity: row.get(4),
+                        entity_id: row.get::<_, Option<i32>>(5).map(|x| x as u16),
+                        before: before.and_then(|x| serde_json::from_str(&x).ok()),
+                        after: after.and_then(|x| serde_json::from_str(&x).ok()),
+                        ip: row.get(8),
+                        request_id: row.get(9),
+                    }
+                })
+                .collect())
+        })
+    }
+
+    fn verify_audit_chain(&self) -> Result<ChainReport> {
+        self.run(|client| {
+            let mut report = ChainReport {
+                valid: true,
+                checked: 0,
+                unchained: 0,
+                head: None,
+                table: None,
+                broken_at: None,
+                reason: None,
+            };
+
+            let query = format!(
+                "SELECT id, created_at, actor, action, entity, entity_id, before, after, ip, request_id, prev_hash, hash FROM {} ORDER BY id",
+                Table::AUDIT_EVENT.as_str()
+            );
+            let rows = client
+                .query(&query, &[])?
+                .iter()
+                .map(|row| {
+                    let fields = journal_fields(row, 10).split_off(1);
+                    (
+                        row.get::<_, i32>(0) as u32,
+                        row.get(10),
+                        row.get(11),
+                        fields,
+                    )
+                })
+                .collect();
+            if !verify_rows(Table::AUDIT_EVENT.as_str(), rows, &mut report, true) {
+                return Ok(report);
+            }
+
+            for table in JOURNALS {
+                let columns = table.journal_columns().unwrap();
+                let count = columns.split(", ").count();
+                let query = format!(
+                    "SELECT {}, prev_hash, hash FROM {} ORDER BY id",
+                    columns,
+                    table.as_str()
+                );
+                let rows = client
+                    .query(&query, &[])?
+                    .iter()
+                    .map(|row| {
+                        (
+                            row.get::<_, i32>(0) as u32,
+                            row.get(count),
+                            row.get(count + 1),
+                            journal_fields(row, count),
+                        )
+                    })
+                    .collect();
+                if !verify_rows(table.as_str(), rows, &mut report, false) {
+                    break;
+                }
+            }
+            Ok(report)
+        })
+    }
+
+    fn reconcile(&self, correct: bool) -> Result<Reconciliation> {
+        self.write(move |tx| {
+            // no balance may change between reading and correcting it
+            tx.batch_execute(&format!(
+                "LOCK TABLE {} IN EXCLUSIVE MODE",
+                Table::CUSTOMER.as_str()
+            ))?;
+
+            let query = format!(
+                "SELECT c.id, c.balance, {} FROM {} AS c ORDER BY c.id",
+                crud::expected_balance_sql(),
+                Table::CUSTOMER.as_str()
+            );
+            let balances: Vec<(u16, f64, f64)> = tx
+                .query(&query, &[])?
+                .iter()
+                .map(|row| (row.get::<_, i32>(0) as u16, row.get(1), row.get(2)))
+                .collect();
+
+            let mut report = Reconciliation {
+                total_balance: balances.iter().map(|x| x.1).sum(),
+                expected_total: balances.iter().map(|x| x.2).sum(),
+                invariant_holds: true,
+                discrepancies: Vec::new(),
+                carried_over: Vec::new(),
+                corrected: correct,
+            };
+            report.invariant_holds = (report.total_balance - report.expected_total).abs() < 0.005;
+            let mut events: Vec<stream::Pending> = Vec::new();
+
+            let query = format!(
+                "SELECT customer_id, SUM(amount) FROM {} WHERE kind = 'carried_over' GROUP BY customer_id ORDER BY customer_id",
+                Table::MOVEMENT.as_str()
+            );
+            report.carried_over = tx
+                .query(&query, &[])?
+                .iter()
+                .map(|row| models::CarriedOver {
+                    customer_id: row.get::<_, i32>(0) as u16,
+                    amount: row.get(1),
+                })
+                .collect();
+
+            for (id, balance, expected) in balances {
+                if (balance - expected).abs() < 0.005 {
+                    continue;
+                }
+
+                if correct {
+                    let created_at = Utc::now().to_rfc2822();
+                    let query = format!(
+                        "INSERT INTO {} (created_at, customer_id, kind, amount) VALUES ($1, $2, $3, $4) RETURNING id",
+                        Table::MOVEMENT.as_str()
+                    );
+                    let mut movement_ids = Vec::new();
+                    for (kind, amount) in [
+                        ("discrepancy", balance - expected),
+                        ("correction", expected - balance),
+                    ] {
+                        let movement_id: i32 = tx
+                            .query_one(&query, &[&created_at, &i32::from(id), &kind, &amount])?
+                            .get(0);
+                        chain_journal_row(tx, Table::MOVEMENT, movement_id)?;
+                        movement_ids.push(movement_id);
+                    }
+                    let corrected = add_to_balance(tx, id, expected - balance)?;
+
+                    insert_audit_event(
+                        tx,
+                        "reconcile",
+                        Table::CUSTOMER.as_str(),
+                        Some(id),
+                        Some(serde_json::json!({ "balance": balance })),
+                        Some(serde_json::json!({ "balance": corrected, "movementIds": movement_ids })),
+                    )?;
+                    enqueue_balance_low(tx, id, Some(balance), corrected)?;
+                    events.push((
+                        "balance.changed",
+                        vec![id],
+                        serde_json::json!({ "customerId": id, "balance": corrected }),
+                    ));
+                }
+
+                report.discrepancies.push(Discrepancy {
+                    customer_id: id,
+                    balance,
+                    expected,
+                    difference: balance - expected,
+                });
+            }
+            Ok((report, events))
+        })
+    }
+
+    fn create_webhook(&self, webhook: &Webhook) -> Result<u16> {
+        let webhook = webhook.clone();
+
+        self.write(move |tx| {
+            let query = format!(
+                "INSERT INTO {} (created_at, url, secret, event_types) VALUES ($1, $2, $3, $4) RETURNING id",
+                Table::WEBHOOK.as_str()
+            );
+            let id: i32 = tx
+                .query_one(
+                    &query,
+                    &[
+                        &webhook.created_at,
+                        &webhook.url,
+                        &webhook.secret,
+                        &webhook.event_types.join(","),
+                    ],
+                )?
+                .get(0);
+
+            insert_audit_event(
+                tx,
+                "create",
+                Table::WEBHOOK.as_str(),
+                Some(id as u16),
+                None,
+                Some(serde_json::json!(webhook)),
+            )?;
+            Ok((id as u16, Vec::new()))
+        })
+    }
+
+    fn get_webhook(&self, id: u16) -> Result<Webhook> {
+        self.run(move |client| read_webhook(client, id))
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        self.run(read_webhooks)
+    }
+
+    fn delete_webhook(&self, id: u16) -> Result<()> {
+        self.write(move |tx| {
+            let before = read_webhook(tx, id).ok().map(|x| serde_json::json!(x));
+
+            let query = format!(
+                "DELETE FROM {} WHERE webhook_id = $1",
+                Table::WEBHOOK_DELIVERY.as_str()
+            );
+            tx.execute(&query, &[&i32::from(id)])?;
+
+            let query = format!("DELETE FROM {} WHERE id = $1", Table::WEBHOOK.as_str());
+            if tx.execute(&query, &[&i32::from(id)])? == 0 {
+                return Err(StorageError::NotFound);
+            }
+
+            insert_audit_event(
+                tx,
+                "delete",
+                Table::WEBHOOK.as_str(),
+                Some(id),
+                before,
+                None,
+            )?;
+            Ok(((), Vec::new()))
+        })
+    }
+
+    fn get_webhook_deliveries(&self, webhook_id: u16) -> Result<Vec<WebhookDelivery>> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT d.id, d.event_id, d.webhook_id, e.event_type, d.status, d.attempts, d.next_attempt_at, d.last_status_code, d.last_error, d.updated_at
+                FROM {} AS d JOIN {} AS e ON e.id = d.event_id WHERE d.webhook_id = $1 ORDER BY d.id DESC",
+                Table::WEBHOOK_DELIVERY.as_str(),
+                Table::WEBHOOK_EVENT.as_str()
+            );
+            Ok(client
+                .query(&query, &[&i32::from(webhook_id)])?
+                .iter()
+                .map(|row| WebhookDelivery {
+                    id: row.get::<_, i32>(0) as u32,
+                    event_id: row.get::<_, i32>(1) as u32,
+                    webhook_id: row.get::<_, i32>(2) as u16,
+                    event_type: row.get(3),
+                    status: row.get(4),
+                    attempts: row.get::<_, i32>(5) as u32,
+                    next_attempt_at: row.get(6),
+                    last_status_code: row.get::<_, Option<i32>>(7).map(|x| x as u16),
+                    last_error: row.get(8),
+                    updated_at: row.get(9),
+                })
+                .collect())
+        })
+    }
+
+    fn fan_out_webhook_events(&self) -> Result<usize> {
+        self.write(|tx| {
+            let webhooks = read_webhooks(tx)?;
+            let query = format!(
+                "SELECT id, event_type FROM {} WHERE NOT fanned_out ORDER BY id FOR UPDATE",
+                Table::WEBHOOK_EVENT.as_str()
+            );
+            let events: Vec<(i32, String)> = tx
+                .query(&query, &[])?
+                .iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+
+            let now = Utc::now();
+            for (event_id, event_type) in &events {
+                for webhook in webhooks
+                    .iter()
+                    .filter(|x| x.event_types.contains(event_type))
+                {
+                    let query = format!(
+                        "INSERT INTO {} (event_id, webhook_id, status, next_attempt_at, updated_at) VALUES ($1, $2, 'pending', $3, $4)",
+                        Table::WEBHOOK_DELIVERY.as_str()
+                    );
+                    tx.execute(
+                        &query,
+                        &[
+                            event_id,
+                            &webhook.id.map(i32::from),
+                            &now.timestamp(),
+                            &now.to_rfc2822(),
+                        ],
+                    )?;
+                }
+                let query = format!(
+                    "UPDATE {} SET fanned_out = TRUE WHERE id = $1",
+                    Table::WEBHOOK_EVENT.as_str()
+                );
+                tx.execute(&query, &[event_id])?;
+            }
+            Ok((events.len(), Vec::new()))
+        })
+    }
+
+    fn get_due_webhook_deliveries(&self, now: i64, limit: u32) -> Result<Vec<WebhookDispatch>> {
+        self.run(move |client| {
+            let query = format!(
+                "SELECT d.id, d.attempts, w.url, w.secret, e.id, e.event_type, e.created_at, e.payload
+                FROM {} AS d JOIN {} AS w ON w.id = d.webhook_id JOIN {} AS e ON e.id = d.event_id
+                WHERE d.status = 'pending' AND d.next_attempt_at <= $1 ORDER BY d.next_attempt_at, d.id LIMIT $2",
+                Table::WEBHOOK_DELIVERY.as_str(),
+                Table::WEBHOOK.as_str(),
+                Table::WEBHOOK_EVENT.as_str()
+            );
+            Ok(client
+                .query(&query, &[&now, &i64::from(limit)])?
+                .iter()
+                .map(|row| WebhookDispatch {
+                    id: row.get::<_, i32>(0) as u32,
+                    attempts: row.get::<_, i32>(1) as u32,
+                    url: row.get(2),
+                    secret: row.get(3),
+                    event_id: row.get::<_, i32>(4) as u32,
+                    event_type: row.get(5),
+                    event_created_at: row.get(6),
+                    payload: row.get(7),
+                })
+                .collect())
+        })
+    }
+
+    fn record_webhook_attempt(
+        &self,
+        id: u32,
+        status: &str,
+        next_attempt_at: Option<i64>,
+        status_code: Option<u16>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        let status = status.to_string();
+        let error = error.map(|x| x.to_string());
+
+        self.run(move |client| {
+            let query = format!(
+                "UPDATE {} SET status = $1, attempts = attempts + 1, next_attempt_at = $2, last_status_code = $3, last_error = $4, updated_at = $5 WHERE id = $6",
+                Table::WEBHOOK_DELIVERY.as_str()
+            );
+            client.execute(
+                &query,
+                &[
+                    &status,
+                    &next_attempt_at,
+                    &status_code.map(i32::from),
+                    &error,
+                    &Utc::now().to_rfc2822(),
+                    &(id as i32),
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn get_jwt_secrets(&self) -> Result<Vec<JwtSecret>> {
+        self.run(|client| {
+            let query = format!(
+                "SELECT id, created_at, secret, retired_at FROM {} ORDER BY id DESC",
+                Table::JWT_SECRET.as_str()
+            );
+            Ok(client
+                .query(&query, &[])?
+                .iter()
+                .map(|row| JwtSecret {
+                    id: row.get::<_, i32>(0) as u32,
+                    created_at: row.get(1),
+                    secret: row.get(2),
+                    retired_at: row.get(3),
+                })
+                .collect())
+        })
+    }
+
+    fn rotate_jwt_secret(&self, secret: &str) -> Result<u32> {
+        let secret = secret.to_string();
+
+        self.write(move |tx| {
+            let now = Utc::now();
+
+            let query = format!(
+                "UPDATE {} SET retired_at = $1 WHERE retired_at IS NULL",
+                Table::JWT_SECRET.as_str()
+            );
+            tx.execute(&query, &[&now.timestamp()])?;
+
+            let query = format!(
+                "INSERT INTO {} (created_at, secret) VALUES ($1, $2) RETURNING id",
+                Table::JWT_SECRET.as_str()
+            );
+            let id: i32 = tx.query_one(&query, &[&now.to_rfc2822(), &secret])?.get(0);
+
+            insert_audit_event(
+                tx,
+                "rotate",
+                Table::JWT_SECRET.as_str(),
+                Some(id as u16),
+                None,
+                None,
+            )?;
+            Ok((id as u32, Vec::new()))
+        })
+    }
+
+    fn check(&self) -> Result<String> {
+        self.run(|client| {
+            let version: String = client.query_one("SHOW server_version", &[])?.get(0);
+            Ok(format!("PostgreSQL {}", version))
+        })
+    }
+}
@@ -0,0 +1,525 @@
+use super::crud;
+use super::models::{
+    AuditEvent, AuditFilter, Batch, Booking, ChainReport, Customer, CustomerEdit, CustomerPatch,
+    CustomerStatus, JwtSecret, KycDocument, KycLevel, Payee, Payment, Reconciliation, Reversal,
+    Statement, Transfer, TransferHuman, Webhook, WebhookDelivery, WebhookDispatch,
+};
+use super::postgres::PostgresStorage;
+use crate::stream::Streams;
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    /// The balance does not cover a debit, or the customer no longer exists.
+    InsufficientFunds,
+    /// The item at this index of a batch failed, and with it the batch.
+    InBatch(usize, Box<StorageError>),
+    Backend(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "not found"),
+            StorageError::InsufficientFunds => write!(f, "not enough balance"),
+            StorageError::InBatch(i, e) => write!(f, "item {}: {}", i, e),
+            StorageError::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> StorageError {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => StorageError::NotFound,
+            rusqlite::Error::StatementChangedRows(0) => StorageError::InsufficientFunds,
+            e => StorageError::Backend(e.to_string()),
+        }
+    }
+}
+
+impl From<::postgres::Error> for StorageError {
+    fn from(e: ::postgres::Error) -> StorageError {
+        StorageError::Backend(e.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+/// A transfer or payment made as part of a batch.
+#[derive(Clone)]
+pub enum BatchItem {
+    Transfer {
+        id_from: u16,
+        id_to: u16,
+        amount: f64,
+    },
+    Payment(Payment),
+}
+
+/// Everything the routes and background jobs keep. Every backend keeps
+/// balances and the transactions behind them in step, writes the audit event
+/// and webhook events of a change in the same transaction as the change, and
+/// publishes the same live updates.
+///
+/// Balances are only ever changed relative to what is stored, and a debit
+/// fails with `InsufficientFunds` instead of overdrawing, so that a balance
+/// read by a concurrent request cannot be written back.
+pub trait Storage: Send + Sync {
+    fn create_customer(&self, customer: &Customer) -> Result<()>;
+    fn get_customer(&self, id: u16) -> Result<Customer>;
+    fn get_all_customers(&self) -> Result<Vec<Customer>>;
+    fn edit_customer(&self, id: u16, customer: &CustomerEdit) -> Result<()>;
+    /// Only the fields present in `customer` are changed.
+    fn patch_customer(&self, id: u16, customer: &CustomerPatch) -> Result<()>;
+    /// Moves the customer to `status` and records why. With
+    /// `payout_receiver_code`, the balance read into `customer` is paid out to
+    /// it first, and a balance changed since then fails with
+    /// `InsufficientFunds`.
+    fn change_status(
+        &self,
+        customer: &Customer,
+        status: CustomerStatus,
+        reason: &str,
+        payout_receiver_code: Option<&str>,
+    ) -> Result<()>;
+    fn update_kyc_level(&self, id: u16, level: KycLevel) -> Result<()>;
+    fn create_kyc_document(&self, document: &KycDocument) -> Result<()>;
+    fn get_kyc_documents_by_customer(&self, customer_id: u16) -> Result<Vec<KycDocument>>;
+    /// Deposits (positive `amount`) and withdrawals (negative `amount`).
+    fn create_movement(&self, id: u16, kind: &str, amount: f64) -> Result<()>;
+    /// Moves `amount` between the customers and records the transfer, all or
+    /// nothing.
+    fn create_transfer(&self, id_from: u16, id_to: u16, amount: f64) -> Result<()>;
+    fn get_all_transfers(&self) -> Result<Vec<TransferHuman>>;
+    /// Transfers sent by the customer; `name_from` is left empty.
+    fn get_transfers_by_customer(&self, id: u16) -> Result<Vec<TransferHuman>>;
+    /// Records the payment and takes its amount from the customer's balance.
+    fn create_payment(&self, payment: &Payment) -> Result<()>;
+    fn get_payments_by_customer(&self, id: u16) -> Result<Vec<Payment>>;
+    /// Makes every item and keeps `report`, the outcome of the batch, in one
+    /// transaction, or none of them when one fails. Returns the report's id.
+    fn create_batch(&self, items: &[BatchItem], report: &Batch) -> Result<u32>;
+    /// Keeps a batch as "running" before its lines are made one by one.
+    fn start_batch_report(&self, batch: &Batch) -> Result<u32>;
+    /// Saves the outcome of a batch started with `start_batch_report`.
+    fn finish_batch_report(&self, id: u32, batch: &Batch) -> Result<()>;
+    /// Keeps the report of a batch that made nothing.
+    fn create_batch_report(&self, batch: &Batch) -> Result<u32>;
+    fn get_batch_report(&self, id: u32) -> Result<Batch>;
+
+    fn get_transfer(&self, id: u16) -> Result<Transfer>;
+    fn get_payment(&self, id: u16) -> Result<Payment>;
+    /// Gives back `amount` of the transfer, or whatever is left of it when
+    /// `None`, and returns the amount. Fails with `InsufficientFunds` when
+    /// less is left or the recipient cannot cover it.
+    fn reverse_transfer(&self, id: u16, amount: Option<f64>) -> Result<f64>;
+    /// Like `reverse_transfer`, crediting the customer who paid.
+    fn reverse_payment(&self, id: u16, amount: Option<f64>) -> Result<f64>;
+    fn get_reversals(&self) -> Result<Vec<Reversal>>;
+
+    fn create_payee(&self, payee: &Payee) -> Result<()>;
+    fn get_payee(&self, customer_id: u16, id: u16) -> Result<Payee>;
+    fn get_payees_by_customer(&self, customer_id: u16) -> Result<Vec<Payee>>;
+    /// Changing the receiver code or account number restarts the payee's
+    /// cooling-off period.
+    fn edit_payee(&self, customer_id: u16, id: u16, payee: &Payee) -> Result<()>;
+    fn delete_payee(&self, customer_id: u16, id: u16) -> Result<()>;
+
+    /// Every transfer sent or received by the customer and every payment they
+    /// made, in no particular order.
+    fn get_bookings(&self, id: u16) -> Result<Vec<Booking>>;
+    /// Every transaction that moved the customer's balance, oldest first.
+    /// Lines before `from` are folded into the opening balance and lines
+    /// after `to` are left out.
+    fn get_statement(
+        &self,
+        id: u16,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Statement>;
+
+    /// Records an event that is not tied to a table, such as an HTTP request.
+    fn create_audit_event(
+        &self,
+        action: &str,
+        entity: &str,
+        entity_id: Option<u16>,
+        before: Option<serde_json::Value>,
+        after: Option<serde_json::Value>,
+    ) -> Result<()>;
+    fn get_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>>;
+    /// Walks the audit chain and the chain of every journal table, and stops
+    /// at the first row that does not hold.
+    fn verify_audit_chain(&self) -> Result<ChainReport>;
+    /// Compares every balance with the recorded transactions and, with
+    /// `correct`, books the difference back out of the balance.
+    fn reconcile(&self, correct: bool) -> Result<Reconciliation>;
+
+    fn create_webhook(&self, webhook: &Webhook) -> Result<u16>;
+    fn get_webhook(&self, id: u16) -> Result<Webhook>;
+    fn get_webhooks(&self) -> Result<Vec<Webhook>>;
+    /// Removes the subscription together with its deliveries.
+    fn delete_webhook(&self, id: u16) -> Result<()>;
+    fn get_webhook_deliveries(&self, webhook_id: u16) -> Result<Vec<WebhookDelivery>>;
+    /// Creates a pending delivery for every subscription to each new outbox
+    /// event, and returns the number of events.
+    fn fan_out_webhook_events(&self) -> Result<usize>;
+    /// Pending deliveries whose next attempt is at or before `now` (Unix time).
+    fn get_due_webhook_deliveries(&self, now: i64, limit: u32) -> Result<Vec<WebhookDispatch>>;
+    /// Records the outcome of an attempt; `next_attempt_at` is only kept
+    /// while the delivery is still `pending`.
+    fn record_webhook_attempt(
+        &self,
+        id: u32,
+        status: &str,
+        next_attempt_at: Option<i64>,
+        status_code: Option<u16>,
+        error: Option<&str>,
+    ) -> Result<()>;
+
+    /// The signing keys, newest first.
+    fn get_jwt_secrets(&self) -> Result<Vec<JwtSecret>>;
+    /// Signs new tokens with `secret` and retires the previous keys.
+    fn rotate_jwt_secret(&self, secret: &str) -> Result<u32>;
+
+    /// What the readiness check reports about the database, or why it cannot
+    /// be used.
+    fn check(&self) -> Result<String>;
+}
+
+/// The SQLite database at `path`, through `crud`. Audit events and webhook
+/// events are written along with every change.
+pub struct SqliteStorage {
+    path: String,
+    /// An in-memory database is dropped with its last connection, so one is
+    /// held for as long as the storage lives.
+    _memory: Option<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens the file at `path`, creating it and applying migrations as needed.
+    pub fn open(path: &str) -> Result<SqliteStorage> {
+        let storage = SqliteStorage {
+            path: path.to_string(),
+            _memory: None,
+        };
+        storage.scope(crud::check_db)?;
+        Ok(storage)
+    }
+
+    /// A fresh database that only lives in memory. Each one is separate, so
+    /// any number can be used side by side, e.g. one per test.
+    pub fn memory() -> Result<SqliteStorage> {
+        // a shared cache makes every connection to the name within the process
+        // see the same database; unlike the memdb VFS it keeps readers out of
+        // pages that a concurrent writer is changing
+        let path = format!(
+            "file:bank-{}?mode=memory&cache=shared",
+            uuid::Uuid::new_v4()
+        );
+        let storage = SqliteStorage {
+            _memory: Some(Mutex::new(Connection::open(&path)?)),
+            path,
+        };
+        storage.scope(crud::check_db)?;
+        Ok(storage)
+    }
+
+    /// What `crud::DATABASE` is set to for this storage.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn scope<T>(&self, f: impl FnOnce() -> rusqlite::Result<T>) -> Result<T> {
+        Ok(crud::DATABASE.sync_scope(self.path.clone(), f)?)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn create_customer(&self, customer: &Customer) -> Result<()> {
+        self.scope(|| crud::create_customer(customer))
+    }
+
+    fn get_customer(&self, id: u16) -> Result<Customer> {
+        self.scope(|| crud::get_customer(id))
+    }
+
+    fn get_all_customers(&self) -> Result<Vec<Customer>> {
+        self.scope(crud::get_all_customers)
+    }
+
+    fn edit_customer(&self, id: u16, customer: &CustomerEdit) -> Result<()> {
+        self.scope(|| crud::edit_customer(id, customer))
+    }
+
+    fn patch_customer(&self, id: u16, customer: &CustomerPatch) -> Result<()> {
+        self.scope(|| crud::patch_customer(id, customer))
+    }
+
+    fn change_status(
+        &self,
+        customer: &Customer,
+        status: CustomerStatus,
+        reason: &str,
+        payout_receiver_code: Option<&str>,
+    ) -> Result<()> {
+        self.scope(|| crud::change_status(customer, status, reason, payout_receiver_code))
+    }
+
+    fn update_kyc_level(&self, id: u16, level: KycLevel) -> Result<()> {
+        self.scope(|| crud::update_kyc_level(id, level))
+    }
+
+    fn create_kyc_document(&self, document: &KycDocument) -> Result<()> {
+        self.scope(|| crud::create_kyc_document(document))
+    }
+
+    fn get_kyc_documents_by_customer(&self, customer_id: u16) -> Result<Vec<KycDocument>> {
+        self.scope(|| crud::get_kyc_documents_by_customer(customer_id))
+    }
+
+    fn create_movement(&self, id: u16, kind: &str, amount: f64) -> Result<()> {
+        self.scope(|| crud::create_movement(id, kind, amount))
+    }
+
+    fn create_transfer(&self, id_from: u16, id_to: u16, amount: f64) -> Result<()> {
+        self.scope(|| crud::create_transfer(id_from, id_to, amount))
+    }
+
+    fn get_all_transfers(&self) -> Result<Vec<TransferHuman>> {
+        self.scope(crud::get_all_transfers)
+    }
+
+    fn get_transfers_by_customer(&self, id: u16) -> Result<Vec<TransferHuman>> {
+        self.scope(|| crud::get_transfers_by_customer(id))
+    }
+
+    fn create_payment(&self, payment: &Payment) -> Result<()> {
+        self.scope(|| crud::create_payment(payment))
+    }
+
+    fn get_payments_by_customer(&self, id: u16) -> Result<Vec<Payment>> {
+        self.scope(|| crud::get_payments_by_customer(id))
+    }
+
+    fn create_batch(&self, items: &[BatchItem], report: &Batch) -> Result<u32> {
+        crud::DATABASE.sync_scope(self.path.clone(), || crud::create_batch(items, report))
+    }
+
+    fn start_batch_report(&self, batch: &Batch) -> Result<u32> {
+        self.scope(|| crud::start_batch_report(batch))
+    }
+
+    fn finish_batch_report(&self, id: u32, batch: &Batch) -> Result<()> {
+        self.scope(|| crud::finish_batch_report(id, batch))
+    }
+
+    fn create_batch_report(&self, batch: &Batch) -> Result<u32> {
+        self.scope(|| crud::create_batch_report(batch))
+    }
+
+    fn get_batch_report(&self, id: u32) -> Result<Batch> {
+        self.scope(|| crud::get_batch_report(id))
+    }
+
+    fn get_transfer(&self, id: u16) -> Result<Transfer> {
+        self.scope(|| crud::get_transfer(id))
+    }
+
+    fn get_payment(&self, id: u16) -> Result<Payment> {
+        self.scope(|| crud::get_payment(id))
+    }
+
+    fn reverse_transfer(&self, id: u16, amount: Option<f64>) -> Result<f64> {
+        self.scope(|| crud::reverse_transfer(id, amount))
+    }
+
+    fn reverse_payment(&self, id: u16, amount: Option<f64>) -> Result<f64> {
+        self.scope(|| crud::reverse_payment(id, amount))
+    }
+
+    fn get_reversals(&self) -> Result<Vec<Reversal>> {
+        self.scope(crud::get_reversals)
+    }
+
+    fn create_payee(&self, payee: &Payee) -> Result<()> {
+        self.scope(|| crud::create_payee(payee))
+    }
+
+    fn get_payee(&self, customer_id: u16, id: u16) -> Result<Payee> {
+        self.scope(|| crud::get_payee(customer_id, id))
+    }
+
+    fn get_payees_by_customer(&self, customer_id: u16) -> Result<Vec<Payee>> {
+        self.scope(|| crud::get_payees_by_customer(customer_id))
+    }
+
+    fn edit_payee(&self, customer_id: u16, id: u16, payee: &Payee) -> Result<()> {
+        self.scope(|| crud::edit_payee(customer_id, id, payee))
+    }
+
+    fn delete_payee(&self, customer_id: u16, id: u16) -> Result<()> {
+        self.scope(|| crud::delete_payee(customer_id, id))
+    }
+
+    fn get_bookings(&self, id: u16) -> Result<Vec<Booking>> {
+        self.scope(|| crud::get_bookings(id))
+    }
+
+    fn get_statement(
+        &self,
+        id: u16,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Statement> {
+        self.scope(|| crud::get_statement(id, from, to))
+    }
+
+    fn create_audit_event(
+        &self,
+        action: &str,
+        entity: &str,
+        entity_id: Option<u16>,
+        before: Option<serde_json::Value>,
+        after: Option<serde_json::Value>,
+    ) -> Result<()> {
+        self.scope(|| crud::create_audit_event(action, entity, entity_id, before, after))
+    }
+
+    fn get_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>> {
+        self.scope(|| crud::get_audit_events(filter))
+    }
+
+    fn verify_audit_chain(&self) -> Result<ChainReport> {
+        self.scope(crud::verify_audit_chain)
+    }
+
+    fn reconcile(&self, correct: bool) -> Result<Reconciliation> {
+        self.scope(|| crud::reconcile(correct))
+    }
+
+    fn create_webhook(&self, webhook: &Webhook) -> Result<u16> {
+        self.scope(|| crud::create_webhook(webhook))
+    }
+
+    fn get_webhook(&self, id: u16) -> Result<Webhook> {
+        self.scope(|| crud::get_webhook(id))
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        self.scope(crud::get_webhooks)
+    }
+
+    fn delete_webhook(&self, id: u16) -> Result<()> {
+        self.scope(|| crud::delete_webhook(id))
+    }
+
+    fn get_webhook_deliveries(&self, webhook_id: u16) -> Result<Vec<WebhookDelivery>> {
+        self.scope(|| crud::get_webhook_deliveries(webhook_id))
+    }
+
+    fn fan_out_webhook_events(&self) -> Result<usize> {
+        self.scope(crud::fan_out_webhook_events)
+    }
+
+    fn get_due_webhook_deliveries(&self, now: i64, limit: u32) -> Result<Vec<WebhookDispatch>> {
+        self.scope(|| crud::get_due_webhook_deliveries(now, limit))
+    }
+
+    fn record_webhook_attempt(
+        &self,
+        id: u32,
+        status: &str,
+        next_attempt_at: Option<i64>,
+        status_code: Option<u16>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        self.scope(|| crud::record_webhook_attempt(id, status, next_attempt_at, status_code, error))
+    }
+
+    fn get_jwt_secrets(&self) -> Result<Vec<JwtSecret>> {
+        self.scope(crud::get_jwt_secrets)
+    }
+
+    fn rotate_jwt_secret(&self, secret: &str) -> Result<u32> {
+        self.scope(|| crud::rotate_jwt_secret(secret))
+    }
+
+    fn check(&self) -> Result<String> {
+        match self.scope(crud::schema_version)? {
+            (current, expected) if current == expected => Ok(format!("at version {}", current)),
+            (current, expected) => Err(StorageError::Backend(format!(
+                "at version {}, expected {}",
+                current, expected
+            ))),
+        }
+    }
+}
+
+/// What the app runs against: the backend behind `Storage`, the SQLite
+/// database file it works on, if any, for backups and the readiness check,
+/// and the subscribers to its live updates.
+#[derive(Clone)]
+pub struct Backend {
+    pub storage: Arc<dyn Storage>,
+    pub database: Option<String>,
+    pub streams: Arc<Streams>,
+}
+
+impl Backend {
+    /// Everything in the SQLite database file at `path`.
+    pub fn sqlite(path: &str) -> Result<Backend> {
+        Ok(Backend {
+            storage: Arc::new(SqliteStorage::open(path)?),
+            database: Some(path.to_string()),
+            streams: Arc::default(),
+        })
+    }
+
+    /// Everything in one fresh in-memory SQLite database.
+    pub fn memory() -> Result<Backend> {
+        let storage = SqliteStorage::memory()?;
+        Ok(Backend {
+            database: Some(storage.path().to_string()),
+            storage: Arc::new(storage),
+            streams: Arc::default(),
+        })
+    }
+
+    /// Everything in the PostgreSQL database at `url`.
+    pub fn postgres(url: &str) -> Result<Backend> {
+        Ok(Backend {
+            storage: Arc::new(PostgresStorage::connect(url)?),
+            database: None,
+            streams: Arc::default(),
+        })
+    }
+}
+
+/// The backend named by `BANK_STORAGE`: `sqlite` (the default) uses
+/// `crud::DATABASE_FILE`, `memory` a fresh in-memory database that is gone
+/// when the server stops, and `postgres` the database at `BANK_POSTGRES_URL`.
+pub fn from_config() -> std::result::Result<Backend, String> {
+    let backend = std::env::var("BANK_STORAGE").unwrap_or_else(|_| "sqlite".to_string());
+
+    match backend.as_str() {
+        "sqlite" => Backend::sqlite(crud::DATABASE_FILE).map_err(|e| e.to_string()),
+        "memory" => Backend::memory().map_err(|e| e.to_string()),
+        "postgres" => {
+            let url = std::env::var("BANK_POSTGRES_URL")
+                .map_err(|_| "BANK_STORAGE=postgres needs BANK_POSTGRES_URL".to_string())?;
+            Backend::postgres(&url).map_err(|e| e.to_string())
+        }
+        x => Err(format!(
+            "unknown BANK_STORAGE {}, expected sqlite, memory or postgres",
+            x
+        )),
+    }
+}
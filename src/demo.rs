@@ -0,0 +1,31 @@
+use crate::database::models::Customer;
+use crate::database::storage::{Result, Storage};
+use chrono::Utc;
+
+/// Name, opening balance and email of the customers a demo starts with.
+static CUSTOMERS: [(&str, f64, &str); 4] = [
+    ("Ada Lovelace", 2500.0, "ada@example.com"),
+    ("Alan Turing", 1200.0, "alan@example.com"),
+    ("Grace Hopper", 800.0, "grace@example.com"),
+    ("Katherine Johnson", 50.0, "katherine@example.com"),
+];
+
+/// Opens the sample accounts, so a demo has something to show from the start.
+pub fn seed(storage: &dyn Storage) -> Result<()> {
+    for (name, balance, email) in CUSTOMERS {
+        storage.create_customer(&Customer {
+            id: None,
+            name: name.to_string(),
+            balance: Some(balance),
+            created_at: Some(Utc::now().to_rfc2822()),
+            status: None,
+            date_of_birth: None,
+            national_id: None,
+            address: None,
+            email: Some(email.to_string()),
+            phone: None,
+            kyc_level: None,
+        })?;
+    }
+    Ok(())
+}
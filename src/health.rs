@@ -0,0 +1,159 @@
+use crate::database::crud;
+use crate::database::models::{ComponentHealth, HealthReport};
+use crate::database::storage::Backend;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Below this much free space on the volume holding the database the
+/// service reports itself as not ready.
+static MIN_FREE_DISK_BYTES: u64 = 100 * 1024 * 1024;
+
+struct JobState {
+    every: Duration,
+    last_run: Instant,
+    error: Option<String>,
+}
+
+static JOBS: LazyLock<Mutex<BTreeMap<&'static str, JobState>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// Called by background jobs after each run. A job is unhealthy when its last
+/// run failed or when it has not run for twice its interval.
+pub fn report_job(name: &'static str, every: Duration, result: Result<(), String>) {
+    JOBS.lock().unwrap().insert(
+        name,
+        JobState {
+            every,
+            last_run: Instant::now(),
+            error: result.err(),
+        },
+    );
+}
+
+fn component(name: &str, result: Result<String, String>) -> ComponentHealth {
+    let (status, detail) = match result {
+        Ok(detail) => ("ok", detail),
+        Err(detail) => ("fail", detail),
+    };
+    ComponentHealth {
+        name: name.to_string(),
+        status: status.to_string(),
+        detail: Some(detail),
+    }
+}
+
+/// The file behind `database`, or `None` when it only lives in memory.
+fn database_file(database: &str) -> Option<&Path> {
+    (!database.starts_with("file:")).then(|| Path::new(database))
+}
+
+fn schema_version(database: &str) -> rusqlite::Result<(usize, usize)> {
+    crud::DATABASE.sync_scope(database.to_string(), crud::schema_version)
+}
+
+fn check_database(database: &str) -> Result<String, String> {
+    let Some(path) = database_file(database) else {
+        return schema_version(database)
+            .map(|_| "in memory".to_string())
+            .map_err(|e| e.to_string());
+    };
+    if !path.is_file() {
+        return Err(format!("{} is missing", database));
+    }
+    schema_version(database)
+        .map(|_| format!("{} is readable", database))
+        .map_err(|e| e.to_string())
+}
+
+fn check_migrations(database: &str) -> Result<String, String> {
+    // opening a missing file would create an empty database in its place
+    if database_file(database).is_some_and(|x| !x.is_file()) {
+        return Err("database is unavailable".to_string());
+    }
+    match schema_version(database) {
+        Ok((current, expected)) if current == expected => Ok(format!("at version {}", current)),
+        Ok((current, expected)) => Err(format!("at version {}, expected {}", current, expected)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn free_disk_bytes(path: &Path) -> Result<u64, String> {
+    let path = CString::new(path.as_os_str().as_encoded_bytes()).map_err(|e| e.to_string())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a valid C string and `stat` is a writable statvfs.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+fn check_disk(database: &str) -> Result<String, String> {
+    // an in-memory database still has its backups written next to the process
+    let directory = match database_file(database).and_then(|x| x.parent()) {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+    let free = free_disk_bytes(directory)?;
+    let detail = format!("{} MiB free", free / 1024 / 1024);
+
+    if free < MIN_FREE_DISK_BYTES {
+        return Err(detail);
+    }
+    Ok(detail)
+}
+
+fn check_jobs() -> Vec<ComponentHealth> {
+    JOBS.lock()
+        .unwrap()
+        .iter()
+        .map(|(name, job)| {
+            let result = match &job.error {
+                Some(e) => Err(format!("last run failed: {}", e)),
+                None if job.last_run.elapsed() > job.every * 2 => Err(format!(
+                    "has not run for {}s",
+                    job.last_run.elapsed().as_secs()
+                )),
+                None => Ok(format!("ran {}s ago", job.last_run.elapsed().as_secs())),
+            };
+            component(&format!("job:{}", name), result)
+        })
+        .collect()
+}
+
+fn report(components: Vec<ComponentHealth>) -> HealthReport {
+    let healthy = components.iter().all(|x| x.status == "ok");
+    HealthReport {
+        status: if healthy { "ok" } else { "fail" }.to_string(),
+        components,
+    }
+}
+
+/// The process is up and serving requests.
+pub fn liveness() -> HealthReport {
+    report(vec![component(
+        "process",
+        Ok("serving requests".to_string()),
+    )])
+}
+
+/// Everything the service needs to handle traffic on `backend` is in place.
+pub fn readiness(backend: &Backend) -> HealthReport {
+    let mut components = match &backend.database {
+        Some(database) => vec![
+            component("database", check_database(database)),
+            component("migrations", check_migrations(database)),
+            component("disk", check_disk(database)),
+        ],
+        // nothing is kept on the server's own disk
+        None => vec![component(
+            "database",
+            backend.storage.check().map_err(|e| e.to_string()),
+        )],
+    };
+    components.extend(check_jobs());
+    report(components)
+}
@@ -0,0 +1,359 @@
+use crate::database::models::{Booking, Payment, Statement};
+use crate::references::{CreditorReference, Iban, ReferenceValidator};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use roxmltree::{Document, Node};
+
+/// Balances carry no currency, so every message is in this one.
+pub static CURRENCY: &str = "EUR";
+static CAMT_053: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.02";
+/// Stands in for an end-to-end id, which the bank does not keep.
+static NOT_PROVIDED: &str = "NOTPROVIDED";
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|x| x.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |x| x.tag_name().name() == name)
+}
+
+/// The trimmed text at the end of `path`, e.g. `["PmtId", "EndToEndId"]`.
+fn text<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    path.iter()
+        .try_fold(node, |node, name| child(node, name))?
+        .text()
+        .map(str::trim)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Reads the credit transfers of a `pain.001` initiation, of any version,
+/// as payments by customer `id`. Elements are matched by name whatever their
+/// namespace. The whole file is turned down when it cannot be read, when its
+/// totals do not add up, or when a debtor account is not customer `id` (an
+/// IBAN never is).
+pub fn read_pain001(xml: &str, id: u16) -> Result<Vec<Payment>, String> {
+    let document = Document::parse(xml).map_err(|e| e.to_string())?;
+    let initiation = child(document.root_element(), "CstmrCdtTrfInitn")
+        .ok_or("not a pain.001 document, CstmrCdtTrfInitn is missing")?;
+    let header = child(initiation, "GrpHdr").ok_or("GrpHdr is missing")?;
+
+    let mut payments = Vec::new();
+    for information in children(initiation, "PmtInf") {
+        // customers have no IBAN, so only an Othr/Id account can be checked
+        if let Some(account) = child(information, "DbtrAcct") {
+            match (
+                text(account, &["Id", "IBAN"]),
+                text(account, &["Id", "Othr", "Id"]),
+            ) {
+                (None, Some(debtor)) if debtor == id.to_string() => {}
+                (None, Some(debtor)) => {
+                    return Err(format!(
+                        "the debtor account {} is not customer {}",
+                        debtor, id
+                    ))
+                }
+                _ => {
+                    return Err(format!(
+                        "the debtor account must be customer {} as Othr/Id, not an IBAN",
+                        id
+                    ))
+                }
+            }
+        }
+        for transaction in children(information, "CdtTrfTxInf") {
+            let mut payment = read_credit_transfer(transaction)?;
+            payment.customer_id = Some(id);
+            payments.push(payment);
+        }
+    }
+
+    // the totals catch files that were cut short or put together wrongly
+    if text(header, &["NbOfTxs"]) != Some(&payments.len().to_string()) {
+        return Err(format!(
+            "NbOfTxs does not match the {} credit transfers",
+            payments.len()
+        ));
+    }
+    if let Some(sum) = text(header, &["CtrlSum"]) {
+        let sum: f64 = sum.parse().map_err(|_| "CtrlSum is not a number")?;
+        let total: f64 = payments.iter().map(|x| x.amount).sum();
+        if (sum - total).abs() >= 0.005 {
+            return Err(format!(
+                "CtrlSum does not match the credit transfers, which add up to {:.2}",
+                total
+            ));
+        }
+    }
+    Ok(payments)
+}
+
+fn read_credit_transfer(node: Node) -> Result<Payment, String> {
+    let end_to_end = text(node, &["PmtId", "EndToEndId"]).unwrap_or(NOT_PROVIDED);
+    let amount = child(node, "Amt")
+        .and_then(|x| child(x, "InstdAmt"))
+        .ok_or_else(|| format!("{}: InstdAmt is missing", end_to_end))?;
+    if amount.attribute("Ccy") != Some(CURRENCY) {
+        return Err(format!(
+            "{}: only {} amounts are supported",
+            end_to_end, CURRENCY
+        ));
+    }
+    let amount: f64 = amount
+        .text()
+        .unwrap_or_default()
+        .trim()
+        .parse()
+        .map_err(|_| format!("{}: InstdAmt is not a number", end_to_end))?;
+
+    let reference = read_reference(node)
+        .or(Some(end_to_end).filter(|x| *x != NOT_PROVIDED))
+        .unwrap_or_default();
+    Ok(Payment {
+        id: None,
+        created_at: None,
+        customer_id: None,
+        amount,
+        receiver_code: child(node, "CdtrAcct")
+            .and_then(read_account)
+            .unwrap_or_default()
+            .to_string(),
+        reference: reference.to_string(),
+        note: text(node, &["InstrForDbtrAgt"]).map(|x| x.to_string()),
+        payee_id: None,
+    })
+}
+
+/// `receiver_code` of a payment, from an account like `CdtrAcct`.
+fn read_account<'a>(account: Node<'a, '_>) -> Option<&'a str> {
+    text(account, &["Id", "IBAN"]).or_else(|| text(account, &["Id", "Othr", "Id"]))
+}
+
+fn write_account(receiver_code: &str, payment: &Payment) -> String {
+    if Iban.applies_to(payment) {
+        let iban: String = receiver_code.split_whitespace().collect();
+        format!("<Id><IBAN>{}</IBAN></Id>", escape(&iban))
+    } else {
+        format!("<Id><Othr><Id>{}</Id></Othr></Id>", escape(receiver_code))
+    }
+}
+
+/// `reference` of a payment, from the `RmtInf` of an element: a structured
+/// creditor reference, or else the unstructured text.
+fn read_reference<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    text(node, &["RmtInf", "Strd", "CdtrRefInf", "Ref"])
+        .or_else(|| text(node, &["RmtInf", "Ustrd"]))
+}
+
+fn write_reference(payment: &Payment) -> String {
+    if payment.reference.is_empty() {
+        return String::new();
+    }
+    if CreditorReference.applies_to(payment) {
+        return format!(
+            "<RmtInf><Strd><CdtrRefInf><Tp><CdOrPrtry><Cd>SCOR</Cd></CdOrPrtry><Issr>ISO</Issr></Tp><Ref>{}</Ref></CdtrRefInf></Strd></RmtInf>",
+            escape(&payment.reference)
+        );
+    }
+    format!(
+        "<RmtInf><Ustrd>{}</Ustrd></RmtInf>",
+        escape(&payment.reference)
+    )
+}
+
+fn amount(value: f64) -> String {
+    let indicator = if value < 0.0 { "DBIT" } else { "CRDT" };
+    format!(
+        "<Amt Ccy=\"{}\">{:.2}</Amt><CdtDbtInd>{}</CdtDbtInd>",
+        CURRENCY,
+        value.abs(),
+        indicator
+    )
+}
+
+fn balance(code: &str, value: f64, day: NaiveDate) -> String {
+    format!(
+        "<Bal><Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>{}<Dt><Dt>{}</Dt></Dt></Bal>\n",
+        code,
+        amount(value),
+        day
+    )
+}
+
+/// A booked `Ntry`. `code` is the content of its `BkTxCd`, and `details`
+/// what follows `Refs` in its `TxDtls`.
+struct Entry {
+    booked: DateTime<Utc>,
+    value: f64,
+    reference: String,
+    code: String,
+    details: String,
+}
+
+impl Entry {
+    fn write(&self) -> String {
+        format!(
+            "<Ntry>{amount}<Sts>BOOK</Sts><BookgDt><DtTm>{booked}</DtTm></BookgDt><ValDt><Dt>{day}</Dt></ValDt><AcctSvcrRef>{reference}</AcctSvcrRef><BkTxCd>{code}</BkTxCd><NtryDtls><TxDtls><Refs><AcctSvcrRef>{reference}</AcctSvcrRef><EndToEndId>{end_to_end}</EndToEndId></Refs>{details}</TxDtls></NtryDtls></Ntry>\n",
+            amount = amount(self.value),
+            booked = self.booked.to_rfc3339_opts(SecondsFormat::Secs, true),
+            day = self.booked.date_naive(),
+            reference = escape(&self.reference),
+            code = self.code,
+            end_to_end = NOT_PROVIDED,
+            details = self.details,
+        )
+    }
+}
+
+fn domain(family: &str, sub_family: &str) -> String {
+    format!(
+        "<Domn><Cd>PMNT</Cd><Fmly><Cd>{}</Cd><SubFmlyCd>{}</SubFmlyCd></Fmly></Domn>",
+        family, sub_family
+    )
+}
+
+fn booked_at(created_at: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(created_at)
+        .map(|x| x.with_timezone(&Utc))
+        .ok()
+}
+
+fn booking_entry(booking: &Booking) -> Option<Entry> {
+    match booking {
+        Booking::Transfer {
+            id,
+            created_at,
+            amount,
+            counterparty_id,
+            counterparty_name,
+        } => {
+            let (family, party) = if *amount < 0.0 {
+                ("ICDT", "Cdtr")
+            } else {
+                ("RCDT", "Dbtr")
+            };
+            Some(Entry {
+                booked: booked_at(created_at)?,
+                value: *amount,
+                reference: format!("TRANSFER-{}", id),
+                code: domain(family, "BOOK"),
+                details: format!(
+                    "<RltdPties><{party}><Nm>{name}</Nm></{party}><{party}Acct><Id><Othr><Id>{id}</Id></Othr></Id></{party}Acct></RltdPties>",
+                    party = party,
+                    name = escape(counterparty_name),
+                    id = counterparty_id
+                ),
+            })
+        }
+        Booking::Payment(payment) => {
+            let note = payment
+                .note
+                .as_deref()
+                .map(|x| format!("<AddtlTxInf>{}</AddtlTxInf>", escape(x)))
+                .unwrap_or_default();
+            Some(Entry {
+                booked: booked_at(payment.created_at.as_deref()?)?,
+                value: -payment.amount,
+                reference: format!("PAYMENT-{}", payment.id?),
+                code: domain("ICDT", "ESCT"),
+                details: format!(
+                    "<RltdPties><CdtrAcct>{}</CdtrAcct></RltdPties>{}{}",
+                    write_account(&payment.receiver_code, payment),
+                    write_reference(payment),
+                    note
+                ),
+            })
+        }
+    }
+}
+
+/// The `camt.053` end-of-day statement of `day`. `statement` covers that
+/// day alone: it gives the balances, and the entries for what is neither a
+/// transfer nor a payment (deposits, withdrawals, reversals). Transfers and
+/// payments are taken from `bookings`, with their counterparties and
+/// references.
+pub fn write_camt053(statement: &Statement, bookings: &[Booking], day: NaiveDate) -> String {
+    let mut entries: Vec<Entry> = bookings
+        .iter()
+        .filter_map(booking_entry)
+        .filter(|x| x.booked.date_naive() == day)
+        .collect();
+
+    let mut others = 0;
+    for line in &statement.lines {
+        if line.kind == "transfer" || line.kind == "payment" {
+            continue;
+        }
+        let Some(booked) = booked_at(&line.created_at) else {
+            continue;
+        };
+        let code = match line.kind.as_str() {
+            "deposit" => domain("CNTR", "CDPT"),
+            "withdrawal" => domain("CNTR", "CWDL"),
+            kind => format!("<Prtry><Cd>{}</Cd></Prtry>", escape(&kind.to_uppercase())),
+        };
+        others += 1;
+        entries.push(Entry {
+            booked,
+            value: line.amount,
+            reference: format!(
+                "{}-{}-{}",
+                line.kind.to_uppercase(),
+                day.format("%Y%m%d"),
+                others
+            ),
+            code,
+            details: format!("<AddtlTxInf>{}</AddtlTxInf>", escape(&line.description)),
+        });
+    }
+    entries.sort_by_key(|x| x.booked);
+
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let id = format!("STMT-{}-{}", statement.customer_id, day.format("%Y%m%d"));
+    let net: f64 = entries.iter().map(|x| x.value).sum();
+    let sum: f64 = entries.iter().map(|x| x.value.abs()).sum();
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Document xmlns=\"{}\"><BkToCstmrStmt>\n",
+        CAMT_053
+    );
+    xml += &format!(
+        "<GrpHdr><MsgId>{}</MsgId><CreDtTm>{}</CreDtTm></GrpHdr>\n",
+        id, now
+    );
+    xml += &format!(
+        "<Stmt><Id>{id}</Id><CreDtTm>{now}</CreDtTm><FrToDt><FrDtTm>{day}T00:00:00Z</FrDtTm><ToDtTm>{day}T23:59:59Z</ToDtTm></FrToDt>\n",
+        id = id,
+        now = now,
+        day = day
+    );
+    xml += &format!(
+        "<Acct><Id><Othr><Id>{}</Id></Othr></Id><Ccy>{}</Ccy><Ownr><Nm>{}</Nm></Ownr></Acct>\n",
+        statement.customer_id,
+        CURRENCY,
+        escape(&statement.name)
+    );
+    xml += &balance("OPBD", statement.opening_balance, day);
+    xml += &balance("CLBD", statement.closing_balance, day);
+    xml += &format!(
+        "<TxsSummry><TtlNtries><NbOfNtries>{}</NbOfNtries><Sum>{:.2}</Sum><TtlNetNtryAmt>{:.2}</TtlNetNtryAmt><CdtDbtInd>{}</CdtDbtInd></TtlNtries></TxsSummry>\n",
+        entries.len(),
+        sum,
+        net.abs(),
+        if net < 0.0 { "DBIT" } else { "CRDT" }
+    );
+    for entry in &entries {
+        xml += &entry.write();
+    }
+    xml += "</Stmt></BkToCstmrStmt></Document>\n";
+    xml
+}
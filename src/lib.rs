@@ -0,0 +1,17 @@
+pub mod app;
+pub mod audit;
+pub mod backup;
+pub mod batch;
+pub mod database;
+pub mod demo;
+pub mod health;
+pub mod iso20022;
+pub mod logging;
+pub mod metrics;
+pub mod openapi;
+pub mod ratelimit;
+pub mod references;
+pub mod routes;
+pub mod stream;
+pub mod versioning;
+pub mod webhooks;
@@ -0,0 +1,40 @@
+use tracing_subscriber::EnvFilter;
+
+static DEFAULT_LOG_LEVEL: &str = "info";
+
+/// JSON logs on stdout. The level is read from `RUST_LOG` (for example
+/// `RUST_LOG=bank=debug` to see the spans around `crud` calls).
+pub fn init() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL));
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(true)
+        .init();
+}
+
+/// Masks anything shaped like a JWT (three base64url segments joined by dots)
+/// so that tokens passed in paths or query strings never reach the logs.
+pub fn redact(text: &str) -> String {
+    text.split_inclusive(|c: char| !is_token_char(c))
+        .map(|part| {
+            let token = part.trim_end_matches(|c: char| !is_token_char(c));
+            if looks_like_jwt(token) {
+                part.replacen(token, "[REDACTED]", 1)
+            } else {
+                part.to_string()
+            }
+        })
+        .collect()
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+fn looks_like_jwt(token: &str) -> bool {
+    let segments: Vec<&str> = token.split('.').collect();
+    segments.len() == 3 && segments.iter().all(|x| x.len() >= 4) && token.starts_with("eyJ")
+}
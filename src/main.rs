@@ -1,40 +1,60 @@
-mod database;
-mod routes;
-use actix_web::{web, App, HttpServer};
+use actix_web::{web, HttpServer};
+use bank::ratelimit::RateLimiter;
+use bank::{app, backup, database, demo, logging, metrics, webhooks};
 
 static HOST: &str = "127.0.0.1";
 static PORT: u16 = 8080;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    database::crud::check_db().unwrap();
+    if std::env::args().nth(1).as_deref() == Some("verify-audit") {
+        let backend = database::storage::from_config().unwrap();
+        let report = backend.storage.verify_audit_chain().unwrap();
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        std::process::exit(if report.valid { 0 } else { 1 });
+    }
 
-    HttpServer::new(|| {
-        App::new()
-            .route("/", web::get().to(routes::health_check))
-            .route("token", web::get().to(routes::get_jwt))
-            .service(web::scope("/transfers").route("", web::get().to(routes::get_all_transfers)))
-            .service(
-                web::scope("/customers")
-                    .route("/transfers", web::put().to(routes::transfer_amount))
-                    .route("", web::post().to(routes::create_customer))
-                    .route("", web::get().to(routes::get_all_customers))
-                    .route("/{id}", web::get().to(routes::get_customer))
-                    .route("/{id}", web::put().to(routes::edit_customer))
-                    .route(
-                        "/{id}/transfers",
-                        web::get().to(routes::get_transfers_by_customer),
-                    )
-                    .route("/{id}/payments", web::post().to(routes::create_payment))
-                    .route(
-                        "/{id}/payments",
-                        web::get().to(routes::get_payments_by_customer),
-                    )
-                    .route("/{id}/deposits", web::put().to(routes::deposit))
-                    .route("/{id}/withdrawals", web::put().to(routes::withdraw)),
-            )
-    })
-    .bind((HOST, PORT))?
-    .run()
-    .await
+    if std::env::args().nth(1).as_deref() == Some("reconcile") {
+        let backend = database::storage::from_config().unwrap();
+        let correct = std::env::args().any(|x| x == "--correct");
+        let report = backend.storage.reconcile(correct).unwrap();
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        std::process::exit(if report.discrepancies.is_empty() {
+            0
+        } else {
+            1
+        });
+    }
+
+    logging::init();
+    metrics::init();
+
+    // `demo` serves sample customers from memory and forgets every change
+    // when it stops; admin tokens are handed out at `/token` to try it
+    let backend = if std::env::args().nth(1).as_deref() == Some("demo") {
+        std::env::set_var("BANK_ISSUE_ADMIN_TOKENS", "1");
+        database::storage::Backend::memory()
+            .and_then(|x| demo::seed(&*x.storage).map(|_| x))
+            .map_err(|e| e.to_string())
+    } else {
+        database::storage::from_config()
+    };
+    let backend = match backend {
+        Ok(x) => x,
+        Err(e) => {
+            tracing::error!(error = %e, "could not open the storage backend");
+            std::process::exit(1);
+        }
+    };
+    webhooks::spawn(backend.storage.clone());
+    if let Some(database) = backend.database.clone() {
+        backup::spawn(database);
+    }
+
+    let limiter = web::Data::new(RateLimiter::from_env());
+
+    HttpServer::new(move || app::new(&backend, &limiter))
+        .bind((HOST, PORT))?
+        .run()
+        .await
 }
@@ -0,0 +1,173 @@
+use actix_web::{HttpResponse, Responder};
+use prometheus::{
+    Counter, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(collector: T) -> T {
+    REGISTRY.register(Box::new(collector.clone())).unwrap();
+    collector
+}
+
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("bank_http_requests_total", "HTTP requests served."),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "bank_http_request_duration_seconds",
+                "Time spent serving HTTP requests.",
+            ),
+            &["method", "route"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static DB_QUERY_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "bank_db_query_duration_seconds",
+                "Time spent running SQL statements.",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5,
+            ]),
+            &["operation", "table"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static API_VERSION_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "bank_api_version_requests_total",
+                "Requests served per API version.",
+            ),
+            &["version"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static TRANSFERS: LazyLock<IntCounter> =
+    LazyLock::new(|| register(IntCounter::new("bank_transfers_total", "Transfers made.").unwrap()));
+
+pub static TRANSFER_VOLUME: LazyLock<Counter> = LazyLock::new(|| {
+    register(Counter::new("bank_transfer_volume_total", "Amount transferred.").unwrap())
+});
+
+pub static PAYMENTS: LazyLock<IntCounter> =
+    LazyLock::new(|| register(IntCounter::new("bank_payments_total", "Payments made.").unwrap()));
+
+pub static PAYMENT_VOLUME: LazyLock<Counter> =
+    LazyLock::new(|| register(Counter::new("bank_payment_volume_total", "Amount paid.").unwrap()));
+
+pub static DEPOSITS: LazyLock<IntCounter> =
+    LazyLock::new(|| register(IntCounter::new("bank_deposits_total", "Deposits made.").unwrap()));
+
+pub static DEPOSIT_VOLUME: LazyLock<Counter> = LazyLock::new(|| {
+    register(Counter::new("bank_deposit_volume_total", "Amount deposited.").unwrap())
+});
+
+pub static WITHDRAWALS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new("bank_withdrawals_total", "Withdrawals made.").unwrap())
+});
+
+pub static WITHDRAWAL_VOLUME: LazyLock<Counter> = LazyLock::new(|| {
+    register(Counter::new("bank_withdrawal_volume_total", "Amount withdrawn.").unwrap())
+});
+
+pub static FAILED_BALANCE_CHECKS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "bank_failed_balance_checks_total",
+            "Operations refused for lack of balance.",
+        )
+        .unwrap(),
+    )
+});
+
+/// Registers every metric up front so that all of them are exported from the
+/// first scrape, not only once they have been used.
+pub fn init() {
+    LazyLock::force(&HTTP_REQUESTS);
+    LazyLock::force(&HTTP_REQUEST_DURATION);
+    LazyLock::force(&DB_QUERY_DURATION);
+    LazyLock::force(&API_VERSION_REQUESTS);
+    LazyLock::force(&TRANSFERS);
+    LazyLock::force(&TRANSFER_VOLUME);
+    LazyLock::force(&PAYMENTS);
+    LazyLock::force(&PAYMENT_VOLUME);
+    LazyLock::force(&DEPOSITS);
+    LazyLock::force(&DEPOSIT_VOLUME);
+    LazyLock::force(&WITHDRAWALS);
+    LazyLock::force(&WITHDRAWAL_VOLUME);
+    LazyLock::force(&FAILED_BALANCE_CHECKS);
+}
+
+pub fn observe_request(method: &str, route: Option<&str>, status: u16, duration: Duration) {
+    let route = route.unwrap_or("unmatched");
+
+    HTTP_REQUESTS
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method, route])
+        .observe(duration.as_secs_f64());
+}
+
+/// Installed with `Connection::profile` on every connection opened by `crud`.
+/// Statements are labelled by operation and table rather than by their text.
+pub fn observe_query(sql: &str, duration: Duration) {
+    let mut words = sql.split_whitespace();
+    let operation = words
+        .next()
+        .unwrap_or("")
+        .trim_end_matches(';')
+        .to_uppercase();
+    let mut word_after = |keyword: &str| {
+        words
+            .by_ref()
+            .skip_while(|x| !x.eq_ignore_ascii_case(keyword))
+            .nth(1)
+    };
+    let table = match operation.as_str() {
+        "SELECT" | "DELETE" => word_after("FROM"),
+        "INSERT" => word_after("INTO"),
+        "UPDATE" => words.next(),
+        _ => None,
+    }
+    .unwrap_or("")
+    .trim_end_matches(';');
+
+    DB_QUERY_DURATION
+        .with_label_values(&[&operation, table])
+        .observe(duration.as_secs_f64());
+}
+
+pub async fn metrics() -> impl Responder {
+    let mut body = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut body)
+        .unwrap();
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
@@ -0,0 +1,103 @@
+use crate::routes;
+use actix_web::{HttpResponse, Responder};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "bank-api"),
+    servers((url = "/v1")),
+    paths(
+        routes::get_jwt,
+        routes::health_check,
+        routes::health_live,
+        routes::health_ready,
+        routes::transfer_amount,
+        routes::get_customer,
+        routes::get_transfers_by_customer,
+        routes::get_payments_by_customer,
+        routes::withdraw,
+        routes::deposit,
+        routes::edit_customer,
+        routes::patch_customer,
+        routes::get_all_customers,
+        routes::get_all_transfers,
+        routes::create_customer,
+        routes::create_payment,
+        routes::reverse_transfer,
+        routes::reverse_payment,
+        routes::get_all_reversals,
+        routes::create_batch,
+        routes::get_batch,
+        routes::import_pain001,
+        routes::export_camt053,
+        routes::create_payee,
+        routes::get_payees_by_customer,
+        routes::get_payee,
+        routes::edit_payee,
+        routes::delete_payee,
+        routes::freeze_customer,
+        routes::unfreeze_customer,
+        routes::close_customer,
+        routes::verify_customer,
+        routes::create_kyc_document,
+        routes::get_kyc_documents_by_customer,
+        routes::get_audit_events,
+        routes::verify_audit_chain,
+        routes::create_backup,
+        routes::get_backups,
+        routes::restore_backup,
+        routes::stream_customer,
+        routes::stream_all,
+        routes::create_webhook,
+        routes::get_all_webhooks,
+        routes::get_webhook,
+        routes::delete_webhook,
+        routes::get_webhook_deliveries,
+        routes::get_reconciliation,
+        routes::correct_reconciliation
+    ),
+    modifiers(&BearerToken)
+)]
+pub struct ApiDoc;
+
+/// Admin routes expect the JWT from `/token` as a bearer token.
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+static REDOC_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>bank-api</title>
+    <meta charset="utf-8" />
+  </head>
+  <body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+  </body>
+</html>
+"#;
+
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+pub async fn docs() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(REDOC_PAGE)
+}
@@ -0,0 +1,285 @@
+use crate::database::models;
+use crate::routes;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::{web, Error, HttpResponse};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Buckets that are full again carry no information, so they are dropped
+/// once this many are held.
+static MAX_BUCKETS: usize = 10_000;
+
+/// Routes that share a quota.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Auth,
+    Money,
+    Default,
+}
+
+impl RouteGroup {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RouteGroup::Auth => "auth",
+            RouteGroup::Money => "money",
+            RouteGroup::Default => "default",
+        }
+    }
+
+    /// `None` for the routes that are never limited (health, metrics, docs).
+    fn of(method: &actix_web::http::Method, path: &str) -> Option<RouteGroup> {
+        let path = path.strip_prefix("/v1").unwrap_or(path);
+
+        if ["/health", "/metrics", "/docs", "/openapi.json"]
+            .iter()
+            .any(|x| path.starts_with(x))
+        {
+            return None;
+        }
+        if path.trim_end_matches('/') == "/token" {
+            return Some(RouteGroup::Auth);
+        }
+        let moves_money = [
+            "/transfers",
+            "/payments",
+            "/deposits",
+            "/withdrawals",
+            "/reversals",
+            "/batches",
+            "/pain.001",
+        ]
+        .iter()
+        .any(|x| path.ends_with(x));
+        if moves_money && method != actix_web::http::Method::GET {
+            return Some(RouteGroup::Money);
+        }
+        Some(RouteGroup::Default)
+    }
+}
+
+/// `requests` per `seconds`, with bursts of up to `requests`.
+#[derive(Clone, Copy)]
+pub struct Quota {
+    pub requests: u32,
+    pub seconds: u32,
+}
+
+impl Quota {
+    /// The default for `group`, unless `BANK_RATE_LIMIT_<GROUP>` is set to
+    /// `<requests>/<seconds>`, e.g. `BANK_RATE_LIMIT_MONEY=30/60`.
+    fn of(group: RouteGroup) -> Quota {
+        let default = match group {
+            RouteGroup::Auth => Quota {
+                requests: 5,
+                seconds: 60,
+            },
+            RouteGroup::Money => Quota {
+                requests: 30,
+                seconds: 60,
+            },
+            RouteGroup::Default => Quota {
+                requests: 120,
+                seconds: 60,
+            },
+        };
+        let variable = format!("BANK_RATE_LIMIT_{}", group.as_str().to_uppercase());
+
+        std::env::var(variable)
+            .ok()
+            .and_then(|x| Quota::parse(&x))
+            .unwrap_or(default)
+    }
+
+    fn parse(text: &str) -> Option<Quota> {
+        let (requests, seconds) = text.split_once('/')?;
+        let quota = Quota {
+            requests: requests.trim().parse().ok()?,
+            seconds: seconds.trim().parse().ok()?,
+        };
+        (quota.requests > 0 && quota.seconds > 0).then_some(quota)
+    }
+
+    fn per_second(&self) -> f64 {
+        self.requests as f64 / self.seconds as f64
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, quota: &Quota, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * quota.per_second()).min(quota.requests as f64);
+        self.updated = now;
+    }
+
+    /// Seconds until the bucket holds `tokens` again.
+    fn seconds_until(&self, quota: &Quota, tokens: f64) -> u64 {
+        ((tokens - self.tokens).max(0.0) / quota.per_second()).ceil() as u64
+    }
+}
+
+/// What is sent back in the `RateLimit-*` headers.
+struct Decision {
+    allowed: bool,
+    limit: u32,
+    remaining: u64,
+    reset: u64,
+    retry_after: u64,
+}
+
+/// The quotas of a server and the buckets of its clients, shared by its
+/// workers as app data.
+pub struct RateLimiter {
+    quotas: HashMap<RouteGroup, Quota>,
+    buckets: Mutex<HashMap<(RouteGroup, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// The quotas set in the environment, or the defaults.
+    pub fn from_env() -> RateLimiter {
+        RateLimiter::with_quotas(Quota::of)
+    }
+
+    /// `quota` for every route group.
+    pub fn uniform(quota: Quota) -> RateLimiter {
+        RateLimiter::with_quotas(|_| quota)
+    }
+
+    fn with_quotas(quota: impl Fn(RouteGroup) -> Quota) -> RateLimiter {
+        RateLimiter {
+            quotas: [RouteGroup::Auth, RouteGroup::Money, RouteGroup::Default]
+                .into_iter()
+                .map(|x| (x, quota(x)))
+                .collect(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of every key, or from none of them when
+    /// one is empty. The headers describe the most restrictive bucket.
+    fn take(&self, group: RouteGroup, keys: &[String]) -> Decision {
+        let quota = self.quotas[&group];
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|(group, _), bucket| {
+                let quota = self.quotas[group];
+                bucket.refill(&quota, now);
+                bucket.tokens < quota.requests as f64
+            });
+        }
+        for key in keys {
+            buckets
+                .entry((group, key.clone()))
+                .or_insert(Bucket {
+                    tokens: quota.requests as f64,
+                    updated: now,
+                })
+                .refill(&quota, now);
+        }
+
+        let allowed = keys
+            .iter()
+            .all(|key| buckets[&(group, key.clone())].tokens >= 1.0);
+        let mut decision = Decision {
+            allowed,
+            limit: quota.requests,
+            remaining: quota.requests as u64,
+            reset: 0,
+            retry_after: 0,
+        };
+        for key in keys {
+            let bucket = buckets.get_mut(&(group, key.clone())).unwrap();
+            if allowed {
+                bucket.tokens -= 1.0;
+            }
+            decision.remaining = decision.remaining.min(bucket.tokens.floor() as u64);
+            decision.reset = decision
+                .reset
+                .max(bucket.seconds_until(&quota, quota.requests as f64));
+            decision.retry_after = decision.retry_after.max(bucket.seconds_until(&quota, 1.0));
+        }
+        decision
+    }
+}
+
+fn insert_header<B>(res: &mut ServiceResponse<B>, name: HeaderName, value: u64) {
+    res.headers_mut().insert(name, HeaderValue::from(value));
+}
+
+/// Token bucket limiting per route group, keyed by the client address and,
+/// when a token is sent, by its subject too, against the app's
+/// `RateLimiter`. Used with `wrap_fn` on the app.
+pub fn limit<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let group = RouteGroup::of(req.method(), req.path());
+    let decision = limiter.zip(group).map(|(limiter, group)| {
+        // the peer address rather than X-Forwarded-For, which clients can set
+        let mut keys = vec![format!(
+            "ip:{}",
+            req.peer_addr()
+                .map(|x| x.ip().to_string())
+                .unwrap_or_default()
+        )];
+        if let Some(claims) = routes::validate_token(req.request()) {
+            keys.push(format!("sub:{}", claims.sub));
+        }
+        limiter.take(group, &keys)
+    });
+
+    let fut = match &decision {
+        Some(x) if !x.allowed => {
+            let response = models::APIResponse {
+                message: "too many requests".to_string(),
+            };
+            let mut res = req
+                .into_response(HttpResponse::TooManyRequests().json(response))
+                .map_into_right_body();
+            insert_header(&mut res, RETRY_AFTER, x.retry_after);
+            Err(res)
+        }
+        _ => Ok(srv.call(req)),
+    };
+
+    async move {
+        let mut res = match fut {
+            Ok(fut) => fut.await?.map_into_left_body(),
+            Err(res) => res,
+        };
+
+        if let Some(decision) = decision {
+            insert_header(
+                &mut res,
+                HeaderName::from_static("ratelimit-limit"),
+                decision.limit as u64,
+            );
+            insert_header(
+                &mut res,
+                HeaderName::from_static("ratelimit-remaining"),
+                decision.remaining,
+            );
+            insert_header(
+                &mut res,
+                HeaderName::from_static("ratelimit-reset"),
+                decision.reset,
+            );
+        }
+        Ok(res)
+    }
+}
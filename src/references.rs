@@ -0,0 +1,165 @@
+use crate::database::models::Payment;
+use std::borrow::Cow;
+use validator::{ValidationError, ValidationErrors};
+
+/// A structured format for payment references or receiver codes.
+/// Validators only check payments they recognise, so free-form references
+/// keep working.
+pub trait ReferenceValidator: Sync {
+    fn applies_to(&self, payment: &Payment) -> bool;
+    fn validate(&self, payment: &Payment, errors: &mut ValidationErrors);
+}
+
+/// ISO 11649 creditor reference, e.g. `RF18539007547034`.
+pub struct CreditorReference;
+
+/// IBAN receiver code, e.g. `PT50000201231234567890154`.
+pub struct Iban;
+
+/// Portuguese Multibanco payment: a 5-digit entity as receiver code and a
+/// 9-digit reference whose last two digits are ISO 7064 mod 97-10 check digits.
+pub struct EntityReference;
+
+static VALIDATORS: &[&dyn ReferenceValidator] = &[&CreditorReference, &Iban, &EntityReference];
+
+pub fn validate_payment(payment: &Payment) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+
+    VALIDATORS
+        .iter()
+        .filter(|v| v.applies_to(payment))
+        .for_each(|v| v.validate(payment, &mut errors));
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Borrowed(message));
+    error
+}
+
+fn compact(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// ISO 7064 mod 97-10 over an alphanumeric string, letters counting as 10..35.
+/// Returns `None` when the string holds anything else.
+fn mod97(text: &str) -> Option<u32> {
+    let mut remainder: u32 = 0;
+
+    for c in text.chars() {
+        let value = c.to_digit(36)?;
+        remainder = if value < 10 {
+            (remainder * 10 + value) % 97
+        } else {
+            (remainder * 100 + value) % 97
+        };
+    }
+    Some(remainder)
+}
+
+/// Both RF references and IBANs carry their check digits in positions 3-4
+/// and are valid when the rearranged string is 1 mod 97.
+fn rearranged_mod97_is_valid(text: &str) -> bool {
+    let rearranged = format!("{}{}", &text[4..], &text[..4]);
+    mod97(&rearranged) == Some(1)
+}
+
+impl ReferenceValidator for CreditorReference {
+    fn applies_to(&self, payment: &Payment) -> bool {
+        compact(&payment.reference).starts_with("RF")
+    }
+
+    fn validate(&self, payment: &Payment, errors: &mut ValidationErrors) {
+        let reference = compact(&payment.reference);
+
+        if !(5..=25).contains(&reference.len())
+            || !reference.chars().all(|c| c.is_ascii_alphanumeric())
+            || !reference[2..4].chars().all(|c| c.is_ascii_digit())
+        {
+            errors.add(
+                "reference",
+                error(
+                    "creditor_reference_format",
+                    "RF creditor references are RF, two check digits and up to 21 letters or digits",
+                ),
+            );
+        } else if !rearranged_mod97_is_valid(&reference) {
+            errors.add(
+                "reference",
+                error(
+                    "creditor_reference_checksum",
+                    "RF creditor reference check digits do not match",
+                ),
+            );
+        }
+    }
+}
+
+impl ReferenceValidator for Iban {
+    fn applies_to(&self, payment: &Payment) -> bool {
+        let code = compact(&payment.receiver_code);
+        let mut chars = code.chars();
+
+        code.len() > 4
+            && chars.by_ref().take(2).all(|c| c.is_ascii_alphabetic())
+            && chars.take(2).all(|c| c.is_ascii_digit())
+    }
+
+    fn validate(&self, payment: &Payment, errors: &mut ValidationErrors) {
+        let code = compact(&payment.receiver_code);
+
+        if !(15..=34).contains(&code.len()) || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+            errors.add(
+                "receiverCode",
+                error(
+                    "iban_format",
+                    "IBANs are a country code, two check digits and 11 to 30 letters or digits",
+                ),
+            );
+        } else if !rearranged_mod97_is_valid(&code) {
+            errors.add(
+                "receiverCode",
+                error("iban_checksum", "IBAN check digits do not match"),
+            );
+        }
+    }
+}
+
+impl ReferenceValidator for EntityReference {
+    fn applies_to(&self, payment: &Payment) -> bool {
+        let entity = compact(&payment.receiver_code);
+        entity.len() == 5 && entity.chars().all(|c| c.is_ascii_digit())
+    }
+
+    fn validate(&self, payment: &Payment, errors: &mut ValidationErrors) {
+        let entity = compact(&payment.receiver_code);
+        let reference = compact(&payment.reference);
+
+        if reference.len() != 9 || !reference.chars().all(|c| c.is_ascii_digit()) {
+            errors.add(
+                "reference",
+                error(
+                    "entity_reference_format",
+                    "payments to a 5-digit entity need a 9-digit reference",
+                ),
+            );
+        } else if mod97(&format!("{}{}", entity, reference)) != Some(1) {
+            errors.add(
+                "reference",
+                error(
+                    "entity_reference_checksum",
+                    "reference check digits do not match the entity",
+                ),
+            );
+        }
+    }
+}
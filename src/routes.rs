@@ -1,14 +1,57 @@
+use crate::audit;
+use crate::backup;
+use crate::batch;
+use crate::database::storage::{Backend, Storage, StorageError};
 use crate::database::{crud, models};
+use crate::health;
+use crate::iso20022;
+use crate::metrics;
+use crate::references;
+use crate::stream;
+use actix_web::http::{header, StatusCode};
 use actix_web::{web, HttpRequest, HttpResponse, Responder, Result};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use validator::Validate;
+use std::sync::Mutex;
+use std::time::Instant;
+use validator::{Validate, ValidationErrors};
 
-static SECRET_KEY: &[u8; 6] = b"secret";
-static TOKEN_EXPIRATION_MINUTES: u16 = 1 * 60 * 24;
+/// Signing keys are read from the database at most this often, so a rotation
+/// reaches a running server within this delay.
+static JWT_SECRETS_REFRESH: std::time::Duration = std::time::Duration::from_secs(10);
+static TOKEN_EXPIRATION_MINUTES: u16 = 60 * 24;
+static DEFAULT_PAYEE_COOLING_OFF_HOURS: i64 = 24;
+static DEFAULT_PAYEE_COOLING_OFF_AMOUNT: f64 = 1000.0;
+static UNVERIFIED_TRANSACTION_LIMIT: f64 = 500.0;
+static BASIC_TRANSACTION_LIMIT: f64 = 5000.0;
 
-pub async fn get_jwt() -> impl Responder {
-    let key = SECRET_KEY;
+#[utoipa::path(
+    get,
+    path = "/token",
+    tag = "auth",
+    responses(
+        (status = 200, description = "a signed JWT", body = String, content_type = "text/plain"),
+        (status = 404, description = "token issuing is disabled", body = models::APIResponse)
+    )
+)]
+pub async fn get_jwt(req: HttpRequest) -> impl Responder {
+    if !issues_admin_tokens() {
+        return HttpResponse::NotFound().json(models::APIResponse {
+            message: "token issuing is disabled".to_string(),
+        });
+    }
+
+    let key = match jwt_secrets(&req)
+        .into_iter()
+        .find(|x| x.retired_at.is_none())
+    {
+        Some(x) => x.secret,
+        None => {
+            return HttpResponse::InternalServerError().json(models::APIResponse {
+                message: "no signing key".to_string(),
+            })
+        }
+    };
     let iat = Utc::now().timestamp();
 
     let exp = Utc::now()
@@ -23,7 +66,11 @@ pub async fn get_jwt() -> impl Responder {
         role: "admin".to_owned(),
     };
 
-    let token = match encode(&Header::default(), &claims, &EncodingKey::from_secret(key)) {
+    let token = match encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(key.as_bytes()),
+    ) {
         Ok(x) => x,
         Err(_) => panic!(),
     };
@@ -31,96 +78,282 @@ pub async fn get_jwt() -> impl Responder {
     HttpResponse::Ok().body(token)
 }
 
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "health",
+    responses(
+        (status = 200, description = "server is up", body = String, content_type = "text/plain")
+    )
+)]
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().body("Hello world!")
 }
 
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses(
+        (status = 200, description = "the process is up", body = models::HealthReport)
+    )
+)]
+pub async fn health_live() -> impl Responder {
+    HttpResponse::Ok().json(health::liveness())
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "every component is healthy", body = models::HealthReport),
+        (status = 503, description = "at least one component is failing", body = models::HealthReport)
+    )
+)]
+pub async fn health_ready(backend: web::Data<Backend>) -> impl Responder {
+    let report = health::readiness(&backend);
+
+    if report.status != "ok" {
+        return HttpResponse::ServiceUnavailable().json(report);
+    }
+    HttpResponse::Ok().json(report)
+}
+
+/// Money can only move in or out of active accounts.
+fn validate_status(customer: &models::Customer) -> bool {
+    customer.status.as_deref() == Some(models::CustomerStatus::Active.as_str())
+}
+
+/// Customers who have not gone through KYC can only send small amounts at a
+/// time: withdrawals, transfers and payments are limited, deposits are not.
+fn validate_limit(amount: f64, customer: &models::Customer) -> bool {
+    match customer
+        .kyc_level
+        .as_deref()
+        .and_then(models::KycLevel::parse)
+    {
+        Some(models::KycLevel::Full) => true,
+        Some(models::KycLevel::Basic) => amount <= BASIC_TRANSACTION_LIMIT,
+        _ => amount <= UNVERIFIED_TRANSACTION_LIMIT,
+    }
+}
+
 fn validate_balance(amount: f64, customer: &models::Customer) -> bool {
-    amount <= customer.balance.unwrap()
+    let enough = amount <= customer.balance.unwrap();
+    if !enough {
+        metrics::FAILED_BALANCE_CHECKS.inc();
+    }
+    enough
+}
+
+/// `BANK_ISSUE_ADMIN_TOKENS=1` lets anyone get an admin token from `/token`,
+/// which is only meant for demos and local development.
+fn issues_admin_tokens() -> bool {
+    std::env::var("BANK_ISSUE_ADMIN_TOKENS").is_ok_and(|x| x == "1")
 }
 
-fn validate_transfer(
-    customer_from: &models::Customer,
-    customer_to: &models::Customer,
-    amount: f64,
-) -> bool {
-    let updated_balance_customer_from = crud::update_balance(
-        customer_from.id.unwrap(),
-        customer_from.balance.unwrap() - amount,
-    );
-    let updated_balance_customer_to = crud::update_balance(
-        customer_to.id.unwrap(),
-        customer_to.balance.unwrap() + amount,
-    );
+/// `BANK_PAYEE_COOLING_OFF_HOURS` a newly added payee spends in cooling-off;
+/// 0 disables it.
+fn payee_cooling_off_hours() -> i64 {
+    std::env::var("BANK_PAYEE_COOLING_OFF_HOURS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x >= 0)
+        .unwrap_or(DEFAULT_PAYEE_COOLING_OFF_HOURS)
+}
 
-    let transfer_created =
-        crud::create_transfer(customer_from.id.unwrap(), customer_to.id.unwrap(), amount);
+/// `BANK_PAYEE_COOLING_OFF_AMOUNT`, the most a payee in cooling-off can receive.
+fn payee_cooling_off_amount() -> f64 {
+    std::env::var("BANK_PAYEE_COOLING_OFF_AMOUNT")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x: &f64| *x >= 0.0)
+        .unwrap_or(DEFAULT_PAYEE_COOLING_OFF_AMOUNT)
+}
 
-    updated_balance_customer_from.is_ok()
-        && updated_balance_customer_to.is_ok()
-        && transfer_created.is_ok()
+/// Newly added (or re-pointed) payees cannot receive large amounts until
+/// their cooling-off period has passed.
+fn payee_in_cooling_off(payee: &models::Payee) -> bool {
+    let hours = payee_cooling_off_hours();
+    if hours == 0 {
+        return false;
+    }
+    match payee
+        .created_at
+        .as_deref()
+        .map(DateTime::parse_from_rfc2822)
+    {
+        Some(Ok(created_at)) => {
+            Utc::now().signed_duration_since(created_at) < Duration::hours(hours)
+        }
+        _ => true,
+    }
 }
 
-pub async fn transfer_amount(transfer: web::Json<models::Transfer>) -> impl Responder {
+#[utoipa::path(
+    put,
+    path = "/customers/transfers",
+    tag = "transfers",
+    request_body = models::Transfer,
+    responses(
+        (status = 200, description = "transfer successfull", body = models::APIResponse),
+        (status = 400, description = "not enough balance or over the limit", body = models::APIResponse),
+        (status = 403, description = "a customer is not active", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn transfer_amount(
+    storage: web::Data<dyn Storage>,
+    transfer: web::Json<models::Transfer>,
+) -> impl Responder {
     let mut response = models::APIResponse {
         message: "could not process the transfer".to_string(),
     };
 
-    let validation = transfer.validate();
-    if validation.is_err() {
-        return HttpResponse::UnprocessableEntity().json(validation.err());
+    if let Err(refusal) = check_transfer(storage.get_ref(), &transfer) {
+        return refusal.response();
     }
-    if transfer.id_from == transfer.id_to {
-        response.message = "cannot transfer from and to the same customer".to_string();
-        return HttpResponse::UnprocessableEntity().json(response);
+
+    match storage.create_transfer(transfer.id_from, transfer.id_to, transfer.amount) {
+        Ok(_) => {
+            metrics::TRANSFERS.inc();
+            metrics::TRANSFER_VOLUME.inc_by(transfer.amount);
+            response.message = "transfer successfull".to_string();
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => balance_error(e, response),
     }
+}
 
-    let customer_from = crud::get_customer(transfer.id_from.into());
+/// Why a transfer or payment is turned down, as its route answers.
+pub(crate) enum Refusal {
+    /// Validation errors per field.
+    Invalid(ValidationErrors),
+    Message(StatusCode, String),
+}
 
-    if customer_from.is_err() {
-        response.message = "could not find customer to transfer from".to_string();
-        return HttpResponse::NotFound().json(response);
+impl Refusal {
+    fn new(status: StatusCode, message: &str) -> Refusal {
+        Refusal::Message(status, message.to_string())
     }
 
-    let customer_from = customer_from.unwrap();
+    pub(crate) fn response(self) -> HttpResponse {
+        match self {
+            Refusal::Invalid(errors) => HttpResponse::UnprocessableEntity().json(errors),
+            Refusal::Message(status, message) => {
+                HttpResponse::build(status).json(models::APIResponse { message })
+            }
+        }
+    }
+}
 
-    if !validate_balance(transfer.amount, &customer_from) {
-        response.message = "not enough balance".to_string();
-        return HttpResponse::BadRequest().json(response);
+/// Everything a transfer is checked against before it is made.
+pub(crate) fn check_transfer(
+    storage: &dyn Storage,
+    transfer: &models::Transfer,
+) -> Result<(), Refusal> {
+    transfer.validate().map_err(Refusal::Invalid)?;
+    if transfer.id_from == transfer.id_to {
+        return Err(Refusal::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "cannot transfer from and to the same customer",
+        ));
     }
 
-    let customer_to = crud::get_customer(transfer.id_to.into());
+    let customer_from = storage.get_customer(transfer.id_from).map_err(|_| {
+        Refusal::new(
+            StatusCode::NOT_FOUND,
+            "could not find customer to transfer from",
+        )
+    })?;
+    if !validate_status(&customer_from) {
+        return Err(Refusal::new(
+            StatusCode::FORBIDDEN,
+            "customer to transfer from is not active",
+        ));
+    }
+    if !validate_limit(transfer.amount, &customer_from) {
+        return Err(Refusal::new(
+            StatusCode::BAD_REQUEST,
+            "amount exceeds the limit for the customer's verification level",
+        ));
+    }
+    if !validate_balance(transfer.amount, &customer_from) {
+        return Err(Refusal::new(StatusCode::BAD_REQUEST, "not enough balance"));
+    }
 
-    if customer_to.is_err() {
-        response.message = "could not find customer to tranfer to".to_string();
-        return HttpResponse::NotFound().json(response);
+    let customer_to = storage.get_customer(transfer.id_to).map_err(|_| {
+        Refusal::new(
+            StatusCode::NOT_FOUND,
+            "could not find customer to tranfer to",
+        )
+    })?;
+    if !validate_status(&customer_to) {
+        return Err(Refusal::new(
+            StatusCode::FORBIDDEN,
+            "customer to transfer to is not active",
+        ));
     }
+    Ok(())
+}
 
-    if validate_transfer(&customer_from, &customer_to.unwrap(), transfer.amount) {
-        response.message = "transfer successfull".to_string();
-        return HttpResponse::Ok().json(response);
+/// A debit that a concurrent request got to first fails in storage even
+/// though the balance read earlier covered it.
+pub(crate) fn balance_refusal(e: StorageError, message: String) -> Refusal {
+    if let StorageError::InsufficientFunds = e {
+        metrics::FAILED_BALANCE_CHECKS.inc();
+        return Refusal::new(StatusCode::BAD_REQUEST, "not enough balance");
     }
-    HttpResponse::BadRequest().json(response)
+    Refusal::Message(StatusCode::BAD_REQUEST, message)
+}
+
+fn balance_error(e: StorageError, response: models::APIResponse) -> HttpResponse {
+    balance_refusal(e, response.message).response()
 }
 
-pub async fn get_customer(id: web::Path<u16>) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/customers/{id}",
+    tag = "customers",
+    params(("id" = u16, Path, description = "customer id")),
+    responses(
+        (status = 200, description = "the customer", body = models::Customer),
+        (status = 404, description = "customer not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_customer(storage: web::Data<dyn Storage>, id: web::Path<u16>) -> impl Responder {
     let response = models::APIResponse {
         message: "could not get customer".to_string(),
     };
 
-    match crud::get_customer(*id) {
+    match storage.get_customer(*id) {
         Ok(x) => HttpResponse::Ok().json(x),
         Err(_e) => HttpResponse::NotFound().json(response),
     }
 }
 
-pub async fn get_transfers_by_customer(id: web::Path<u16>) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/transfers",
+    tag = "transfers",
+    params(("id" = u16, Path, description = "customer id")),
+    responses(
+        (status = 200, description = "transfers sent by the customer", body = [models::TransferHuman]),
+        (status = 404, description = "customer not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_transfers_by_customer(
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+) -> impl Responder {
     let mut response = models::APIResponse {
         message: "could not get customer".to_string(),
     };
 
-    match crud::get_customer(*id) {
-        Ok(x) => match crud::get_transfers_by_customer(x.id.unwrap()) {
+    match storage.get_customer(*id) {
+        Ok(x) => match storage.get_transfers_by_customer(x.id.unwrap()) {
             Ok(x) => HttpResponse::Ok().json(x),
             Err(_) => {
                 response.message = "could not get transfers".to_string();
@@ -131,13 +364,26 @@ pub async fn get_transfers_by_customer(id: web::Path<u16>) -> impl Responder {
     }
 }
 
-pub async fn get_payments_by_customer(id: web::Path<u16>) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/payments",
+    tag = "payments",
+    params(("id" = u16, Path, description = "customer id")),
+    responses(
+        (status = 200, description = "payments made by the customer", body = [models::Payment]),
+        (status = 404, description = "customer not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_payments_by_customer(
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+) -> impl Responder {
     let mut response = models::APIResponse {
         message: "could not get customer".to_string(),
     };
 
-    match crud::get_customer(*id) {
-        Ok(x) => match crud::get_payments_by_customer(x.id.unwrap()) {
+    match storage.get_customer(*id) {
+        Ok(x) => match storage.get_payments_by_customer(x.id.unwrap()) {
             Ok(x) => HttpResponse::Ok().json(x),
             Err(_) => {
                 response.message = "could not get payments".to_string();
@@ -148,30 +394,88 @@ pub async fn get_payments_by_customer(id: web::Path<u16>) -> impl Responder {
     }
 }
 
-pub fn validate_token(req: HttpRequest) -> Option<models::Claims> {
-    let key = SECRET_KEY;
-    let token = req.headers().get("authorization");
-    if token.is_none() {
-        return None;
-    }
-    let token = token
-        .unwrap()
+pub fn validate_token(req: &HttpRequest) -> Option<models::Claims> {
+    let token = req
+        .headers()
+        .get("authorization")?
         .to_str()
-        .unwrap()
+        .ok()?
         .split_whitespace()
-        .nth(1)
-        .unwrap();
+        .nth(1)?;
 
-    match decode::<models::Claims>(
-        &token,
-        &DecodingKey::from_secret(key),
-        &Validation::default(),
-    ) {
-        Ok(x) => Some(x.claims),
-        Err(_) => None,
+    decode_token(req, token)
+}
+
+/// The signing keys of the app's storage, as last read and when.
+#[derive(Default)]
+pub struct SigningKeys(Mutex<Option<(Instant, Vec<models::JwtSecret>)>>);
+
+/// The current signing key of the app's storage and the retired ones that may
+/// still have signed unexpired tokens, newest first.
+fn jwt_secrets(req: &HttpRequest) -> Vec<models::JwtSecret> {
+    let (Some(keys), Some(storage)) = (
+        req.app_data::<web::Data<SigningKeys>>(),
+        req.app_data::<web::Data<dyn Storage>>(),
+    ) else {
+        return Vec::new();
+    };
+    let mut cache = keys.0.lock().unwrap();
+
+    if !matches!(&*cache, Some((x, _)) if x.elapsed() < JWT_SECRETS_REFRESH) {
+        if let Ok(secrets) = storage.get_jwt_secrets() {
+            *cache = Some((Instant::now(), secrets));
+        }
     }
+
+    let oldest_valid = Utc::now().timestamp() - TOKEN_EXPIRATION_MINUTES as i64 * 60;
+    let Some((_, secrets)) = &*cache else {
+        return Vec::new();
+    };
+    secrets
+        .iter()
+        .filter(|x| {
+            x.retired_at
+                .is_none_or(|retired_at| retired_at > oldest_valid)
+        })
+        .cloned()
+        .collect()
+}
+
+fn decode_token(req: &HttpRequest, token: &str) -> Option<models::Claims> {
+    jwt_secrets(req).iter().find_map(|key| {
+        decode::<models::Claims>(
+            token,
+            &DecodingKey::from_secret(key.secret.as_bytes()),
+            &Validation::default(),
+        )
+        .ok()
+        .map(|x| x.claims)
+    })
+}
+
+fn is_admin(req: &HttpRequest) -> bool {
+    matches!(validate_token(req), Some(claims) if claims.role == "admin")
 }
-pub async fn withdraw(money: web::Json<models::Money>, id: web::Path<u16>) -> impl Responder {
+
+#[utoipa::path(
+    put,
+    path = "/customers/{id}/withdrawals",
+    tag = "customers",
+    params(("id" = u16, Path, description = "customer id")),
+    request_body = models::Money,
+    responses(
+        (status = 200, description = "withdrawal successfull", body = models::APIResponse),
+        (status = 400, description = "not enough balance or over the limit", body = models::APIResponse),
+        (status = 403, description = "customer is not active", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn withdraw(
+    storage: web::Data<dyn Storage>,
+    money: web::Json<models::Money>,
+    id: web::Path<u16>,
+) -> impl Responder {
     let mut response = models::APIResponse {
         message: "could not withdraw".to_string(),
     };
@@ -181,32 +485,58 @@ pub async fn withdraw(money: web::Json<models::Money>, id: web::Path<u16>) -> im
         return HttpResponse::UnprocessableEntity().json(validation.err());
     }
 
-    let customer_found = crud::get_customer(*id);
+    let customer_found = storage.get_customer(*id);
 
     match customer_found {
         Err(_) => {
             response.message = "could not find customer".to_string();
-            return HttpResponse::NotFound().json(response);
+            HttpResponse::NotFound().json(response)
         }
         Ok(x) => {
+            if !validate_status(&x) {
+                response.message = "customer account is not active".to_string();
+                return HttpResponse::Forbidden().json(response);
+            }
+            if !validate_limit(money.amount, &x) {
+                response.message =
+                    "amount exceeds the limit for the customer's verification level".to_string();
+                return HttpResponse::BadRequest().json(response);
+            }
             if !validate_balance(money.amount, &x) {
                 response.message = "not enough balance".to_string();
                 return HttpResponse::BadRequest().json(response);
             }
-            let balance = x.balance.unwrap() - money.amount;
-
-            match crud::update_balance(x.id.unwrap(), balance) {
-                Err(_) => return HttpResponse::BadRequest().json(response),
+            match storage.create_movement(x.id.unwrap(), "withdrawal", -money.amount) {
+                Err(e) => balance_error(e, response),
                 Ok(_) => {
+                    metrics::WITHDRAWALS.inc();
+                    metrics::WITHDRAWAL_VOLUME.inc_by(money.amount);
                     response.message = "withdrawal successfull".to_string();
-                    return HttpResponse::Ok().json(response);
+                    HttpResponse::Ok().json(response)
                 }
             }
         }
     }
 }
 
-pub async fn deposit(money: web::Json<models::Money>, id: web::Path<u16>) -> impl Responder {
+#[utoipa::path(
+    put,
+    path = "/customers/{id}/deposits",
+    tag = "customers",
+    params(("id" = u16, Path, description = "customer id")),
+    request_body = models::Money,
+    responses(
+        (status = 200, description = "deposit successfull", body = models::APIResponse),
+        (status = 403, description = "customer is not active", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn deposit(
+    storage: web::Data<dyn Storage>,
+    money: web::Json<models::Money>,
+    id: web::Path<u16>,
+) -> impl Responder {
     let mut response = models::APIResponse {
         message: "could not deposit".to_string(),
     };
@@ -216,7 +546,7 @@ pub async fn deposit(money: web::Json<models::Money>, id: web::Path<u16>) -> imp
         return HttpResponse::UnprocessableEntity().json(validation.err());
     }
 
-    let customer_found = crud::get_customer(*id);
+    let customer_found = storage.get_customer(*id);
 
     match customer_found {
         Err(_) => {
@@ -224,9 +554,16 @@ pub async fn deposit(money: web::Json<models::Money>, id: web::Path<u16>) -> imp
             return HttpResponse::NotFound().json(response);
         }
         Ok(x) => {
-            let balance = x.balance.unwrap() + money.amount;
-
-            if crud::update_balance(x.id.unwrap(), balance).is_ok() {
+            if !validate_status(&x) {
+                response.message = "customer account is not active".to_string();
+                return HttpResponse::Forbidden().json(response);
+            }
+            if storage
+                .create_movement(x.id.unwrap(), "deposit", money.amount)
+                .is_ok()
+            {
+                metrics::DEPOSITS.inc();
+                metrics::DEPOSIT_VOLUME.inc_by(money.amount);
                 response.message = "deposit successfull".to_string();
                 return HttpResponse::Ok().json(response);
             };
@@ -235,7 +572,20 @@ pub async fn deposit(money: web::Json<models::Money>, id: web::Path<u16>) -> imp
     HttpResponse::BadRequest().json(response)
 }
 
+#[utoipa::path(
+    put,
+    path = "/customers/{id}",
+    tag = "customers",
+    params(("id" = u16, Path, description = "customer id")),
+    request_body = models::CustomerEdit,
+    responses(
+        (status = 200, description = "customer edited", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
 pub async fn edit_customer(
+    storage: web::Data<dyn Storage>,
     customer: web::Json<models::CustomerEdit>,
     id: web::Path<u16>,
 ) -> impl Responder {
@@ -243,7 +593,7 @@ pub async fn edit_customer(
         message: "could not edit customer".to_string(),
     };
 
-    let customer_found = crud::get_customer(*id);
+    let customer_found = storage.get_customer(*id);
 
     if customer_found.is_err() {
         response.message = "could not find customer".to_string();
@@ -255,7 +605,46 @@ pub async fn edit_customer(
         return HttpResponse::UnprocessableEntity().json(validation.err());
     }
 
-    if crud::edit_customer(*id, customer.into_inner()).is_ok() {
+    if storage.edit_customer(*id, &customer).is_ok() {
+        response.message = "customer edited".to_string();
+        return HttpResponse::Ok().json(response);
+    }
+
+    HttpResponse::BadRequest().json(response)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/customers/{id}",
+    tag = "customers",
+    params(("id" = u16, Path, description = "customer id")),
+    request_body = models::CustomerPatch,
+    responses(
+        (status = 200, description = "customer edited", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn patch_customer(
+    storage: web::Data<dyn Storage>,
+    customer: web::Json<models::CustomerPatch>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not edit customer".to_string(),
+    };
+
+    if storage.get_customer(*id).is_err() {
+        response.message = "could not find customer".to_string();
+        return HttpResponse::NotFound().json(response);
+    }
+
+    let validation = customer.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
+    }
+
+    if storage.patch_customer(*id, &customer).is_ok() {
         response.message = "customer edited".to_string();
         return HttpResponse::Ok().json(response);
     }
@@ -263,12 +652,24 @@ pub async fn edit_customer(
     HttpResponse::BadRequest().json(response)
 }
 
-pub async fn get_all_customers(_req: HttpRequest) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/customers",
+    tag = "customers",
+    responses(
+        (status = 200, description = "all customers", body = [models::Customer]),
+        (status = 400, description = "could not get customers", body = models::APIResponse)
+    )
+)]
+pub async fn get_all_customers(
+    storage: web::Data<dyn Storage>,
+    _req: HttpRequest,
+) -> impl Responder {
     // if validate_token(req).is_none() {
     //     return HttpResponse::BadRequest().json("Missing or invalid Token");
     // }
 
-    let customer_list = crud::get_all_customers();
+    let customer_list = storage.get_all_customers();
 
     match customer_list {
         Ok(x) => HttpResponse::Ok().json(x),
@@ -281,8 +682,17 @@ pub async fn get_all_customers(_req: HttpRequest) -> impl Responder {
     }
 }
 
-pub async fn get_all_transfers() -> impl Responder {
-    let record_list = crud::get_all_transfers();
+#[utoipa::path(
+    get,
+    path = "/transfers",
+    tag = "transfers",
+    responses(
+        (status = 200, description = "all transfers", body = [models::TransferHuman]),
+        (status = 400, description = "could not get transfers", body = models::APIResponse)
+    )
+)]
+pub async fn get_all_transfers(storage: web::Data<dyn Storage>) -> impl Responder {
+    let record_list = storage.get_all_transfers();
 
     match record_list {
         Ok(x) => HttpResponse::Ok().json(x),
@@ -295,7 +705,20 @@ pub async fn get_all_transfers() -> impl Responder {
     }
 }
 
-pub async fn create_customer(mut customer: web::Json<models::Customer>) -> Result<impl Responder> {
+#[utoipa::path(
+    post,
+    path = "/customers",
+    tag = "customers",
+    request_body = models::Customer,
+    responses(
+        (status = 200, description = "customer created", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn create_customer(
+    storage: web::Data<dyn Storage>,
+    mut customer: web::Json<models::Customer>,
+) -> Result<impl Responder> {
     let mut response = models::APIResponse {
         message: "customer not created".to_string(),
     };
@@ -306,16 +729,31 @@ pub async fn create_customer(mut customer: web::Json<models::Customer>) -> Resul
         return Ok(HttpResponse::UnprocessableEntity().json(created_customer.validate().err()));
     }
 
-    match crud::create_customer(&created_customer) {
+    match storage.create_customer(&created_customer) {
         Ok(_x) => {
             response.message = "customer created".to_string();
-            return Ok(HttpResponse::Ok().json(response));
+            Ok(HttpResponse::Ok().json(response))
         }
         Err(_e) => Ok(HttpResponse::BadRequest().json(response)),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/customers/{id}/payments",
+    tag = "payments",
+    params(("id" = u16, Path, description = "customer id")),
+    request_body = models::Payment,
+    responses(
+        (status = 200, description = "payment successfull", body = models::APIResponse),
+        (status = 400, description = "not enough balance, over the limit or payee cooling off", body = models::APIResponse),
+        (status = 403, description = "customer is not active", body = models::APIResponse),
+        (status = 404, description = "customer or payee not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
 pub async fn create_payment(
+    storage: web::Data<dyn Storage>,
     payment: web::Json<models::Payment>,
     id: web::Path<u16>,
 ) -> impl Responder {
@@ -324,31 +762,1397 @@ pub async fn create_payment(
     };
     let mut created_payment = payment.into_inner();
 
-    if created_payment.validate().is_err() {
-        return HttpResponse::UnprocessableEntity().json(created_payment.validate().err());
+    if let Err(refusal) = check_payment(storage.get_ref(), *id, &mut created_payment) {
+        return refusal.response();
+    }
+    created_payment.created_at = Some(Utc::now().to_rfc2822());
+
+    match storage.create_payment(&created_payment) {
+        Ok(_) => {
+            metrics::PAYMENTS.inc();
+            metrics::PAYMENT_VOLUME.inc_by(created_payment.amount);
+            response.message = "payment successfull".to_string();
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => balance_error(e, response),
+    }
+}
+
+/// Everything a payment by customer `id` is checked against before it is
+/// made. The payment is completed on the way: the receiver code of its payee
+/// and the customer id are filled in.
+pub(crate) fn check_payment(
+    storage: &dyn Storage,
+    id: u16,
+    payment: &mut models::Payment,
+) -> Result<(), Refusal> {
+    payment.validate().map_err(Refusal::Invalid)?;
+
+    let customer = storage
+        .get_customer(id)
+        .map_err(|_| Refusal::new(StatusCode::NOT_FOUND, "could not find customer"))?;
+    if !validate_status(&customer) {
+        return Err(Refusal::new(
+            StatusCode::FORBIDDEN,
+            "customer account is not active",
+        ));
+    }
+    if let Some(payee_id) = payment.payee_id {
+        let payee = storage
+            .get_payee(customer.id.unwrap(), payee_id)
+            .map_err(|_| Refusal::new(StatusCode::NOT_FOUND, "could not find payee"))?;
+        if payment.amount > payee_cooling_off_amount() && payee_in_cooling_off(&payee) {
+            return Err(Refusal::new(
+                StatusCode::BAD_REQUEST,
+                "payee is still in its cooling-off period",
+            ));
+        }
+        payment.receiver_code = payee.account().to_string();
+    } else if payment.receiver_code.is_empty() {
+        return Err(Refusal::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "receiverCode or payeeId is required",
+        ));
+    }
+
+    references::validate_payment(payment).map_err(Refusal::Invalid)?;
+
+    if !validate_limit(payment.amount, &customer) {
+        return Err(Refusal::new(
+            StatusCode::BAD_REQUEST,
+            "amount exceeds the limit for the customer's verification level",
+        ));
+    }
+    if !validate_balance(payment.amount, &customer) {
+        return Err(Refusal::new(StatusCode::BAD_REQUEST, "not enough balance"));
+    }
+    payment.customer_id = customer.id;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/transfers/{id}/reversals",
+    tag = "admin",
+    params(("id" = u16, Path, description = "transfer id")),
+    security(("bearer" = [])),
+    request_body = models::Reversal,
+    responses(
+        (status = 200, description = "transfer reversed", body = models::APIResponse),
+        (status = 400, description = "already reversed or amount too high", body = models::APIResponse),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 403, description = "a customer is not active", body = models::APIResponse),
+        (status = 404, description = "transfer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn reverse_transfer(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    reversal: web::Json<models::Reversal>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not reverse transfer".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let validation = reversal.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
+    }
+
+    let transfer = match storage.get_transfer(*id) {
+        Ok(x) => x,
+        Err(_) => {
+            response.message = "could not find transfer".to_string();
+            return HttpResponse::NotFound().json(response);
+        }
+    };
+
+    if ![transfer.id_from, transfer.id_to]
+        .iter()
+        .all(|x| matches!(storage.get_customer(*x), Ok(c) if validate_status(&c)))
+    {
+        response.message = "customer account is not active".to_string();
+        return HttpResponse::Forbidden().json(response);
+    }
+
+    match storage.reverse_transfer(*id, reversal.amount) {
+        Ok(amount) => {
+            response.message = format!("transfer reversed: {}", amount);
+            HttpResponse::Ok().json(response)
+        }
+        Err(StorageError::InsufficientFunds) => {
+            response.message =
+                "transfer already reversed, amount too high or not enough balance".to_string();
+            HttpResponse::BadRequest().json(response)
+        }
+        Err(_) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/payments/{id}/reversals",
+    tag = "admin",
+    params(("id" = u16, Path, description = "payment id")),
+    security(("bearer" = [])),
+    request_body = models::Reversal,
+    responses(
+        (status = 200, description = "payment reversed", body = models::APIResponse),
+        (status = 400, description = "already reversed or amount too high", body = models::APIResponse),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 403, description = "customer is not active", body = models::APIResponse),
+        (status = 404, description = "payment not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn reverse_payment(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    reversal: web::Json<models::Reversal>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not reverse payment".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let validation = reversal.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
     }
 
-    match crud::get_customer(*id) {
+    let payment = match storage.get_payment(*id) {
+        Ok(x) => x,
         Err(_) => {
-            response.message = "could not find customer".to_string();
+            response.message = "could not find payment".to_string();
             return HttpResponse::NotFound().json(response);
         }
-        Ok(x) => {
-            if !validate_balance(created_payment.amount, &x) {
-                response.message = "not enough balance".to_string();
-                return HttpResponse::BadRequest().json(response);
-            }
-            let balance = x.balance.unwrap() - created_payment.amount;
-            created_payment.created_at = Some(Utc::now().to_rfc2822());
-            created_payment.customer_id = x.id;
+    };
 
-            match crud::create_payment(&created_payment, balance) {
-                Ok(_) => {
-                    response.message = "payment successfull".to_string();
-                    return HttpResponse::Ok().json(response);
-                }
-                Err(_) => return HttpResponse::BadRequest().json(response),
+    if !matches!(storage.get_customer(payment.customer_id.unwrap()), Ok(c) if validate_status(&c)) {
+        response.message = "customer account is not active".to_string();
+        return HttpResponse::Forbidden().json(response);
+    }
+
+    match storage.reverse_payment(*id, reversal.amount) {
+        Ok(amount) => {
+            response.message = format!("payment reversed: {}", amount);
+            HttpResponse::Ok().json(response)
+        }
+        Err(StorageError::InsufficientFunds) => {
+            response.message = "payment already reversed or amount too high".to_string();
+            HttpResponse::BadRequest().json(response)
+        }
+        Err(_) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/reversals",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "all reversals", body = [models::Reversal]),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse)
+    )
+)]
+pub async fn get_all_reversals(
+    storage: web::Data<dyn Storage>,
+    req: HttpRequest,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not get reversals".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match storage.get_reversals() {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(_e) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/batches",
+    tag = "batches",
+    params(models::BatchOptions),
+    request_body(
+        description = "JSON, or CSV whose header row names the same fields",
+        content(
+            (models::BatchUpload = "application/json"),
+            (String = "text/csv", example = "kind,customerId,idTo,amount,receiverCode,reference\npayment,1,,25,12345678,INV-1\ntransfer,1,2,10,,")
+        )
+    ),
+    responses(
+        (status = 200, description = "batch run, see the status of each line", body = models::Batch),
+        (status = 422, description = "the upload cannot be read, or nothing was made", body = models::Batch),
+        (status = 500, description = "the report could not be saved; it is answered with the lines made", body = models::Batch)
+    )
+)]
+pub async fn create_batch(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    options: web::Query<models::BatchOptions>,
+    body: web::Bytes,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not read the batch".to_string(),
+    };
+
+    let mode = options.mode.as_deref().unwrap_or("atomic");
+    let Some(mode) = models::BatchMode::parse(mode) else {
+        response.message = "mode must be atomic or best-effort".to_string();
+        return HttpResponse::UnprocessableEntity().json(response);
+    };
+
+    let csv = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.starts_with("text/csv"));
+    let lines = if csv {
+        std::str::from_utf8(&body)
+            .map_err(|e| e.to_string())
+            .and_then(batch::parse_csv)
+    } else {
+        serde_json::from_slice::<models::BatchUpload>(&body)
+            .map(|x| x.lines)
+            .map_err(|e| e.to_string())
+    };
+    let lines = match lines {
+        Ok(x) => x,
+        Err(e) => {
+            response.message = format!("could not read the batch: {}", e);
+            return HttpResponse::UnprocessableEntity().json(response);
+        }
+    };
+    let format = if csv { "csv" } else { "json" };
+    run_batch(storage, lines, mode, format).await
+}
+
+/// Runs the lines of an upload as a batch and answers with its report.
+async fn run_batch(
+    storage: web::Data<dyn Storage>,
+    lines: Vec<serde_json::Value>,
+    mode: models::BatchMode,
+    format: &'static str,
+) -> HttpResponse {
+    let mut response = models::APIResponse {
+        message: "the batch has no lines".to_string(),
+    };
+    if lines.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(response);
+    }
+    if lines.len() > batch::MAX_LINES {
+        response.message = format!("a batch holds at most {} lines", batch::MAX_LINES);
+        return HttpResponse::UnprocessableEntity().json(response);
+    }
+
+    // the blocking pool runs outside of the request's audit and stream scopes
+    let context = audit::current();
+    let streams = stream::current().unwrap_or_default();
+    let run = web::block(move || {
+        audit::CONTEXT.sync_scope(context, || {
+            stream::STREAMS.sync_scope(streams, || {
+                batch::run(storage.get_ref(), &lines, mode, format)
+            })
+        })
+    });
+    match run.await {
+        Ok(Ok(report)) if report.status == "rejected" => {
+            HttpResponse::UnprocessableEntity().json(report)
+        }
+        Ok(Ok(report)) => HttpResponse::Ok().json(report),
+        Ok(Err(batch::Unsaved::NotStarted(e))) => {
+            tracing::error!(error = %e, "could not start the batch");
+            response.message = "could not start the batch".to_string();
+            HttpResponse::InternalServerError().json(response)
+        }
+        // lines may have been made, so the report is still answered
+        Ok(Err(batch::Unsaved::Lost(report, e))) => {
+            tracing::error!(error = %e, batch = ?report.id, "could not save the batch report");
+            HttpResponse::InternalServerError().json(report)
+        }
+        Err(_) => {
+            response.message = "could not run the batch".to_string();
+            HttpResponse::InternalServerError().json(response)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/batches/{id}",
+    tag = "batches",
+    params(("id" = u32, Path, description = "batch id")),
+    responses(
+        (status = 200, description = "the report of the batch", body = models::Batch),
+        (status = 404, description = "batch not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_batch(storage: web::Data<dyn Storage>, id: web::Path<u32>) -> impl Responder {
+    let response = models::APIResponse {
+        message: "could not find batch".to_string(),
+    };
+
+    match storage.get_batch_report(*id) {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(_) => HttpResponse::NotFound().json(response),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/customers/{id}/pain.001",
+    tag = "payments",
+    params(("id" = u16, Path, description = "customer id, the debtor of every credit transfer"), models::BatchOptions),
+    request_body(
+        description = "ISO 20022 pain.001 credit transfer initiation, any version",
+        content((String = "application/xml"))
+    ),
+    responses(
+        (status = 200, description = "credit transfers run as a batch of payments, see the status of each line", body = models::Batch),
+        (status = 422, description = "the file cannot be read, or nothing was made", body = models::Batch),
+        (status = 500, description = "the report could not be saved; it is answered with the lines made", body = models::Batch)
+    )
+)]
+pub async fn import_pain001(
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+    options: web::Query<models::BatchOptions>,
+    body: web::Bytes,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not read the pain.001 file".to_string(),
+    };
+
+    let mode = options.mode.as_deref().unwrap_or("atomic");
+    let Some(mode) = models::BatchMode::parse(mode) else {
+        response.message = "mode must be atomic or best-effort".to_string();
+        return HttpResponse::UnprocessableEntity().json(response);
+    };
+
+    let payments = std::str::from_utf8(&body)
+        .map_err(|e| e.to_string())
+        .and_then(|x| iso20022::read_pain001(x, *id));
+    let payments = match payments {
+        Ok(x) => x,
+        Err(e) => {
+            response.message = format!("could not read the pain.001 file: {}", e);
+            return HttpResponse::UnprocessableEntity().json(response);
+        }
+    };
+
+    // each credit transfer goes through the same checks as a payment line
+    let lines: Vec<serde_json::Value> = payments
+        .into_iter()
+        .map(|x| {
+            serde_json::json!({
+                "kind": "payment",
+                "customerId": *id,
+                "amount": x.amount,
+                "receiverCode": x.receiver_code,
+                "reference": x.reference,
+                "note": x.note,
+            })
+        })
+        .collect();
+    run_batch(storage, lines, mode, "pain.001").await
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/camt.053",
+    tag = "customers",
+    params(("id" = u16, Path, description = "customer id"), models::StatementDay),
+    responses(
+        (status = 200, description = "ISO 20022 camt.053 end-of-day statement", body = String, content_type = "application/xml"),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "invalid date", body = models::APIResponse)
+    )
+)]
+pub async fn export_camt053(
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+    query: web::Query<models::StatementDay>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not find customer".to_string(),
+    };
+
+    let day = match query.date.as_deref() {
+        None => Utc::now().date_naive(),
+        Some(x) => match NaiveDate::parse_from_str(x, "%Y-%m-%d") {
+            Ok(x) => x,
+            Err(_) => {
+                response.message = "date must be YYYY-MM-DD".to_string();
+                return HttpResponse::UnprocessableEntity().json(response);
             }
+        },
+    };
+
+    let Ok(statement) = storage.get_statement(*id, Some(day), Some(day)) else {
+        return HttpResponse::NotFound().json(response);
+    };
+    match storage.get_bookings(*id) {
+        Ok(bookings) => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(iso20022::write_camt053(&statement, &bookings, day)),
+        Err(_) => {
+            response.message = "could not build the statement".to_string();
+            HttpResponse::BadRequest().json(response)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/customers/{id}/payees",
+    tag = "payees",
+    params(("id" = u16, Path, description = "customer id")),
+    request_body = models::Payee,
+    responses(
+        (status = 200, description = "payee created", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn create_payee(
+    storage: web::Data<dyn Storage>,
+    payee: web::Json<models::Payee>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "payee not created".to_string(),
+    };
+    let mut created_payee = payee.into_inner();
+
+    if created_payee.validate().is_err() {
+        return HttpResponse::UnprocessableEntity().json(created_payee.validate().err());
+    }
+
+    match storage.get_customer(*id) {
+        Err(_) => {
+            response.message = "could not find customer".to_string();
+            HttpResponse::NotFound().json(response)
+        }
+        Ok(x) => {
+            created_payee.created_at = Some(Utc::now().to_rfc2822());
+            created_payee.customer_id = x.id;
+
+            match storage.create_payee(&created_payee) {
+                Ok(_) => {
+                    response.message = "payee created".to_string();
+                    HttpResponse::Ok().json(response)
+                }
+                Err(_) => HttpResponse::BadRequest().json(response),
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/payees",
+    tag = "payees",
+    params(("id" = u16, Path, description = "customer id")),
+    responses(
+        (status = 200, description = "the customer's payees", body = [models::Payee]),
+        (status = 404, description = "customer not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_payees_by_customer(
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not get customer".to_string(),
+    };
+
+    match storage.get_customer(*id) {
+        Ok(x) => match storage.get_payees_by_customer(x.id.unwrap()) {
+            Ok(x) => HttpResponse::Ok().json(x),
+            Err(_) => {
+                response.message = "could not get payees".to_string();
+                HttpResponse::NotFound().json(response)
+            }
+        },
+        Err(_) => HttpResponse::NotFound().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/payees/{payee_id}",
+    tag = "payees",
+    params(("id" = u16, Path, description = "customer id"), ("payee_id" = u16, Path, description = "payee id")),
+    responses(
+        (status = 200, description = "the payee", body = models::Payee),
+        (status = 404, description = "payee not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_payee(
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(u16, u16)>,
+) -> impl Responder {
+    let response = models::APIResponse {
+        message: "could not get payee".to_string(),
+    };
+    let (id, payee_id) = path.into_inner();
+
+    match storage.get_payee(id, payee_id) {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(_e) => HttpResponse::NotFound().json(response),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/customers/{id}/payees/{payee_id}",
+    tag = "payees",
+    params(("id" = u16, Path, description = "customer id"), ("payee_id" = u16, Path, description = "payee id")),
+    request_body = models::Payee,
+    responses(
+        (status = 200, description = "payee edited", body = models::APIResponse),
+        (status = 404, description = "payee not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn edit_payee(
+    storage: web::Data<dyn Storage>,
+    payee: web::Json<models::Payee>,
+    path: web::Path<(u16, u16)>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not edit payee".to_string(),
+    };
+    let (id, payee_id) = path.into_inner();
+
+    if storage.get_payee(id, payee_id).is_err() {
+        response.message = "could not find payee".to_string();
+        return HttpResponse::NotFound().json(response);
+    }
+
+    let validation = payee.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
+    }
+
+    if storage.edit_payee(id, payee_id, &payee).is_ok() {
+        response.message = "payee edited".to_string();
+        return HttpResponse::Ok().json(response);
+    }
+
+    HttpResponse::BadRequest().json(response)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/customers/{id}/payees/{payee_id}",
+    tag = "payees",
+    params(("id" = u16, Path, description = "customer id"), ("payee_id" = u16, Path, description = "payee id")),
+    responses(
+        (status = 200, description = "payee deleted", body = models::APIResponse),
+        (status = 404, description = "payee not found", body = models::APIResponse)
+    )
+)]
+pub async fn delete_payee(
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(u16, u16)>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not delete payee".to_string(),
+    };
+    let (id, payee_id) = path.into_inner();
+
+    if storage.get_payee(id, payee_id).is_err() {
+        response.message = "could not find payee".to_string();
+        return HttpResponse::NotFound().json(response);
+    }
+
+    if storage.delete_payee(id, payee_id).is_ok() {
+        response.message = "payee deleted".to_string();
+        return HttpResponse::Ok().json(response);
+    }
+
+    HttpResponse::BadRequest().json(response)
+}
+
+fn change_status(
+    req: HttpRequest,
+    storage: &dyn Storage,
+    status_change: models::StatusChange,
+    id: u16,
+    next: models::CustomerStatus,
+) -> HttpResponse {
+    let mut response = models::APIResponse {
+        message: format!("could not change customer status to {}", next.as_str()),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let validation = status_change.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
+    }
+
+    let customer = match storage.get_customer(id) {
+        Ok(x) => x,
+        Err(_) => {
+            response.message = "could not find customer".to_string();
+            return HttpResponse::NotFound().json(response);
+        }
+    };
+
+    let current = customer
+        .status
+        .as_deref()
+        .and_then(models::CustomerStatus::parse);
+
+    if !matches!(current, Some(x) if x.can_become(next)) {
+        response.message = format!(
+            "customer account cannot go from {} to {}",
+            customer.status.as_deref().unwrap_or("unknown"),
+            next.as_str()
+        );
+        return HttpResponse::Conflict().json(response);
+    }
+
+    let payout = match next {
+        models::CustomerStatus::Closed => status_change.payout_receiver_code.as_deref(),
+        _ => None,
+    };
+
+    if next == models::CustomerStatus::Closed
+        && customer.balance.unwrap() != 0.0
+        && payout.is_none()
+    {
+        response.message = "balance must be zero or a payoutReceiverCode must be given".to_string();
+        return HttpResponse::BadRequest().json(response);
+    }
+
+    match storage.change_status(&customer, next, &status_change.reason, payout) {
+        Ok(_) => {
+            response.message = format!("customer status changed to {}", next.as_str());
+            HttpResponse::Ok().json(response)
+        }
+        Err(_) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/customers/{id}/freeze",
+    tag = "admin",
+    params(("id" = u16, Path, description = "customer id")),
+    security(("bearer" = [])),
+    request_body = models::StatusChange,
+    responses(
+        (status = 200, description = "customer status changed", body = models::APIResponse),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 409, description = "transition not allowed", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn freeze_customer(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    status_change: web::Json<models::StatusChange>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    change_status(
+        req,
+        storage.get_ref(),
+        status_change.into_inner(),
+        *id,
+        models::CustomerStatus::Frozen,
+    )
+}
+
+#[utoipa::path(
+    put,
+    path = "/customers/{id}/unfreeze",
+    tag = "admin",
+    params(("id" = u16, Path, description = "customer id")),
+    security(("bearer" = [])),
+    request_body = models::StatusChange,
+    responses(
+        (status = 200, description = "customer status changed", body = models::APIResponse),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 409, description = "transition not allowed", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn unfreeze_customer(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    status_change: web::Json<models::StatusChange>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    change_status(
+        req,
+        storage.get_ref(),
+        status_change.into_inner(),
+        *id,
+        models::CustomerStatus::Active,
+    )
+}
+
+#[utoipa::path(
+    put,
+    path = "/customers/{id}/close",
+    tag = "admin",
+    params(("id" = u16, Path, description = "customer id")),
+    security(("bearer" = [])),
+    request_body = models::StatusChange,
+    responses(
+        (status = 200, description = "customer status changed", body = models::APIResponse),
+        (status = 400, description = "balance is not zero and no payout was given", body = models::APIResponse),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 409, description = "transition not allowed", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn close_customer(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    status_change: web::Json<models::StatusChange>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    change_status(
+        req,
+        storage.get_ref(),
+        status_change.into_inner(),
+        *id,
+        models::CustomerStatus::Closed,
+    )
+}
+
+#[utoipa::path(
+    put,
+    path = "/customers/{id}/kyc",
+    tag = "admin",
+    params(("id" = u16, Path, description = "customer id")),
+    security(("bearer" = [])),
+    request_body = models::KycVerification,
+    responses(
+        (status = 200, description = "verification level set", body = models::APIResponse),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn verify_customer(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    verification: web::Json<models::KycVerification>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not verify customer".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let validation = verification.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
+    }
+
+    if storage.get_customer(*id).is_err() {
+        response.message = "could not find customer".to_string();
+        return HttpResponse::NotFound().json(response);
+    }
+
+    let level = models::KycLevel::parse(&verification.level).unwrap();
+
+    match storage.update_kyc_level(*id, level) {
+        Ok(_) => {
+            response.message = format!("customer verification level set to {}", level.as_str());
+            HttpResponse::Ok().json(response)
+        }
+        Err(_) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/customers/{id}/documents",
+    tag = "kyc",
+    params(("id" = u16, Path, description = "customer id")),
+    request_body = models::KycDocument,
+    responses(
+        (status = 200, description = "document created", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn create_kyc_document(
+    storage: web::Data<dyn Storage>,
+    document: web::Json<models::KycDocument>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "document not created".to_string(),
+    };
+    let mut created_document = document.into_inner();
+
+    if created_document.validate().is_err() {
+        return HttpResponse::UnprocessableEntity().json(created_document.validate().err());
+    }
+
+    match storage.get_customer(*id) {
+        Err(_) => {
+            response.message = "could not find customer".to_string();
+            HttpResponse::NotFound().json(response)
+        }
+        Ok(x) => {
+            created_document.created_at = Some(Utc::now().to_rfc2822());
+            created_document.customer_id = x.id;
+
+            match storage.create_kyc_document(&created_document) {
+                Ok(_) => {
+                    response.message = "document created".to_string();
+                    HttpResponse::Ok().json(response)
+                }
+                Err(_) => HttpResponse::BadRequest().json(response),
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/documents",
+    tag = "kyc",
+    params(("id" = u16, Path, description = "customer id")),
+    responses(
+        (status = 200, description = "the customer's documents", body = [models::KycDocument]),
+        (status = 404, description = "customer not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_kyc_documents_by_customer(
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not get customer".to_string(),
+    };
+
+    match storage.get_customer(*id) {
+        Ok(x) => match storage.get_kyc_documents_by_customer(x.id.unwrap()) {
+            Ok(x) => HttpResponse::Ok().json(x),
+            Err(_) => {
+                response.message = "could not get documents".to_string();
+                HttpResponse::NotFound().json(response)
+            }
+        },
+        Err(_) => HttpResponse::NotFound().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit",
+    tag = "admin",
+    params(models::AuditFilter),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "matching audit events", body = [models::AuditEvent]),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn get_audit_events(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    filter: web::Query<models::AuditFilter>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not get audit events".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let validation = filter.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
+    }
+
+    match storage.get_audit_events(&filter) {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(_e) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit/verify",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "the chain is intact", body = models::ChainReport),
+        (status = 409, description = "the chain is broken", body = models::ChainReport),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse)
+    )
+)]
+pub async fn verify_audit_chain(
+    storage: web::Data<dyn Storage>,
+    req: HttpRequest,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not verify audit chain".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match storage.verify_audit_chain() {
+        Ok(x) if x.valid => HttpResponse::Ok().json(x),
+        Ok(x) => HttpResponse::Conflict().json(x),
+        Err(_e) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/reconciliation",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "balance discrepancies", body = models::Reconciliation),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse)
+    )
+)]
+pub async fn get_reconciliation(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+) -> impl Responder {
+    reconcile(req, storage.get_ref(), false)
+}
+
+#[utoipa::path(
+    put,
+    path = "/reconciliation",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "balance discrepancies, now corrected", body = models::Reconciliation),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse)
+    )
+)]
+pub async fn correct_reconciliation(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+) -> impl Responder {
+    reconcile(req, storage.get_ref(), true)
+}
+
+fn reconcile(req: HttpRequest, storage: &dyn Storage, correct: bool) -> HttpResponse {
+    let mut response = models::APIResponse {
+        message: "could not reconcile balances".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match storage.reconcile(correct) {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(_e) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    security(("bearer" = [])),
+    request_body = models::Webhook,
+    responses(
+        (status = 201, description = "subscription created", body = models::Webhook),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn create_webhook(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    webhook: web::Json<models::Webhook>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "webhook not created".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let mut created_webhook = webhook.into_inner();
+    if created_webhook.validate().is_err() {
+        return HttpResponse::UnprocessableEntity().json(created_webhook.validate().err());
+    }
+    created_webhook.created_at = Some(Utc::now().to_rfc2822());
+
+    match storage.create_webhook(&created_webhook) {
+        Ok(id) => {
+            created_webhook.id = Some(id);
+            HttpResponse::Created().json(created_webhook)
+        }
+        Err(_) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "all subscriptions", body = [models::Webhook]),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse)
+    )
+)]
+pub async fn get_all_webhooks(storage: web::Data<dyn Storage>, req: HttpRequest) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not get webhooks".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match storage.get_webhooks() {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(_e) => HttpResponse::BadRequest().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    security(("bearer" = [])),
+    params(("id" = u16, Path, description = "webhook id")),
+    responses(
+        (status = 200, description = "the subscription", body = models::Webhook),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "webhook not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_webhook(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not find webhook".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match storage.get_webhook(*id) {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(_e) => HttpResponse::NotFound().json(response),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    security(("bearer" = [])),
+    params(("id" = u16, Path, description = "webhook id")),
+    responses(
+        (status = 200, description = "subscription and its deliveries deleted", body = models::APIResponse),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "webhook not found", body = models::APIResponse)
+    )
+)]
+pub async fn delete_webhook(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not find webhook".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match storage.delete_webhook(*id) {
+        Ok(_) => {
+            response.message = "webhook deleted".to_string();
+            HttpResponse::Ok().json(response)
+        }
+        Err(_e) => HttpResponse::NotFound().json(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    security(("bearer" = [])),
+    params(("id" = u16, Path, description = "webhook id")),
+    responses(
+        (status = 200, description = "deliveries to the subscription, newest first", body = [models::WebhookDelivery]),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "webhook not found", body = models::APIResponse)
+    )
+)]
+pub async fn get_webhook_deliveries(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "could not find webhook".to_string(),
+    };
+
+    if !is_admin(&req) {
+        response.message = "missing or invalid token".to_string();
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match storage.get_webhook(*id) {
+        Err(_e) => HttpResponse::NotFound().json(response),
+        Ok(x) => match storage.get_webhook_deliveries(x.id.unwrap()) {
+            Ok(x) => HttpResponse::Ok().json(x),
+            Err(_e) => {
+                response.message = "could not get deliveries".to_string();
+                HttpResponse::BadRequest().json(response)
+            }
+        },
+    }
+}
+
+/// Browsers cannot set headers on an `EventSource`, so the stream routes also
+/// accept the token as a `token` query parameter.
+fn validate_stream_token(req: &HttpRequest, auth: &models::StreamAuth) -> Option<models::Claims> {
+    validate_token(req).or_else(|| decode_token(req, auth.token.as_deref()?))
+}
+
+fn event_stream(streams: &stream::Streams, customer_id: Option<u16>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("cache-control", "no-cache"))
+        .streaming(streams.subscribe(customer_id))
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/stream",
+    tag = "customers",
+    security(("bearer" = [])),
+    params(("id" = u16, Path, description = "customer id"), models::StreamAuth),
+    responses(
+        (status = 200, description = "server-sent `balance.changed`, `transfer.created` and `payment.created` events for the customer", content_type = "text/event-stream"),
+        (status = 401, description = "missing or invalid token", body = models::APIResponse),
+        (status = 403, description = "token is for another customer", body = models::APIResponse),
+        (status = 404, description = "customer not found", body = models::APIResponse)
+    )
+)]
+pub async fn stream_customer(
+    req: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    streams: web::Data<stream::Streams>,
+    auth: web::Query<models::StreamAuth>,
+    id: web::Path<u16>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "missing or invalid token".to_string(),
+    };
+
+    // admins can follow anyone, other tokens only the customer they were issued for
+    let claims = match validate_stream_token(&req, &auth) {
+        Some(x) => x,
+        None => return HttpResponse::Unauthorized().json(response),
+    };
+    if claims.role != "admin" && claims.sub != id.to_string() {
+        response.message = "token is for another customer".to_string();
+        return HttpResponse::Forbidden().json(response);
+    }
+
+    match storage.get_customer(*id) {
+        Ok(x) => event_stream(&streams, x.id),
+        Err(_) => {
+            response.message = "could not find customer".to_string();
+            HttpResponse::NotFound().json(response)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/stream",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(models::StreamAuth),
+    responses(
+        (status = 200, description = "server-sent events for every customer", content_type = "text/event-stream"),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse)
+    )
+)]
+pub async fn stream_all(
+    req: HttpRequest,
+    streams: web::Data<stream::Streams>,
+    auth: web::Query<models::StreamAuth>,
+) -> impl Responder {
+    let response = models::APIResponse {
+        message: "missing or invalid token".to_string(),
+    };
+
+    match validate_stream_token(&req, &auth) {
+        Some(claims) if claims.role == "admin" => event_stream(&streams, None),
+        _ => HttpResponse::Unauthorized().json(response),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/backups",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "snapshot written and checked", body = models::Backup),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 500, description = "the snapshot could not be written or failed the integrity check", body = models::APIResponse),
+        (status = 501, description = "the server does not run on SQLite", body = models::APIResponse)
+    )
+)]
+pub async fn create_backup(req: HttpRequest, backend: web::Data<Backend>) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "missing or invalid token".to_string(),
+    };
+
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let Some(database) = backend.database.clone() else {
+        response.message = "backups are only taken of SQLite databases".to_string();
+        return HttpResponse::NotImplemented().json(response);
+    };
+    match web::block(move || crud::DATABASE.sync_scope(database, backup::create)).await {
+        Ok(Ok(x)) => HttpResponse::Created().json(x),
+        Ok(Err(e)) => {
+            response.message = e;
+            HttpResponse::InternalServerError().json(response)
+        }
+        Err(_) => {
+            response.message = "could not create backup".to_string();
+            HttpResponse::InternalServerError().json(response)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/backups",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "snapshots in the backup directory, oldest first", body = [models::Backup]),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse)
+    )
+)]
+pub async fn get_backups(req: HttpRequest) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "missing or invalid token".to_string(),
+    };
+
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    match backup::list() {
+        Ok(x) => HttpResponse::Ok().json(x),
+        Err(e) => {
+            response.message = e;
+            HttpResponse::InternalServerError().json(response)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/backups/restore",
+    tag = "admin",
+    security(("bearer" = [])),
+    request_body = models::BackupRestore,
+    responses(
+        (status = 201, description = "snapshot restored into a new file next to it, with the integrity check result", body = models::Backup),
+        (status = 401, description = "missing or invalid admin token", body = models::APIResponse),
+        (status = 404, description = "no such backup", body = models::APIResponse),
+        (status = 422, description = "validation errors per field")
+    )
+)]
+pub async fn restore_backup(
+    req: HttpRequest,
+    restore: web::Json<models::BackupRestore>,
+) -> impl Responder {
+    let mut response = models::APIResponse {
+        message: "missing or invalid token".to_string(),
+    };
+
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().json(response);
+    }
+
+    let validation = restore.validate();
+    if validation.is_err() {
+        return HttpResponse::UnprocessableEntity().json(validation.err());
+    }
+
+    match web::block(move || backup::restore_by_name(&restore.file)).await {
+        Ok(Ok(x)) => HttpResponse::Created().json(x),
+        Ok(Err(e)) => {
+            response.message = e;
+            HttpResponse::NotFound().json(response)
+        }
+        Err(_) => {
+            response.message = "could not restore backup".to_string();
+            HttpResponse::InternalServerError().json(response)
         }
     }
 }
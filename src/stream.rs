@@ -0,0 +1,117 @@
+use actix_web::web::Bytes;
+use futures_core::Stream;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Events a subscriber may fall behind by before it is disconnected, so that
+/// one slow client cannot hold up the others.
+static BUFFER: usize = 64;
+/// Proxies close connections that stay silent, so a comment is sent when
+/// nothing else has been.
+static KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+struct Subscriber {
+    /// `None` receives every event.
+    customer_id: Option<u16>,
+    sender: mpsc::Sender<Bytes>,
+}
+
+/// The subscribers to the live updates of one backend, shared by the
+/// workers of a server as app data.
+#[derive(Default)]
+pub struct Streams {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+tokio::task_local! {
+    /// The streams `publish` sends to. The app sets it for every request.
+    pub static STREAMS: Arc<Streams>;
+}
+
+impl Streams {
+    /// Sends an event to the firehose and to the streams of `customers`.
+    pub fn publish(&self, event: &str, customers: &[u16], data: serde_json::Value) {
+        let frame = Bytes::from(format!("event: {}\ndata: {}\n\n", event, data));
+
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            let concerned = match subscriber.customer_id {
+                Some(id) => customers.contains(&id),
+                None => true,
+            };
+            if !concerned {
+                return !subscriber.sender.is_closed();
+            }
+            subscriber.sender.try_send(frame.clone()).is_ok()
+        });
+    }
+
+    /// Server-sent events for `customer_id`, or for everyone when `None`.
+    pub fn subscribe(&self, customer_id: Option<u16>) -> EventStream {
+        let (sender, receiver) = mpsc::channel(BUFFER);
+        self.subscribers.lock().unwrap().push(Subscriber {
+            customer_id,
+            sender,
+        });
+
+        EventStream {
+            receiver,
+            keep_alive: tokio::time::interval_at(
+                tokio::time::Instant::now() + KEEP_ALIVE,
+                KEEP_ALIVE,
+            ),
+        }
+    }
+}
+
+/// The streams of the current scope, if any.
+pub fn current() -> Option<Arc<Streams>> {
+    STREAMS.try_with(|x| x.clone()).ok()
+}
+
+/// Publishes to the streams of the current scope. Called by `crud` once the
+/// change has been committed; outside of a scope (e.g. in `bank-admin`) there
+/// is nobody to tell.
+pub fn publish(event: &str, customers: &[u16], data: serde_json::Value) {
+    if let Some(streams) = current() {
+        streams.publish(event, customers, data);
+    }
+}
+
+/// An event held back until the transaction behind it commits: its name,
+/// the customers it concerns and its data.
+pub type Pending = (&'static str, Vec<u16>, serde_json::Value);
+
+pub fn publish_all(events: Vec<Pending>) {
+    for (event, customers, data) in events {
+        publish(event, &customers, data);
+    }
+}
+
+pub struct EventStream {
+    receiver: mpsc::Receiver<Bytes>,
+    keep_alive: tokio::time::Interval,
+}
+
+impl Stream for EventStream {
+    type Item = Result<Bytes, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.receiver.poll_recv(cx) {
+            Poll::Ready(Some(frame)) => {
+                self.keep_alive.reset();
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            // dropped by `publish` for falling behind
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {}
+        }
+        match self.keep_alive.poll_tick(cx) {
+            Poll::Ready(_) => Poll::Ready(Some(Ok(Bytes::from_static(b": keep-alive\n\n")))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error;
+use std::future::Future;
+
+/// A mounted version of the API. Each version is its own scope in `app.rs`,
+/// so a new version can replace handlers while the old one keeps serving.
+pub struct ApiVersion {
+    pub name: &'static str,
+    /// RFC 9745 `Deprecation` value, set once clients should move away.
+    pub deprecation: Option<&'static str>,
+    /// RFC 8594 `Sunset` date after which the version may be removed.
+    pub sunset: Option<&'static str>,
+}
+
+pub static V1: ApiVersion = ApiVersion {
+    name: "v1",
+    deprecation: None,
+    sunset: None,
+};
+
+/// The routes as they were served before versioning, kept as an alias of v1.
+pub static UNVERSIONED: ApiVersion = ApiVersion {
+    name: "unversioned",
+    deprecation: Some("@1792368000"),
+    sunset: Some("Mon, 19 Apr 2027 00:00:00 GMT"),
+};
+
+/// Counts the request against `version` and marks the response when the
+/// version is deprecated. Used with `wrap_fn` on the version's scope.
+pub fn track<S, B>(
+    version: &'static ApiVersion,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    metrics::API_VERSION_REQUESTS
+        .with_label_values(&[version.name])
+        .inc();
+    let fut = srv.call(req);
+
+    async move {
+        let mut res = fut.await?;
+
+        if let Some(deprecation) = version.deprecation {
+            res.headers_mut().insert(
+                HeaderName::from_static("deprecation"),
+                HeaderValue::from_static(deprecation),
+            );
+        }
+        if let Some(sunset) = version.sunset {
+            res.headers_mut().insert(
+                HeaderName::from_static("sunset"),
+                HeaderValue::from_static(sunset),
+            );
+        }
+        Ok(res)
+    }
+}
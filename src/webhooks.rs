@@ -0,0 +1,200 @@
+use crate::database::models::WebhookDispatch;
+use crate::database::storage::{self, Storage};
+use crate::health;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `balance.low` is sent when a balance drops below this amount.
+pub static LOW_BALANCE_THRESHOLD: f64 = 100.0;
+
+static POLL_INTERVAL: Duration = Duration::from_secs(1);
+static DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+static DELIVERIES_PER_RUN: u32 = 10;
+/// Attempts are spaced by `RETRY_BASE_SECONDS * 2^(attempt - 1)`, up to
+/// `RETRY_MAX_SECONDS`, and the delivery fails after `MAX_ATTEMPTS`.
+static RETRY_BASE_SECONDS: i64 = 10;
+static RETRY_MAX_SECONDS: i64 = 60 * 60;
+static MAX_ATTEMPTS: u32 = 8;
+
+/// Sends a delivery and returns the HTTP status of the response. The
+/// dispatcher only talks to subscribers through this, so it can be pointed
+/// at a stub.
+pub trait Transport {
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &str) -> Result<u16, String>;
+}
+
+/// HTTP/1.1 over a plain TCP connection, one request per connection.
+pub struct HttpTransport {
+    pub timeout: Duration,
+}
+
+impl Transport for HttpTransport {
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &str) -> Result<u16, String> {
+        let url = url::Url::parse(url).map_err(|e| e.to_string())?;
+        if url.scheme() != "http" {
+            return Err(format!("unsupported scheme {}", url.scheme()));
+        }
+        let host = url.host_str().ok_or("url has no host")?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        // resolves IPv6 literals without the brackets `host_str` keeps
+        let address = url
+            .socket_addrs(|| Some(80))
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .next()
+            .ok_or("could not resolve host")?;
+
+        let mut stream =
+            TcpStream::connect_timeout(&address, self.timeout).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(|e| e.to_string())?;
+
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            path,
+            host,
+            port,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| e.to_string())?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream)
+            .read_line(&mut status_line)
+            .map_err(|e| e.to_string())?;
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| format!("invalid response: {}", status_line.trim()))
+    }
+}
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>`. Subscribers recompute it with
+/// their secret and the `X-Webhook-Timestamp` header, and should reject old
+/// timestamps to prevent replays.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    // HMAC takes keys of any length, so this cannot fail
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+/// Seconds to wait before the attempt following attempt number `attempt`.
+fn backoff(attempt: u32) -> i64 {
+    let exponent = attempt.saturating_sub(1).min(20);
+    (RETRY_BASE_SECONDS << exponent).min(RETRY_MAX_SECONDS)
+}
+
+pub struct Dispatcher<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> Dispatcher<T> {
+    pub fn new(transport: T) -> Dispatcher<T> {
+        Dispatcher { transport }
+    }
+
+    /// Fans out new outbox events, then attempts the deliveries that are due.
+    /// Returns the number of attempts made.
+    pub fn run_once(&self, storage: &dyn Storage) -> storage::Result<usize> {
+        storage.fan_out_webhook_events()?;
+        let due = storage.get_due_webhook_deliveries(Utc::now().timestamp(), DELIVERIES_PER_RUN)?;
+
+        for delivery in &due {
+            self.deliver(storage, delivery)?;
+        }
+        Ok(due.len())
+    }
+
+    fn deliver(&self, storage: &dyn Storage, delivery: &WebhookDispatch) -> storage::Result<()> {
+        let body = serde_json::json!({
+            "id": delivery.event_id,
+            "type": delivery.event_type,
+            "createdAt": delivery.event_created_at,
+            "data": serde_json::from_str::<serde_json::Value>(&delivery.payload).unwrap_or_default(),
+        })
+        .to_string();
+        let timestamp = Utc::now().timestamp();
+        let headers = [
+            ("X-Webhook-Id", delivery.event_id.to_string()),
+            ("X-Webhook-Event", delivery.event_type.clone()),
+            ("X-Webhook-Timestamp", timestamp.to_string()),
+            (
+                "X-Webhook-Signature",
+                format!("sha256={}", sign(&delivery.secret, timestamp, &body)),
+            ),
+        ];
+
+        let attempt = delivery.attempts + 1;
+        let (status_code, error) = match self.transport.post(&delivery.url, &headers, &body) {
+            Ok(code) if (200..300).contains(&code) => (Some(code), None),
+            Ok(code) => (Some(code), Some(format!("subscriber answered {}", code))),
+            Err(e) => (None, Some(e)),
+        };
+
+        let (status, next_attempt_at) = match &error {
+            None => ("delivered", None),
+            Some(_) if attempt >= MAX_ATTEMPTS => ("failed", None),
+            Some(_) => ("pending", Some(timestamp + backoff(attempt))),
+        };
+        match &error {
+            None => tracing::info!(delivery = delivery.id, attempt, "webhook delivered"),
+            Some(e) => {
+                tracing::warn!(delivery = delivery.id, attempt, status, error = %e, "webhook delivery failed")
+            }
+        }
+
+        storage.record_webhook_attempt(
+            delivery.id,
+            status,
+            next_attempt_at,
+            status_code,
+            error.as_deref(),
+        )
+    }
+}
+
+/// Starts the dispatcher for the outbox in `storage` on its own thread; it
+/// reports to the readiness check after every run.
+pub fn spawn(storage: Arc<dyn Storage>) {
+    std::thread::spawn(move || {
+        let dispatcher = Dispatcher::new(HttpTransport {
+            timeout: DELIVERY_TIMEOUT,
+        });
+        loop {
+            let result = dispatcher.run_once(&*storage);
+            // the longest a run can take when every subscriber times out
+            health::report_job(
+                "webhooks",
+                POLL_INTERVAL + DELIVERY_TIMEOUT * DELIVERIES_PER_RUN,
+                result.map(|_| ()).map_err(|e| e.to_string()),
+            );
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
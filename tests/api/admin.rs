@@ -0,0 +1,381 @@
+use crate::fixtures::{app, app_on, backend, customer, next_event, payment, transfer};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use bank::database::storage::Backend;
+use serde_json::json;
+
+#[actix_web::test]
+async fn reports_health() {
+    let app = app_on(&Backend::memory().unwrap()).await;
+
+    let (status, body) = app.text(TestRequest::get().uri("/")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "Hello world!");
+    let (status, _) = app.get("/health/live").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app.get("/health/ready").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["components"][0]["detail"], "in memory");
+}
+
+#[actix_web::test]
+async fn documents_and_measures_the_api() {
+    let app = app().await;
+
+    let (status, body) = app.get("/openapi.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["paths"]["/customers/{id}"].is_object());
+    let (status, body) = app.text(TestRequest::get().uri("/docs")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("openapi.json"));
+    let (status, body) = app.text(TestRequest::get().uri("/metrics")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("bank_"));
+}
+
+#[actix_web::test]
+async fn unversioned_paths_are_deprecated() {
+    let app = app().await;
+    app.create_customer(customer("Ada Lovelace")).await;
+
+    let res = app.call(TestRequest::get().uri("/v1/customers")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!res.headers().contains_key("deprecation"));
+
+    let res = app.call(TestRequest::get().uri("/customers")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().contains_key("deprecation"));
+    assert!(res.headers().contains_key("sunset"));
+}
+
+#[actix_web::test]
+async fn issues_admin_tokens() {
+    let app = app().await;
+    let bearer = |token: &str| {
+        TestRequest::get()
+            .uri("/reversals")
+            .insert_header(("authorization", format!("Bearer {}", token)))
+    };
+
+    let (status, _) = app.send(bearer(app.admin_token())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.send(bearer("nope")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // every database signs with a random key of its own
+    let other = app_on(&backend()).await;
+    let (status, _) = other.send(bearer(app.admin_token())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // `/token` hands them out only when enabled, as in the demo
+    let (status, _) = app.get("/token").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    std::env::set_var("BANK_ISSUE_ADMIN_TOKENS", "1");
+    let (status, token) = app.text(TestRequest::get().uri("/token")).await;
+    std::env::remove_var("BANK_ISSUE_ADMIN_TOKENS");
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.send(bearer(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn records_an_audit_trail() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+
+    let (status, _) = app.get("/audit").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.admin_get("/audit?limit=0").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = app
+        .admin_get(&format!("/audit?entity=customers&entityId={}", id))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.as_array().unwrap().is_empty());
+
+    let (status, _) = app.get("/audit/verify").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = app.admin_get("/audit/verify").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["valid"], true);
+}
+
+#[actix_web::test]
+async fn keeps_changes_with_their_audit_events() {
+    let backend = Backend::memory().unwrap();
+    let app = app_on(&backend).await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let payee = app.create_payee(id, "Electricity", "87654321").await;
+    let uri = format!("/customers/{}/payees/{}", id, payee);
+
+    // audit events can no longer be saved
+    let conn = rusqlite::Connection::open(backend.database.as_ref().unwrap()).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER no_audit BEFORE INSERT ON audit_events BEGIN SELECT RAISE(ABORT, 'disk full'); END",
+    )
+    .unwrap();
+
+    let name = json!({ "name": "Ada King" });
+    let (status, _) = app.put(&format!("/customers/{}", id), name.clone()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .send(
+            TestRequest::patch()
+                .uri(&format!("/customers/{}", id))
+                .set_json(name),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (_, body) = app.get(&format!("/customers/{}", id)).await;
+    assert_eq!(body["name"], "Ada Lovelace");
+
+    let (status, _) = app
+        .put(
+            &uri,
+            json!({ "name": "Power company", "receiverCode": "11223344" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app.send(TestRequest::delete().uri(&uri)).await;
+    assert_ne!(status, StatusCode::OK);
+    let (_, body) = app.get(&uri).await;
+    assert_eq!(body["receiverCode"], "87654321");
+
+    conn.execute_batch("DROP TRIGGER no_audit").unwrap();
+    let (_, body) = app.admin_get("/audit/verify").await;
+    assert_eq!(body["valid"], true);
+}
+
+#[actix_web::test]
+async fn detects_edited_transactions() {
+    let backend = Backend::memory().unwrap();
+    let app = app_on(&backend).await;
+    let from = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let to = app.create_customer(customer("Alan Turing")).await;
+    app.transfer(transfer(from, to).amount(40.0)).await;
+    app.transfer(transfer(from, to).amount(10.0)).await;
+    app.pay(payment(from).amount(5.0)).await;
+
+    let (status, body) = app.admin_get("/audit/verify").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let conn = rusqlite::Connection::open(backend.database.as_ref().unwrap()).unwrap();
+    conn.execute("UPDATE transfers SET amount = 1 WHERE id = 1", [])
+        .unwrap();
+    let (status, body) = app.admin_get("/audit/verify").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["table"], "transfers");
+    assert_eq!(body["brokenAt"], 1);
+
+    // taking the row out breaks the link of the one after it instead
+    conn.execute("DELETE FROM transfers WHERE id = 1", [])
+        .unwrap();
+    let (_, body) = app.admin_get("/audit/verify").await;
+    assert_eq!(body["brokenAt"], 2);
+    assert_eq!(body["reason"], "does not link to the previous row");
+}
+
+#[actix_web::test]
+async fn reconciles_balances() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    app.put(
+        &format!("/customers/{}/deposits", id),
+        json!({ "amount": 10 }),
+    )
+    .await;
+
+    let (status, _) = app.get("/reconciliation").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.put("/reconciliation", json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app.admin_get("/reconciliation").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri("/reconciliation"), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn corrects_balances_with_movements() {
+    let backend = Backend::memory().unwrap();
+    let app = app_on(&backend).await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+
+    // a change that never went through the ledger
+    let conn = rusqlite::Connection::open(backend.database.as_ref().unwrap()).unwrap();
+    conn.execute("UPDATE customers SET balance = 130 WHERE id = ?1", [id])
+        .unwrap();
+
+    let (_, body) = app.admin_get("/reconciliation").await;
+    assert_eq!(body["discrepancies"][0]["difference"], 30.0);
+    let (status, body) = app
+        .admin_send(TestRequest::put().uri("/reconciliation"), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["corrected"], true);
+    assert_eq!(app.balance(id).await, 100.0);
+
+    let (_, body) = app.admin_get("/reconciliation").await;
+    assert!(body["discrepancies"].as_array().unwrap().is_empty());
+    let kinds: Vec<(String, f64)> = conn
+        .prepare("SELECT kind, amount FROM movements WHERE customer_id = ?1 ORDER BY id")
+        .unwrap()
+        .query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        kinds,
+        [
+            ("opening".to_string(), 100.0),
+            ("discrepancy".to_string(), 30.0),
+            ("correction".to_string(), -30.0)
+        ]
+    );
+    let (status, _) = app.admin_get("/audit/verify").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn manages_webhooks() {
+    let app = app().await;
+    let webhook = json!({
+        "url": "http://localhost:9000/hooks",
+        "secret": "0123456789abcdef",
+        "eventTypes": ["transfer.created"],
+    });
+
+    let (status, _) = app.post("/webhooks", webhook.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    for invalid in [
+        json!({ "url": "ftp://localhost", "secret": "0123456789abcdef", "eventTypes": ["transfer.created"] }),
+        json!({ "url": "http://localhost:9000", "secret": "short", "eventTypes": ["transfer.created"] }),
+        json!({ "url": "http://localhost:9000", "secret": "0123456789abcdef", "eventTypes": [] }),
+        json!({ "url": "http://localhost:9000", "secret": "0123456789abcdef", "eventTypes": ["nope"] }),
+    ] {
+        let (status, _) = app
+            .admin_send(TestRequest::post().uri("/webhooks"), invalid.clone())
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", invalid);
+    }
+
+    let (status, _) = app
+        .admin_send(TestRequest::post().uri("/webhooks"), webhook)
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = app.admin_get("/webhooks").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body[0].get("secret").is_none());
+    let id = body[0]["id"].as_u64().unwrap();
+    let uri = format!("/webhooks/{}", id);
+
+    let (status, _) = app.get(&uri).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = app.admin_get(&uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["url"], "http://localhost:9000/hooks");
+    let (status, body) = app.admin_get(&format!("{}/deliveries", uri)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.as_array().unwrap().is_empty());
+
+    let (status, _) = app.send(TestRequest::delete().uri(&uri)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .send(
+            TestRequest::delete()
+                .uri(&uri)
+                .insert_header(("authorization", format!("Bearer {}", app.admin_token()))),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.admin_get(&uri).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.admin_get("/webhooks/99/deliveries").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn backs_up_and_restores() {
+    let app = app_on(&Backend::memory().unwrap()).await;
+    app.create_customer(customer("Ada Lovelace")).await;
+
+    let (status, _) = app.post("/backups", json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.get("/backups").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, backup) = app
+        .admin_send(TestRequest::post().uri("/backups"), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", backup);
+    assert_eq!(backup["integrity"], "ok");
+
+    let (status, body) = app.admin_get("/backups").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|x| x["file"] == backup["file"]));
+
+    let uri = "/backups/restore";
+    let (status, _) = app.post(uri, json!({ "file": backup["file"] })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .admin_send(TestRequest::post().uri(uri), json!({ "file": "" }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app
+        .admin_send(
+            TestRequest::post().uri(uri),
+            json!({ "file": "nope.sqlite" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = app
+        .admin_send(
+            TestRequest::post().uri(uri),
+            json!({ "file": backup["file"] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["integrity"], "ok");
+}
+
+#[actix_web::test]
+async fn admins_follow_every_customer() {
+    let app = app().await;
+
+    let (status, _) = app.get("/stream").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let res = app
+        .call(TestRequest::get().uri(&format!("/stream?token={}", app.admin_token())))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+
+    let mut body = Box::pin(res.into_body());
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let (status, _) = app
+        .put(
+            &format!("/customers/{}/deposits", id),
+            json!({ "amount": 50 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (event, data) = next_event(&mut body).await.unwrap();
+    assert_eq!(event, "balance.changed");
+    assert_eq!(data["customerId"], id);
+    assert_eq!(data["balance"], 50.0);
+}
@@ -0,0 +1,273 @@
+use crate::fixtures::{app, app_on, customer};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use bank::database::storage::Backend;
+use serde_json::{json, Value};
+
+fn statuses(report: &Value) -> Vec<(&str, Option<u64>)> {
+    report["lines"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| (x["status"].as_str().unwrap(), x["code"].as_u64()))
+        .collect()
+}
+
+fn csv(uri: &str, body: &str) -> TestRequest {
+    TestRequest::post()
+        .uri(uri)
+        .insert_header(("content-type", "text/csv"))
+        .set_payload(body.to_string())
+}
+
+#[actix_web::test]
+async fn runs_every_line_of_a_batch() {
+    let app = app().await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let alan = app.create_customer(customer("Alan Turing")).await;
+
+    let (status, report) = app
+        .post(
+            "/batches",
+            json!({ "lines": [
+                { "kind": "payment", "customerId": ada, "amount": 30, "receiverCode": "12345678", "reference": "INV-1" },
+                { "kind": "transfer", "customerId": ada, "idTo": alan, "amount": 50 },
+            ] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["mode"], "atomic");
+    assert_eq!(report["format"], "json");
+    assert_eq!(report["status"], "completed");
+    assert_eq!(report["succeeded"], 2);
+    assert_eq!(
+        statuses(&report),
+        [("succeeded", Some(200)), ("succeeded", Some(200))]
+    );
+    assert_eq!(app.balance(ada).await, 20.0);
+    assert_eq!(app.balance(alan).await, 50.0);
+
+    let (status, found) = app
+        .get(&format!("/batches/{}", report["id"].as_u64().unwrap()))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(found["lines"], report["lines"]);
+    assert_eq!(found["status"], "completed");
+
+    let (status, _) = app.get("/batches/999").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn atomic_batches_with_a_bad_line_make_nothing() {
+    let app = app().await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+
+    let (status, report) = app
+        .post(
+            "/batches?mode=atomic",
+            json!({ "lines": [
+                { "kind": "payment", "customerId": ada, "amount": 30, "receiverCode": "12345678", "reference": "INV-1" },
+                { "kind": "transfer", "customerId": ada, "idTo": 999, "amount": 10 },
+                { "kind": "refund", "customerId": ada, "amount": 10 },
+                { "kind": "payment", "customerId": ada, "amount": 0, "receiverCode": "12345678" },
+                { "kind": "payment", "amount": 10 },
+            ] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", report);
+    assert_eq!(report["status"], "rejected");
+    assert_eq!(
+        statuses(&report),
+        [
+            ("skipped", None),
+            ("failed", Some(404)),
+            ("failed", Some(422)),
+            ("failed", Some(422)),
+            ("failed", Some(422)),
+        ]
+    );
+    assert_eq!(
+        report["lines"][1]["message"],
+        "could not find customer to tranfer to"
+    );
+    assert_eq!(app.balance(ada).await, 100.0);
+
+    // each line is covered by the balance, but not all of them together
+    let payment = json!({ "kind": "payment", "customerId": ada, "amount": 60, "receiverCode": "12345678", "reference": "INV-2" });
+    let (status, report) = app
+        .post("/batches", json!({ "lines": [payment, payment] }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", report);
+    assert_eq!(
+        statuses(&report),
+        [("skipped", None), ("failed", Some(400))]
+    );
+    assert_eq!(report["lines"][1]["message"], "not enough balance");
+    assert_eq!(app.balance(ada).await, 100.0);
+    let (_, payments) = app.get(&format!("/customers/{}/payments", ada)).await;
+    assert!(payments.as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn best_effort_batches_make_the_lines_that_pass() {
+    let app = app().await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let alan = app.create_customer(customer("Alan Turing")).await;
+
+    let payment = json!({ "kind": "payment", "customerId": ada, "amount": 60, "receiverCode": "12345678", "reference": "INV-1" });
+    let (status, report) = app
+        .post(
+            "/batches?mode=best-effort",
+            json!({ "lines": [
+                payment,
+                payment,
+                { "kind": "transfer", "customerId": ada, "idTo": ada, "amount": 10 },
+                { "kind": "transfer", "customerId": ada, "idTo": alan, "amount": 40 },
+            ] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["mode"], "best-effort");
+    assert_eq!(report["status"], "partial");
+    assert_eq!(report["succeeded"], 2);
+    assert_eq!(report["failed"], 2);
+    assert_eq!(
+        statuses(&report),
+        [
+            ("succeeded", Some(200)),
+            ("failed", Some(400)),
+            ("failed", Some(422)),
+            ("succeeded", Some(200)),
+        ]
+    );
+    assert_eq!(app.balance(ada).await, 0.0);
+    assert_eq!(app.balance(alan).await, 40.0);
+
+    let (status, report) = app
+        .post("/batches?mode=best-effort", json!({ "lines": [payment] }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(report["status"], "rejected");
+}
+
+#[actix_web::test]
+async fn keeps_the_report_with_the_lines_made() {
+    let backend = Backend::memory().unwrap();
+    let app = app_on(&backend).await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let alan = app.create_customer(customer("Alan Turing")).await;
+    let transfer = json!({ "kind": "transfer", "customerId": ada, "idTo": alan, "amount": 10 });
+
+    // line results can no longer be saved
+    let conn = rusqlite::Connection::open(backend.database.as_ref().unwrap()).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER no_lines BEFORE INSERT ON batch_lines BEGIN SELECT RAISE(ABORT, 'disk full'); END",
+    )
+    .unwrap();
+
+    // an atomic batch goes with its report
+    let (status, report) = app.post("/batches", json!({ "lines": [transfer] })).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", report);
+    assert_eq!(statuses(&report), [("failed", Some(400))]);
+    assert_eq!(app.balance(ada).await, 100.0);
+
+    // a best-effort one is made, and left running with the report answered
+    let (status, report) = app
+        .post("/batches?mode=best-effort", json!({ "lines": [transfer] }))
+        .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", report);
+    assert_eq!(statuses(&report), [("succeeded", Some(200))]);
+    assert_eq!(app.balance(ada).await, 90.0);
+    let (_, saved) = app.get(&format!("/batches/{}", report["id"])).await;
+    assert_eq!(saved["status"], "running");
+
+    conn.execute_batch("DROP TRIGGER no_lines").unwrap();
+    let (status, report) = app.post("/batches", json!({ "lines": [transfer] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    let (_, saved) = app.get(&format!("/batches/{}", report["id"])).await;
+    assert_eq!(saved["status"], "completed");
+    assert_eq!(statuses(&saved), [("succeeded", Some(200))]);
+    assert_eq!(app.balance(ada).await, 80.0);
+}
+
+#[actix_web::test]
+async fn reads_csv_uploads() {
+    let app = app().await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let alan = app.create_customer(customer("Alan Turing")).await;
+
+    let upload = format!(
+        "kind,customerId,idTo,amount,receiverCode,reference\r\n\
+         payment,{0},,25,12345678,\"INV-1, \"\"March\"\"\"\r\n\
+         transfer,{0},{1},10.5,,\r\n\
+         \r\n\
+         payment,{0},,abc,12345678,INV-2\r\n",
+        ada, alan
+    );
+    let (status, report) = app.send(csv("/batches?mode=best-effort", &upload)).await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["format"], "csv");
+    assert_eq!(
+        statuses(&report),
+        [
+            ("succeeded", Some(200)),
+            ("succeeded", Some(200)),
+            ("failed", Some(422))
+        ]
+    );
+    assert_eq!(app.balance(ada).await, 64.5);
+    assert_eq!(app.balance(alan).await, 10.5);
+
+    let (_, payments) = app.get(&format!("/customers/{}/payments", ada)).await;
+    assert_eq!(payments[0]["reference"], "INV-1, \"March\"");
+}
+
+#[actix_web::test]
+async fn rejects_uploads_it_cannot_read() {
+    let app = app().await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let line =
+        json!({ "kind": "payment", "customerId": ada, "amount": 10, "receiverCode": "12345678" });
+
+    let (status, body) = app
+        .post("/batches?mode=sometimes", json!({ "lines": [line] }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["message"], "mode must be atomic or best-effort");
+
+    let (status, body) = app.post("/batches", json!({ "lines": [] })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["message"], "the batch has no lines");
+
+    let (status, body) = app
+        .post("/batches", json!({ "lines": vec![line.clone(); 1001] }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["message"], "a batch holds at most 1000 lines");
+
+    let (status, _) = app.post("/batches", json!([line])).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    for upload in [
+        "",
+        "kind,customerId,amount\npayment,1",
+        "kind,reference\npayment,\"INV-1",
+    ] {
+        let (status, _) = app.send(csv("/batches", upload)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{:?}", upload);
+    }
+    assert_eq!(app.balance(ada).await, 100.0);
+}
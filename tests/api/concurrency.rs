@@ -0,0 +1,131 @@
+//! Parallel requests from several instances of the app on one database, the
+//! way the workers of a server send them.
+
+use crate::fixtures::{app_on, customer, payment, transfer};
+use actix_web::http::StatusCode;
+use bank::database::storage::Backend;
+use serde_json::json;
+use std::thread;
+
+const WORKERS: usize = 8;
+const REQUESTS: usize = 40;
+const AMOUNT: f64 = 10.0;
+
+/// Requests that went through, per kind.
+#[derive(Default)]
+struct Tally {
+    withdrawals: u32,
+    payments: u32,
+    sent: u32,
+    returned: u32,
+    refused: u32,
+}
+
+#[test]
+fn parallel_requests_never_overdraw_in_memory() {
+    parallel_requests_never_overdraw(Backend::memory().unwrap());
+}
+
+#[test]
+fn parallel_requests_never_overdraw_a_file() {
+    let path = std::env::temp_dir().join(format!("bank-test-{}.sqlite", uuid::Uuid::new_v4()));
+    parallel_requests_never_overdraw(Backend::sqlite(path.to_str().unwrap()).unwrap());
+}
+
+fn parallel_requests_never_overdraw(backend: Backend) {
+    let (ada, alan) = actix_web::rt::System::new().block_on(async {
+        let app = app_on(&backend).await;
+        (
+            app.create_customer(customer("Ada Lovelace").balance(1000.0))
+                .await,
+            app.create_customer(customer("Alan Turing")).await,
+        )
+    });
+
+    // together they ask for far more than Ada has
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|worker| {
+            let backend = backend.clone();
+            thread::spawn(move || {
+                actix_web::rt::System::new().block_on(async move {
+                    let app = app_on(&backend).await;
+                    let mut tally = Tally::default();
+
+                    for i in 0..REQUESTS {
+                        let kind = (worker + i) % 4;
+                        let (status, body) = match kind {
+                            0 => {
+                                app.put(
+                                    &format!("/customers/{}/withdrawals", ada),
+                                    json!({ "amount": AMOUNT }),
+                                )
+                                .await
+                            }
+                            1 => app.pay(payment(ada).amount(AMOUNT)).await,
+                            2 => app.transfer(transfer(ada, alan).amount(AMOUNT)).await,
+                            _ => app.transfer(transfer(alan, ada).amount(AMOUNT)).await,
+                        };
+
+                        match status {
+                            StatusCode::OK => match kind {
+                                0 => tally.withdrawals += 1,
+                                1 => tally.payments += 1,
+                                2 => tally.sent += 1,
+                                _ => tally.returned += 1,
+                            },
+                            StatusCode::BAD_REQUEST => {
+                                assert_eq!(body["message"], "not enough balance");
+                                tally.refused += 1;
+                            }
+                            _ => panic!("unexpected {}: {}", status, body),
+                        }
+                    }
+                    tally
+                })
+            })
+        })
+        .collect();
+
+    let mut tally = Tally::default();
+    for worker in workers {
+        let x = worker.join().unwrap();
+        tally.withdrawals += x.withdrawals;
+        tally.payments += x.payments;
+        tally.sent += x.sent;
+        tally.returned += x.returned;
+        tally.refused += x.refused;
+    }
+
+    actix_web::rt::System::new().block_on(async {
+        let app = app_on(&backend).await;
+        let (ada_balance, alan_balance) = (app.balance(ada).await, app.balance(alan).await);
+        assert!(ada_balance >= 0.0 && alan_balance >= 0.0);
+
+        let debits = (tally.withdrawals + tally.payments + tally.sent) as f64 * AMOUNT;
+        assert_eq!(
+            ada_balance,
+            1000.0 - debits + tally.returned as f64 * AMOUNT
+        );
+        assert_eq!(
+            alan_balance,
+            (tally.sent as f64 - tally.returned as f64) * AMOUNT
+        );
+        assert!(tally.refused > 0);
+
+        let (_, transfers) = app.get("/transfers").await;
+        let transfers = transfers.as_array().unwrap();
+        let sent = transfers
+            .iter()
+            .filter(|x| x["name_from"] == "Ada Lovelace")
+            .count();
+        assert_eq!(sent as u32, tally.sent);
+        assert_eq!(transfers.len() as u32, tally.sent + tally.returned);
+        let (_, payments) = app.get(&format!("/customers/{}/payments", ada)).await;
+        assert_eq!(payments.as_array().unwrap().len() as u32, tally.payments);
+
+        let (status, report) = app.admin_get("/reconciliation").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["invariantHolds"], true, "{}", report);
+        assert!(report["discrepancies"].as_array().unwrap().is_empty());
+    });
+}
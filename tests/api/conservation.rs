@@ -0,0 +1,305 @@
+//! Random sequences of money movements against the routes. A model predicts
+//! the outcome of every request, and after each step the balances have to
+//! add up with the history the API reports. The model counts in cents, and
+//! the API's balances, which are floats, are compared to it within half a cent.
+
+use crate::fixtures::{app, customer, payment, transfer, TestApp};
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::Error;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Transaction limit of unverified customers, which everyone here is, in cents.
+const LIMIT: i64 = 50_000;
+/// Smallest deposit, withdrawal or transfer, in cents; payments only have to
+/// be above zero.
+const MINIMUM: i64 = 100;
+/// Id of a customer that never exists.
+const MISSING: u16 = 999;
+
+#[derive(Clone, Debug)]
+enum Op {
+    CreateCustomer { balance: i64 },
+    Deposit { customer: Index, amount: i64 },
+    Withdraw { customer: Index, amount: i64 },
+    Transfer { from: Index, to: Index, amount: i64 },
+    Payment { customer: Index, amount: i64 },
+}
+
+/// Mostly valid amounts in cents, with some below the minimum, zero,
+/// negative and over the limit.
+fn amount() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        8 => MINIMUM..=LIMIT,
+        1 => -500..MINIMUM,
+        1 => LIMIT + 1..=LIMIT + 10_000
+    ]
+}
+
+/// Cents as the units the API takes and reports.
+fn units(cents: i64) -> f64 {
+    cents as f64 / 100.0
+}
+
+fn assert_close(actual: f64, cents: i64, context: &str) {
+    assert!(
+        (actual - units(cents)).abs() < 0.005,
+        "{}: {} instead of {}",
+        context,
+        actual,
+        units(cents)
+    );
+}
+
+/// The status the model expects, unless the request takes out exactly the
+/// whole balance: float balances can end up a hair below it, so the API may
+/// also refuse that one.
+fn accepts(expected: StatusCode, drains: bool, status: StatusCode) -> bool {
+    status == expected
+        || (expected == StatusCode::OK && drains && status == StatusCode::BAD_REQUEST)
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        1 => (0..=100_000i64).prop_map(|balance| Op::CreateCustomer { balance }),
+        2 => (any::<Index>(), amount()).prop_map(|(customer, amount)| Op::Deposit { customer, amount }),
+        2 => (any::<Index>(), amount()).prop_map(|(customer, amount)| Op::Withdraw { customer, amount }),
+        3 => (any::<Index>(), any::<Index>(), amount())
+            .prop_map(|(from, to, amount)| Op::Transfer { from, to, amount }),
+        2 => (any::<Index>(), amount()).prop_map(|(customer, amount)| Op::Payment { customer, amount }),
+    ]
+}
+
+/// What the bank should look like, in cents so that sums are exact.
+#[derive(Default)]
+struct Model {
+    ids: Vec<u16>,
+    names: HashMap<u16, String>,
+    balances: HashMap<u16, i64>,
+    opening: HashMap<u16, i64>,
+    /// Deposits minus withdrawals per customer; the API does not list them.
+    movements: HashMap<u16, i64>,
+}
+
+impl Model {
+    /// Picks an existing customer, or one that does not exist before the
+    /// first is created.
+    fn pick(&self, index: &Index) -> u16 {
+        match self.ids.len() {
+            0 => MISSING,
+            n => self.ids[index.index(n)],
+        }
+    }
+
+    fn balance(&self, id: u16) -> Option<i64> {
+        self.balances.get(&id).copied()
+    }
+
+    /// Whether taking `amount` out leaves exactly nothing.
+    fn drains(&self, id: u16, amount: i64) -> bool {
+        self.balance(id) == Some(amount)
+    }
+
+    /// Status of a deposit or withdrawal and the balance change it makes.
+    fn movement(&self, id: u16, amount: i64, withdrawal: bool) -> (StatusCode, i64) {
+        match self.balance(id) {
+            _ if amount < MINIMUM => (StatusCode::UNPROCESSABLE_ENTITY, 0),
+            None => (StatusCode::NOT_FOUND, 0),
+            Some(_) if withdrawal && amount > LIMIT => (StatusCode::BAD_REQUEST, 0),
+            Some(x) if withdrawal && amount > x => (StatusCode::BAD_REQUEST, 0),
+            Some(_) if withdrawal => (StatusCode::OK, -amount),
+            Some(_) => (StatusCode::OK, amount),
+        }
+    }
+
+    fn transfer(&self, from: u16, to: u16, amount: i64) -> StatusCode {
+        if amount < MINIMUM || from == to {
+            return StatusCode::UNPROCESSABLE_ENTITY;
+        }
+        match (self.balance(from), self.balance(to)) {
+            (None, _) => StatusCode::NOT_FOUND,
+            (Some(_), _) if amount > LIMIT => StatusCode::BAD_REQUEST,
+            (Some(x), _) if amount > x => StatusCode::BAD_REQUEST,
+            (_, None) => StatusCode::NOT_FOUND,
+            _ => StatusCode::OK,
+        }
+    }
+
+    fn payment(&self, id: u16, amount: i64) -> StatusCode {
+        match self.balance(id) {
+            _ if amount < 1 => StatusCode::UNPROCESSABLE_ENTITY,
+            None => StatusCode::NOT_FOUND,
+            Some(_) if amount > LIMIT => StatusCode::BAD_REQUEST,
+            Some(x) if amount > x => StatusCode::BAD_REQUEST,
+            Some(_) => StatusCode::OK,
+        }
+    }
+}
+
+async fn apply<S, B>(app: &TestApp<S>, model: &mut Model, op: &Op)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    match op {
+        Op::CreateCustomer { balance } => {
+            let name = format!("Customer {}", model.ids.len() + 1);
+            let id = app
+                .create_customer(customer(&name).balance(units(*balance)))
+                .await;
+            model.ids.push(id);
+            model.names.insert(id, name);
+            model.balances.insert(id, *balance);
+            model.opening.insert(id, *balance);
+        }
+        Op::Deposit { customer, amount } | Op::Withdraw { customer, amount } => {
+            let id = model.pick(customer);
+            let withdrawal = matches!(op, Op::Withdraw { .. });
+            let kind = if withdrawal {
+                "withdrawals"
+            } else {
+                "deposits"
+            };
+            let (expected, change) = model.movement(id, *amount, withdrawal);
+            let drains = withdrawal && model.drains(id, *amount);
+            let (status, body) = app
+                .put(
+                    &format!("/customers/{}/{}", id, kind),
+                    json!({ "amount": units(*amount) }),
+                )
+                .await;
+            assert!(
+                accepts(expected, drains, status),
+                "{:?}: {} {}",
+                op,
+                status,
+                body
+            );
+            if status == StatusCode::OK {
+                *model.balances.get_mut(&id).unwrap() += change;
+                *model.movements.entry(id).or_default() += change;
+            }
+        }
+        Op::Transfer { from, to, amount } => {
+            let (from, to) = (model.pick(from), model.pick(to));
+            let expected = model.transfer(from, to, *amount);
+            let drains = model.drains(from, *amount);
+            let (status, body) = app
+                .transfer(transfer(from, to).amount(units(*amount)))
+                .await;
+            assert!(
+                accepts(expected, drains, status),
+                "{:?}: {} {}",
+                op,
+                status,
+                body
+            );
+            if status == StatusCode::OK {
+                *model.balances.get_mut(&from).unwrap() -= amount;
+                *model.balances.get_mut(&to).unwrap() += amount;
+            }
+        }
+        Op::Payment { customer, amount } => {
+            let id = model.pick(customer);
+            let expected = model.payment(id, *amount);
+            let drains = model.drains(id, *amount);
+            let (status, body) = app.pay(payment(id).amount(units(*amount))).await;
+            assert!(
+                accepts(expected, drains, status),
+                "{:?}: {} {}",
+                op,
+                status,
+                body
+            );
+            if status == StatusCode::OK {
+                *model.balances.get_mut(&id).unwrap() -= amount;
+            }
+        }
+    }
+}
+
+/// The balances the API reports against the model and against the history
+/// of transfers and payments it keeps.
+async fn check<S, B>(app: &TestApp<S>, model: &Model)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let (_, customers) = app.get("/customers").await;
+    let balances: HashMap<u16, f64> = customers
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| {
+            (
+                x["id"].as_u64().unwrap() as u16,
+                x["balance"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    let (_, transfers) = app.get("/transfers").await;
+    let transfers = transfers.as_array().unwrap();
+
+    let mut total = 0.0;
+    let mut expected_total = 0;
+    for &id in &model.ids {
+        let balance = balances[&id];
+        assert!(balance >= 0.0, "customer {} is overdrawn: {}", id, balance);
+        assert_close(balance, model.balances[&id], &format!("customer {}", id));
+
+        let name = &model.names[&id];
+        let sent: f64 = transfers
+            .iter()
+            .filter(|x| &x["name_from"] == name)
+            .map(|x| x["amount"].as_f64().unwrap())
+            .sum();
+        let received: f64 = transfers
+            .iter()
+            .filter(|x| &x["name_to"] == name)
+            .map(|x| x["amount"].as_f64().unwrap())
+            .sum();
+        let (_, payments) = app.get(&format!("/customers/{}/payments", id)).await;
+        let paid: f64 = payments
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["amount"].as_f64().unwrap())
+            .sum();
+        let movements = model.movements.get(&id).copied().unwrap_or_default();
+
+        assert_close(
+            units(model.opening[&id] + movements) + received - sent - paid,
+            model.balances[&id],
+            &format!("history of customer {}", id),
+        );
+        total += balance;
+        expected_total += model.opening[&id] + movements;
+        expected_total -= (paid * 100.0).round() as i64;
+    }
+    // transfers only move money between customers
+    assert_close(total, expected_total, "total");
+}
+
+proptest! {
+    // `with_cases` would override `PROPTEST_CASES`, so it is read here
+    #![proptest_config(ProptestConfig::with_cases(
+        std::env::var("PROPTEST_CASES").ok().and_then(|x| x.parse().ok()).unwrap_or(48)
+    ))]
+
+    #[test]
+    fn money_is_conserved(ops in prop::collection::vec(op(), 1..40)) {
+        actix_web::rt::System::new().block_on(async {
+            let app = app().await;
+            let mut model = Model::default();
+            for op in &ops {
+                apply(&app, &mut model, op).await;
+                check(&app, &model).await;
+            }
+        });
+    }
+}
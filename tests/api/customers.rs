@@ -0,0 +1,307 @@
+use crate::fixtures::{app, app_on, backend, customer, next_event};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use serde_json::json;
+
+#[actix_web::test]
+async fn creates_and_lists_customers() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    app.create_customer(customer("Alan Turing")).await;
+
+    let (status, body) = app.get(&format!("/customers/{}", id)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "Ada Lovelace");
+    assert_eq!(body["balance"], 100.0);
+    assert_eq!(body["status"], "active");
+    assert_eq!(body["kycLevel"], "unverified");
+
+    let (status, body) = app.get("/customers").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn rejects_invalid_customers() {
+    let app = app().await;
+
+    for body in [
+        json!({ "name": "Al" }),
+        json!({ "name": "Ada Lovelace", "balance": -1 }),
+        json!({ "name": "Ada Lovelace", "email": "not an email" }),
+        json!({ "name": "Ada Lovelace", "phone": "12" }),
+        json!({ "name": "Ada Lovelace", "dateOfBirth": "3000-01-01" }),
+    ] {
+        let (status, _) = app.post("/customers", body.clone()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    }
+    let (_, body) = app.get("/customers").await;
+    assert!(body.as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn missing_customer_is_not_found() {
+    let app = app().await;
+
+    for uri in [
+        "/customers/99",
+        "/customers/99/transfers",
+        "/customers/99/payments",
+        "/customers/99/payees",
+        "/customers/99/documents",
+    ] {
+        let (status, _) = app.get(uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn edits_customers() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let uri = format!("/customers/{}", id);
+
+    let (status, _) = app.put(&uri, json!({ "name": "Ada King" })).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .send(TestRequest::patch().uri(&uri).set_json(json!({
+            "address": "12 St James's Square",
+            "email": "ada@example.com",
+        })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, body) = app.get(&uri).await;
+    assert_eq!(body["name"], "Ada King");
+    assert_eq!(body["email"], "ada@example.com");
+
+    let (status, _) = app.put(&uri, json!({ "name": "Al" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app
+        .send(
+            TestRequest::patch()
+                .uri(&uri)
+                .set_json(json!({ "email": "nope" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = app.put("/customers/99", json!({ "name": "Ada" })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .send(
+            TestRequest::patch()
+                .uri("/customers/99")
+                .set_json(json!({ "name": "Ada" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn admins_set_the_verification_level() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let uri = format!("/customers/{}/kyc", id);
+
+    let (status, _) = app.put(&uri, json!({ "level": "full" })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri(&uri), json!({ "level": "gold" }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app
+        .admin_send(
+            TestRequest::put().uri("/customers/99/kyc"),
+            json!({ "level": "full" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri(&uri), json!({ "level": "full" }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.get(&format!("/customers/{}", id)).await;
+    assert_eq!(body["kycLevel"], "full");
+}
+
+#[actix_web::test]
+async fn stores_kyc_documents() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let uri = format!("/customers/{}/documents", id);
+    let document = json!({
+        "kind": "passport",
+        "number": "P1234567",
+        "issuingCountry": "GB",
+        "expiresAt": "2030-01-01",
+    });
+
+    let (status, _) = app.post(&uri, document.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app.get(&uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["number"], "P1234567");
+
+    let (status, _) = app
+        .post(
+            &uri,
+            json!({ "kind": "passport", "number": "P1", "issuingCountry": "GBR" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app.post("/customers/99/documents", document).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn admins_freeze_unfreeze_and_close_accounts() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(50.0))
+        .await;
+    let reason = json!({ "reason": "suspicious activity" });
+    let uri = |action: &str| format!("/customers/{}/{}", id, action);
+
+    let (status, _) = app.put(&uri("freeze"), reason.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .admin_send(
+            TestRequest::put().uri(&uri("freeze")),
+            json!({ "reason": "no" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app
+        .admin_send(
+            TestRequest::put().uri("/customers/99/freeze"),
+            reason.clone(),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri(&uri("unfreeze")), reason.clone())
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri(&uri("freeze")), reason.clone())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.get(&format!("/customers/{}", id)).await;
+    assert_eq!(body["status"], "frozen");
+
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri(&uri("unfreeze")), reason.clone())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // money left in the account has to be paid out somewhere
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri(&uri("close")), reason.clone())
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .admin_send(
+            TestRequest::put().uri(&uri("close")),
+            json!({ "reason": "customer request", "payoutReceiverCode": "12345678" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.get(&format!("/customers/{}", id)).await;
+    assert_eq!(body["status"], "closed");
+    assert_eq!(body["balance"], 0.0);
+
+    let (status, _) = app
+        .admin_send(TestRequest::put().uri(&uri("unfreeze")), reason)
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[actix_web::test]
+async fn customers_follow_their_own_stream() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let other = app.create_customer(customer("Alan Turing")).await;
+    let uri = format!("/customers/{}/stream", id);
+
+    let (status, _) = app.get(&uri).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let res = app
+        .call(TestRequest::get().uri(&format!("{}?token={}", uri, app.customer_token(id))))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+
+    // a deposit of someone else is not sent, the customer's own is
+    let mut body = Box::pin(res.into_body());
+    for to in [other, id] {
+        let (status, _) = app
+            .put(
+                &format!("/customers/{}/deposits", to),
+                json!({ "amount": 50 }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (event, data) = next_event(&mut body).await.unwrap();
+    assert_eq!(event, "balance.changed");
+    assert_eq!(data["customerId"], id);
+    assert_eq!(data["balance"], 50.0);
+
+    let (status, _) = app
+        .send(TestRequest::get().uri(&format!(
+            "/customers/{}/stream?token={}",
+            other,
+            app.customer_token(id)
+        )))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app.admin_get("/customers/99/stream").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn streams_belong_to_their_backend() {
+    let first = app().await;
+    let backend = backend();
+    let second = app_on(&backend).await;
+    let id = first.create_customer(customer("Ada Lovelace")).await;
+    assert_eq!(second.create_customer(customer("Alan Turing")).await, id);
+
+    let uri = format!(
+        "/customers/{}/stream?token={}",
+        id,
+        second.customer_token(id)
+    );
+    let res = second.call(TestRequest::get().uri(&uri)).await;
+    let mut body = Box::pin(res.into_body());
+    let (status, _) = first
+        .put(
+            &format!("/customers/{}/deposits", id),
+            json!({ "amount": 50 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(next_event(&mut body).await.is_none());
+
+    // another app on the same backend, like another worker, shares them
+    let worker = app_on(&backend).await;
+    worker
+        .put(
+            &format!("/customers/{}/deposits", id),
+            json!({ "amount": 20 }),
+        )
+        .await;
+    let (event, data) = next_event(&mut body).await.unwrap();
+    assert_eq!(event, "balance.changed");
+    assert_eq!(data["balance"], 20.0);
+}
@@ -0,0 +1,388 @@
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::{test, web, Error};
+use bank::app;
+use bank::database::models::Claims;
+use bank::database::postgres::PostgresStorage;
+use bank::database::storage::Backend;
+use bank::ratelimit::RateLimiter;
+use chrono::Utc;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::{json, Value};
+use std::pin::Pin;
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+static SETUP: Once = Once::new();
+
+/// Settings read once per process: the quotas are lifted, as some tests send
+/// many requests, and backups go to a directory of their own.
+fn setup() {
+    SETUP.call_once(|| {
+        for group in ["AUTH", "MONEY", "DEFAULT"] {
+            std::env::set_var(format!("BANK_RATE_LIMIT_{}", group), "1000000/1");
+        }
+        let backups =
+            std::env::temp_dir().join(format!("bank-test-backups-{}", uuid::Uuid::new_v4()));
+        std::env::set_var("BANK_BACKUP_DIR", backups);
+    });
+}
+
+/// A fresh backend: an in-memory SQLite database, or with
+/// `BANK_STORAGE=postgres` a schema of its own in the PostgreSQL database at
+/// `BANK_TEST_POSTGRES_URL`.
+pub fn backend() -> Backend {
+    if std::env::var("BANK_STORAGE").as_deref() != Ok("postgres") {
+        return Backend::memory().unwrap();
+    }
+    let url = std::env::var("BANK_TEST_POSTGRES_URL")
+        .expect("BANK_STORAGE=postgres needs BANK_TEST_POSTGRES_URL");
+    let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+    let mut config: postgres::Config = url.parse().unwrap();
+
+    config.options(&format!("-c search_path={}", schema));
+
+    // the blocking client cannot connect from inside the test's runtime
+    let storage = std::thread::spawn(move || {
+        let mut client = config.connect(postgres::NoTls).unwrap();
+        client
+            .batch_execute(&format!("CREATE SCHEMA {}", schema))
+            .unwrap();
+        PostgresStorage::with_config(config).unwrap()
+    });
+    Backend {
+        storage: Arc::new(storage.join().unwrap()),
+        database: None,
+        streams: Arc::default(),
+    }
+}
+
+/// The app `main` serves, on a fresh backend.
+pub struct TestApp<S> {
+    service: S,
+    secret: String,
+    admin: String,
+}
+
+pub async fn app(
+) -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error>> {
+    app_on(&backend()).await
+}
+
+/// Another instance of the app on `backend`, like each worker of a server.
+pub async fn app_on(
+    backend: &Backend,
+) -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error>> {
+    setup();
+    app_limited(backend, RateLimiter::from_env()).await
+}
+
+/// The app on `backend`, limited by `limiter` rather than the lifted quotas.
+pub async fn app_limited(
+    backend: &Backend,
+    limiter: RateLimiter,
+) -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error>> {
+    let service = test::init_service(app::new(backend, &web::Data::new(limiter))).await;
+
+    let secret = backend.storage.get_jwt_secrets().unwrap().remove(0).secret;
+    TestApp {
+        service,
+        admin: token(&secret, "admin@mail.com", "admin"),
+        secret,
+    }
+}
+
+/// A token for `sub` with `role`, signed with `secret`.
+fn token(secret: &str, sub: &str, role: &str) -> String {
+    let now = Utc::now().timestamp() as usize;
+    let claims = Claims {
+        sub: sub.to_string(),
+        iat: now,
+        exp: now + 60,
+        role: role.to_string(),
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .unwrap()
+}
+
+impl<S, B> TestApp<S>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    pub fn admin_token(&self) -> &str {
+        &self.admin
+    }
+
+    /// A token for a customer rather than an admin, signed with the app's key.
+    pub fn customer_token(&self, id: u16) -> String {
+        token(&self.secret, &id.to_string(), "customer")
+    }
+
+    /// Sends the request and returns the status and the JSON body, or `Null`
+    /// when the body is not JSON.
+    pub async fn send(&self, req: test::TestRequest) -> (StatusCode, Value) {
+        let res = test::call_service(&self.service, req.to_request()).await;
+        let status = res.status();
+        let body = test::read_body(res).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    /// Sends the request and returns the response without reading its body,
+    /// for streams that never end.
+    pub async fn call(&self, req: test::TestRequest) -> ServiceResponse<B> {
+        test::call_service(&self.service, req.to_request()).await
+    }
+
+    pub async fn text(&self, req: test::TestRequest) -> (StatusCode, String) {
+        let res = test::call_service(&self.service, req.to_request()).await;
+        let status = res.status();
+        let body = test::read_body(res).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        self.send(test::TestRequest::get().uri(uri)).await
+    }
+
+    /// `GET` with the admin token.
+    pub async fn admin_get(&self, uri: &str) -> (StatusCode, Value) {
+        self.send(
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("authorization", format!("Bearer {}", self.admin))),
+        )
+        .await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.send(test::TestRequest::post().uri(uri).set_json(body))
+            .await
+    }
+
+    pub async fn put(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.send(test::TestRequest::put().uri(uri).set_json(body))
+            .await
+    }
+
+    /// `method` with the admin token and a JSON body.
+    pub async fn admin_send(&self, req: test::TestRequest, body: Value) -> (StatusCode, Value) {
+        self.send(
+            req.insert_header(("authorization", format!("Bearer {}", self.admin)))
+                .set_json(body),
+        )
+        .await
+    }
+
+    pub async fn balance(&self, id: u16) -> f64 {
+        let (_, customer) = self.get(&format!("/customers/{}", id)).await;
+        customer["balance"].as_f64().unwrap()
+    }
+
+    pub async fn create_customer(&self, customer: CustomerBuilder) -> u16 {
+        let (status, body) = self
+            .post(
+                "/customers",
+                json!({ "name": customer.name, "balance": customer.balance }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (_, customers) = self.get("/customers").await;
+        let id = customers
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|x| x["name"] == customer.name)
+            .filter_map(|x| x["id"].as_u64())
+            .max()
+            .unwrap() as u16;
+
+        if let Some(level) = customer.kyc {
+            let (status, body) = self
+                .admin_send(
+                    test::TestRequest::put().uri(&format!("/customers/{}/kyc", id)),
+                    json!({ "level": level }),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+        if customer.frozen {
+            let (status, body) = self
+                .admin_send(
+                    test::TestRequest::put().uri(&format!("/customers/{}/freeze", id)),
+                    json!({ "reason": "fixture" }),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+        id
+    }
+
+    pub async fn transfer(&self, transfer: TransferBuilder) -> (StatusCode, Value) {
+        self.put("/customers/transfers", transfer.json()).await
+    }
+
+    pub async fn pay(&self, payment: PaymentBuilder) -> (StatusCode, Value) {
+        self.post(
+            &format!("/customers/{}/payments", payment.customer),
+            payment.json(),
+        )
+        .await
+    }
+
+    pub async fn create_payee(&self, customer: u16, name: &str, receiver_code: &str) -> u16 {
+        let uri = format!("/customers/{}/payees", customer);
+        let (status, body) = self
+            .post(&uri, json!({ "name": name, "receiverCode": receiver_code }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (_, payees) = self.get(&uri).await;
+        payees
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|x| x["name"] == name)
+            .filter_map(|x| x["id"].as_u64())
+            .max()
+            .unwrap() as u16
+    }
+}
+
+pub struct CustomerBuilder {
+    name: String,
+    balance: f64,
+    kyc: Option<&'static str>,
+    frozen: bool,
+}
+
+/// An active, unverified customer with no money.
+pub fn customer(name: &str) -> CustomerBuilder {
+    CustomerBuilder {
+        name: name.to_string(),
+        balance: 0.0,
+        kyc: None,
+        frozen: false,
+    }
+}
+
+impl CustomerBuilder {
+    pub fn balance(mut self, balance: f64) -> CustomerBuilder {
+        self.balance = balance;
+        self
+    }
+
+    pub fn kyc(mut self, level: &'static str) -> CustomerBuilder {
+        self.kyc = Some(level);
+        self
+    }
+
+    pub fn frozen(mut self) -> CustomerBuilder {
+        self.frozen = true;
+        self
+    }
+}
+
+pub struct TransferBuilder {
+    from: u16,
+    to: u16,
+    amount: f64,
+}
+
+pub fn transfer(from: u16, to: u16) -> TransferBuilder {
+    TransferBuilder {
+        from,
+        to,
+        amount: 10.0,
+    }
+}
+
+impl TransferBuilder {
+    pub fn amount(mut self, amount: f64) -> TransferBuilder {
+        self.amount = amount;
+        self
+    }
+
+    pub fn json(&self) -> Value {
+        json!({ "idFrom": self.from, "idTo": self.to, "amount": self.amount })
+    }
+}
+
+pub struct PaymentBuilder {
+    customer: u16,
+    amount: f64,
+    receiver_code: Option<String>,
+    reference: String,
+    payee: Option<u16>,
+}
+
+/// A payment to a plain account number with a free-text reference, which no
+/// reference check applies to.
+pub fn payment(customer: u16) -> PaymentBuilder {
+    PaymentBuilder {
+        customer,
+        amount: 10.0,
+        receiver_code: Some("12345678".to_string()),
+        reference: "INV-1".to_string(),
+        payee: None,
+    }
+}
+
+impl PaymentBuilder {
+    pub fn amount(mut self, amount: f64) -> PaymentBuilder {
+        self.amount = amount;
+        self
+    }
+
+    pub fn receiver_code(mut self, receiver_code: Option<&str>) -> PaymentBuilder {
+        self.receiver_code = receiver_code.map(|x| x.to_string());
+        self
+    }
+
+    pub fn reference(mut self, reference: &str) -> PaymentBuilder {
+        self.reference = reference.to_string();
+        self
+    }
+
+    pub fn payee(mut self, payee: u16) -> PaymentBuilder {
+        self.payee = Some(payee);
+        self.receiver_code = None;
+        self
+    }
+
+    pub fn json(&self) -> Value {
+        let mut body = json!({ "amount": self.amount, "reference": self.reference });
+        if let Some(x) = &self.receiver_code {
+            body["receiverCode"] = json!(x);
+        }
+        if let Some(x) = self.payee {
+            body["payeeId"] = json!(x);
+        }
+        body
+    }
+}
+
+/// The name and data of the next event on a server-sent event stream, or
+/// `None` when nothing but keep-alives arrives within a second.
+pub async fn next_event<B: MessageBody>(body: &mut Pin<Box<B>>) -> Option<(String, Value)> {
+    let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx));
+    let chunk = actix_web::rt::time::timeout(Duration::from_secs(1), chunk)
+        .await
+        .ok()??
+        .ok()
+        .unwrap();
+    let frame = std::str::from_utf8(&chunk).unwrap();
+
+    let (event, data) = frame.trim_end().split_once('\n').unwrap();
+    Some((
+        event.strip_prefix("event: ").unwrap().to_string(),
+        serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap(),
+    ))
+}
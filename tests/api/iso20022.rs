@@ -0,0 +1,242 @@
+use crate::fixtures::{app, customer, transfer};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use chrono::Utc;
+
+fn xml(uri: &str, body: &str) -> TestRequest {
+    TestRequest::post()
+        .uri(uri)
+        .insert_header(("content-type", "application/xml"))
+        .set_payload(body.to_string())
+}
+
+fn credit_transfer(end_to_end: &str, amount: &str, account: &str, remittance: &str) -> String {
+    format!(
+        "<CdtTrfTxInf><PmtId><EndToEndId>{}</EndToEndId></PmtId>\
+         <Amt><InstdAmt Ccy=\"EUR\">{}</InstdAmt></Amt>\
+         <Cdtr><Nm>Supplier</Nm></Cdtr><CdtrAcct><Id>{}</Id></CdtrAcct>{}</CdtTrfTxInf>",
+        end_to_end, amount, account, remittance
+    )
+}
+
+fn pain001(debtor: u16, count: usize, sum: &str, transactions: &[String]) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <Document xmlns=\"urn:iso:std:iso:20022:tech:xsd:pain.001.001.03\"><CstmrCdtTrfInitn>\
+         <GrpHdr><MsgId>MSG-1</MsgId><CreDtTm>2026-01-01T09:00:00</CreDtTm>\
+         <NbOfTxs>{}</NbOfTxs><CtrlSum>{}</CtrlSum><InitgPty><Nm>Ada</Nm></InitgPty></GrpHdr>\
+         <PmtInf><PmtInfId>PMT-1</PmtInfId><PmtMtd>TRF</PmtMtd>\
+         <DbtrAcct><Id><Othr><Id>{}</Id></Othr></Id></DbtrAcct>{}</PmtInf>\
+         </CstmrCdtTrfInitn></Document>",
+        count,
+        sum,
+        debtor,
+        transactions.concat()
+    )
+}
+
+fn local<'a>(node: roxmltree::Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    path.iter()
+        .try_fold(node, |node, name| {
+            node.children().find(|x| x.tag_name().name() == *name)
+        })?
+        .text()
+}
+
+fn amount(node: roxmltree::Node) -> f64 {
+    let value: f64 = local(node, &["Amt"]).unwrap().parse().unwrap();
+    match local(node, &["CdtDbtInd"]) {
+        Some("DBIT") => -value,
+        _ => value,
+    }
+}
+
+fn details<'a, 'input>(entry: &roxmltree::Node<'a, 'input>) -> roxmltree::Node<'a, 'input> {
+    entry
+        .descendants()
+        .find(|x| x.has_tag_name("TxDtls"))
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn payments_round_trip_from_pain001_to_camt053() {
+    let app = app().await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let alan = app.create_customer(customer("Alan Turing")).await;
+
+    let upload = pain001(
+        ada,
+        2,
+        "42.50",
+        &[
+            credit_transfer(
+                "E2E-1",
+                "30.00",
+                "<IBAN>DE89370400440532013000</IBAN>",
+                "<RmtInf><Strd><CdtrRefInf><Ref>RF18539007547034</Ref></CdtrRefInf></Strd></RmtInf>",
+            ),
+            credit_transfer(
+                "E2E-2",
+                "12.5",
+                "<Othr><Id>12345678</Id></Othr>",
+                "<RmtInf><Ustrd>INV-7 &amp; INV-8</Ustrd></RmtInf>",
+            ),
+        ],
+    );
+    let (status, report) = app
+        .send(xml(&format!("/customers/{}/pain.001", ada), &upload))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["format"], "pain.001");
+    assert_eq!(report["succeeded"], 2);
+    assert_eq!(app.balance(ada).await, 57.5);
+
+    let (_, payments) = app.get(&format!("/customers/{}/payments", ada)).await;
+    let references: Vec<&str> = payments
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["reference"].as_str().unwrap())
+        .collect();
+    assert!(references.contains(&"RF18539007547034"));
+    assert!(references.contains(&"INV-7 & INV-8"));
+
+    app.transfer(transfer(ada, alan).amount(7.5)).await;
+
+    let (status, body) = app
+        .text(TestRequest::get().uri(&format!("/customers/{}/camt.053", ada)))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let document = roxmltree::Document::parse(&body).unwrap();
+    let statement = document
+        .descendants()
+        .find(|x| x.has_tag_name("Stmt"))
+        .unwrap();
+    assert_eq!(
+        local(statement, &["Acct", "Id", "Othr", "Id"]),
+        Some(&*ada.to_string())
+    );
+    assert_eq!(
+        local(statement, &["Acct", "Ownr", "Nm"]),
+        Some("Ada Lovelace")
+    );
+
+    let balances: Vec<(&str, f64)> = statement
+        .children()
+        .filter(|x| x.has_tag_name("Bal"))
+        .map(|x| (local(x, &["Tp", "CdOrPrtry", "Cd"]).unwrap(), amount(x)))
+        .collect();
+    assert_eq!(balances[0].0, "OPBD");
+    assert_eq!(balances[1], ("CLBD", 50.0));
+
+    // the entries take the statement from one balance to the other
+    let entries: Vec<_> = statement
+        .children()
+        .filter(|x| x.has_tag_name("Ntry"))
+        .collect();
+    let net: f64 = entries.iter().map(|x| amount(*x)).sum();
+    assert!((balances[0].1 + net - balances[1].1).abs() < 1e-9);
+
+    let iban = entries
+        .iter()
+        .map(details)
+        .find(|x| local(*x, &["RltdPties", "CdtrAcct", "Id", "IBAN"]).is_some())
+        .unwrap();
+    assert_eq!(
+        local(iban, &["RltdPties", "CdtrAcct", "Id", "IBAN"]),
+        Some("DE89370400440532013000")
+    );
+    assert_eq!(
+        local(iban, &["RmtInf", "Strd", "CdtrRefInf", "Ref"]),
+        Some("RF18539007547034")
+    );
+    assert!(entries
+        .iter()
+        .map(details)
+        .any(|x| local(x, &["RmtInf", "Ustrd"]) == Some("INV-7 & INV-8")
+            && local(x, &["RltdPties", "CdtrAcct", "Id", "Othr", "Id"]) == Some("12345678")));
+    assert!(entries.iter().any(|x| amount(*x) == -7.5
+        && local(details(x), &["RltdPties", "Cdtr", "Nm"]) == Some("Alan Turing")));
+
+    // yesterday had no movements
+    let yesterday = Utc::now().date_naive().pred_opt().unwrap();
+    let (status, body) = app
+        .text(TestRequest::get().uri(&format!("/customers/{}/camt.053?date={}", ada, yesterday)))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.contains("<Ntry>"));
+}
+
+#[actix_web::test]
+async fn rejects_pain001_files_it_cannot_use() {
+    let app = app().await;
+    let ada = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let uri = format!("/customers/{}/pain.001", ada);
+    let good = credit_transfer("E2E-1", "10", "<Othr><Id>12345678</Id></Othr>", "");
+
+    let dollars = good.replace("EUR", "USD");
+    let iban = pain001(ada, 1, "10", std::slice::from_ref(&good)).replace(
+        &format!("<Othr><Id>{}</Id></Othr>", ada),
+        "<IBAN>FI2112345600000785</IBAN>",
+    );
+    for (upload, message) in [
+        ("<Document>".to_string(), "could not read the pain.001 file"),
+        (
+            pain001(ada, 2, "10", std::slice::from_ref(&good)),
+            "NbOfTxs",
+        ),
+        (
+            pain001(ada, 1, "99", std::slice::from_ref(&good)),
+            "CtrlSum",
+        ),
+        (pain001(ada, 1, "10", &[dollars]), "only EUR"),
+        (
+            pain001(ada + 1, 1, "10", std::slice::from_ref(&good)),
+            "debtor account",
+        ),
+        (iban, "not an IBAN"),
+    ] {
+        let (status, body) = app.send(xml(&uri, &upload)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", upload);
+        assert!(
+            body["message"].as_str().unwrap().contains(message),
+            "{}",
+            body
+        );
+    }
+
+    // lines are checked like any payment, atomically by default
+    let upload = pain001(
+        ada,
+        2,
+        "110",
+        &[
+            good.clone(),
+            credit_transfer("E2E-2", "100", "<Othr><Id>12345678</Id></Othr>", ""),
+        ],
+    );
+    let (status, report) = app.send(xml(&uri, &upload)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", report);
+    assert_eq!(report["status"], "rejected");
+    assert_eq!(app.balance(ada).await, 100.0);
+
+    let (status, report) = app
+        .send(xml(&format!("{}?mode=best-effort", uri), &upload))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["status"], "partial");
+    assert_eq!(app.balance(ada).await, 90.0);
+
+    let (status, _) = app
+        .text(TestRequest::get().uri("/customers/999/camt.053"))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .text(TestRequest::get().uri(&format!("/customers/{}/camt.053?date=yesterday", ada)))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
@@ -0,0 +1,16 @@
+//! Every route, through the same `App` that `main` serves, each test on a
+//! fresh backend of its own so they can run in parallel: in-memory SQLite, or
+//! PostgreSQL with `BANK_STORAGE=postgres`.
+
+mod admin;
+mod batches;
+mod concurrency;
+mod conservation;
+mod customers;
+mod fixtures;
+mod iso20022;
+mod logging;
+mod money;
+mod payees;
+mod ratelimit;
+mod webhooks;
@@ -0,0 +1,292 @@
+use crate::fixtures::{app, customer, payment, transfer};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use serde_json::json;
+
+#[actix_web::test]
+async fn deposits_and_withdraws() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+
+    let (status, _) = app
+        .put(
+            &format!("/customers/{}/deposits", id),
+            json!({ "amount": 100 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .put(
+            &format!("/customers/{}/withdrawals", id),
+            json!({ "amount": 30 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.balance(id).await, 70.0);
+}
+
+#[actix_web::test]
+async fn rejects_invalid_movements() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let frozen = app
+        .create_customer(customer("Alan Turing").balance(100.0).frozen())
+        .await;
+
+    // over the limit of an unverified customer
+    let (status, _) = app
+        .put(
+            &format!("/customers/{}/withdrawals", id),
+            json!({ "amount": 501 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    for kind in ["deposits", "withdrawals"] {
+        let uri = format!("/customers/{}/{}", id, kind);
+        let (status, _) = app.put(&uri, json!({ "amount": 0 })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", kind);
+
+        let (status, _) = app
+            .put(&format!("/customers/99/{}", kind), json!({ "amount": 10 }))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", kind);
+        let (status, _) = app
+            .put(
+                &format!("/customers/{}/{}", frozen, kind),
+                json!({ "amount": 10 }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", kind);
+    }
+
+    let (status, body) = app
+        .put(
+            &format!("/customers/{}/withdrawals", id),
+            json!({ "amount": 101 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "not enough balance");
+    assert_eq!(app.balance(id).await, 100.0);
+}
+
+#[actix_web::test]
+async fn verified_customers_move_more() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(10000.0).kyc("basic"))
+        .await;
+
+    let (status, body) = app
+        .put(
+            &format!("/customers/{}/withdrawals", id),
+            json!({ "amount": 5001 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "amount exceeds the limit for the customer's verification level"
+    );
+    let (status, _) = app
+        .put(
+            &format!("/customers/{}/withdrawals", id),
+            json!({ "amount": 5000 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn deposits_are_not_limited() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+
+    let (status, body) = app
+        .put(
+            &format!("/customers/{}/deposits", id),
+            json!({ "amount": 10000 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(app.balance(id).await, 10000.0);
+}
+
+#[actix_web::test]
+async fn transfers_between_customers() {
+    let app = app().await;
+    let from = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let to = app.create_customer(customer("Alan Turing")).await;
+
+    let (status, _) = app.transfer(transfer(from, to).amount(40.0)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.balance(from).await, 60.0);
+    assert_eq!(app.balance(to).await, 40.0);
+
+    let (status, body) = app.get("/transfers").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["name_from"], "Ada Lovelace");
+    assert_eq!(body[0]["name_to"], "Alan Turing");
+
+    let (status, body) = app.get(&format!("/customers/{}/transfers", from)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["amount"], 40.0);
+}
+
+#[actix_web::test]
+async fn rejects_invalid_transfers() {
+    let app = app().await;
+    let from = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let to = app.create_customer(customer("Alan Turing")).await;
+    let frozen = app.create_customer(customer("Grace Hopper").frozen()).await;
+
+    let cases = [
+        (
+            transfer(from, to).amount(0.0),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+        (transfer(from, from), StatusCode::UNPROCESSABLE_ENTITY),
+        (transfer(99, to), StatusCode::NOT_FOUND),
+        (transfer(from, 99), StatusCode::NOT_FOUND),
+        (transfer(frozen, to), StatusCode::FORBIDDEN),
+        (transfer(from, frozen), StatusCode::FORBIDDEN),
+        (transfer(from, to).amount(101.0), StatusCode::BAD_REQUEST),
+        (transfer(to, from).amount(1.0), StatusCode::BAD_REQUEST),
+    ];
+    for (transfer, expected) in cases {
+        let body = transfer.json();
+        let (status, _) = app.transfer(transfer).await;
+        assert_eq!(status, expected, "{}", body);
+    }
+
+    assert_eq!(app.balance(from).await, 100.0);
+    let (_, body) = app.get("/transfers").await;
+    assert!(body.as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn pays_to_account_numbers_and_payees() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let payee = app.create_payee(id, "Electricity", "87654321").await;
+
+    let (status, _) = app.pay(payment(id).amount(25.0)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.pay(payment(id).amount(4.5).payee(payee)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.balance(id).await, 70.5);
+
+    let (status, body) = app.get(&format!("/customers/{}/payments", id)).await;
+    assert_eq!(status, StatusCode::OK);
+    let payments = body.as_array().unwrap();
+    assert_eq!(payments.len(), 2);
+    assert!(payments.iter().any(|x| x["receiverCode"] == "87654321"));
+}
+
+#[actix_web::test]
+async fn rejects_invalid_payments() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let frozen = app
+        .create_customer(customer("Alan Turing").balance(100.0).frozen())
+        .await;
+
+    let cases = [
+        (payment(99), StatusCode::NOT_FOUND),
+        (payment(id).payee(99), StatusCode::NOT_FOUND),
+        (payment(frozen), StatusCode::FORBIDDEN),
+        (payment(id).amount(101.0), StatusCode::BAD_REQUEST),
+        (payment(id).amount(501.0), StatusCode::BAD_REQUEST),
+        (payment(id).amount(-10.0), StatusCode::UNPROCESSABLE_ENTITY),
+        (payment(id).amount(0.0), StatusCode::UNPROCESSABLE_ENTITY),
+        (
+            payment(id).receiver_code(None),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+        (
+            payment(id).reference("RF00539007547034"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+    ];
+    for (payment, expected) in cases {
+        let body = payment.json();
+        let (status, _) = app.pay(payment).await;
+        assert_eq!(status, expected, "{}", body);
+    }
+
+    assert_eq!(app.balance(id).await, 100.0);
+    let (_, body) = app.get(&format!("/customers/{}/payments", id)).await;
+    assert!(body.as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn admins_reverse_transfers_and_payments() {
+    let app = app().await;
+    let from = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let to = app.create_customer(customer("Alan Turing")).await;
+    app.transfer(transfer(from, to).amount(40.0)).await;
+    app.pay(payment(from).amount(10.0)).await;
+
+    let (_, transfers) = app.get("/transfers").await;
+    let transfer = transfers[0]["id"].as_u64().unwrap();
+    let (_, payments) = app.get(&format!("/customers/{}/payments", from)).await;
+    let payment = payments[0]["id"].as_u64().unwrap();
+
+    for uri in [
+        format!("/transfers/{}/reversals", transfer),
+        format!("/payments/{}/reversals", payment),
+    ] {
+        let (status, _) = app.post(&uri, json!({})).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", uri);
+        for amount in [0.0, -1.0] {
+            let (status, _) = app
+                .admin_send(TestRequest::post().uri(&uri), json!({ "amount": amount }))
+                .await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", uri);
+        }
+
+        // a partial refund, then whatever is left
+        let (status, body) = app
+            .admin_send(TestRequest::post().uri(&uri), json!({ "amount": 0.5 }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert!(body["message"].as_str().unwrap().ends_with(": 0.5"));
+        let (status, _) = app
+            .admin_send(TestRequest::post().uri(&uri), json!({}))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        let (status, _) = app
+            .admin_send(TestRequest::post().uri(&uri), json!({}))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+    }
+    assert_eq!(app.balance(from).await, 100.0);
+    assert_eq!(app.balance(to).await, 0.0);
+
+    for uri in ["/transfers/99/reversals", "/payments/99/reversals"] {
+        let (status, _) = app
+            .admin_send(TestRequest::post().uri(uri), json!({}))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+    }
+
+    let (status, _) = app.get("/reversals").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = app.admin_get("/reversals").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 4);
+}
@@ -0,0 +1,110 @@
+use crate::fixtures::{app, customer, payment};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use serde_json::json;
+
+#[actix_web::test]
+async fn manages_payees() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let payee = app.create_payee(id, "Electricity", "87654321").await;
+    let uri = format!("/customers/{}/payees/{}", id, payee);
+
+    let (status, body) = app.get(&uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["receiverCode"], "87654321");
+
+    let (status, _) = app
+        .put(
+            &uri,
+            json!({ "name": "Power company", "receiverCode": "11223344", "nickname": "power" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.get(&uri).await;
+    assert_eq!(body["name"], "Power company");
+    assert_eq!(body["nickname"], "power");
+
+    let (status, _) = app.send(TestRequest::delete().uri(&uri)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.get(&uri).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = app.get(&format!("/customers/{}/payees", id)).await;
+    assert!(body.as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_invalid_payees() {
+    let app = app().await;
+    let id = app.create_customer(customer("Ada Lovelace")).await;
+    let payee = app.create_payee(id, "Electricity", "87654321").await;
+    let uri = format!("/customers/{}/payees", id);
+
+    let (status, _) = app
+        .post(&uri, json!({ "name": "El", "receiverCode": "87654321" }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app
+        .post(&uri, json!({ "name": "Electricity", "receiverCode": "" }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app
+        .put(
+            &format!("{}/{}", uri, payee),
+            json!({ "name": "El", "receiverCode": "87654321" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let valid = json!({ "name": "Electricity", "receiverCode": "87654321" });
+    let (status, _) = app.post("/customers/99/payees", valid.clone()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.put(&format!("{}/99", uri), valid).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .send(TestRequest::delete().uri(&format!("{}/99", uri)))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn new_payees_receive_limited_amounts() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(5000.0).kyc("full"))
+        .await;
+    let payee = app.create_payee(id, "Landlord", "87654321").await;
+
+    let (status, body) = app.pay(payment(id).amount(1001.0).payee(payee)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "payee is still in its cooling-off period");
+
+    let (status, _) = app.pay(payment(id).amount(1000.0).payee(payee)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.balance(id).await, 4000.0);
+}
+
+#[actix_web::test]
+async fn payments_remember_their_payee() {
+    let app = app().await;
+    let id = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let uri = format!("/customers/{}/payees", id);
+    let (status, body) = app
+        .post(
+            &uri,
+            json!({ "name": "Landlord", "accountNumber": "DE89370400440532013000" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (_, payees) = app.get(&uri).await;
+    assert_eq!(payees[0]["accountNumber"], "DE89370400440532013000");
+    let payee = payees[0]["id"].as_u64().unwrap() as u16;
+
+    let (status, body) = app.pay(payment(id).amount(10.0).payee(payee)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (_, payments) = app.get(&format!("/customers/{}/payments", id)).await;
+    assert_eq!(payments[0]["payeeId"], payee);
+    assert_eq!(payments[0]["receiverCode"], "DE89370400440532013000");
+}
@@ -0,0 +1,42 @@
+use crate::fixtures::{app_limited, backend};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use bank::ratelimit::{Quota, RateLimiter};
+
+fn header<B>(res: &actix_web::dev::ServiceResponse<B>, name: &str) -> String {
+    res.headers()
+        .get(name)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[actix_web::test]
+async fn throttles_requests_over_the_quota() {
+    let backend = backend();
+    let quota = Quota {
+        requests: 1,
+        seconds: 60,
+    };
+    let app = app_limited(&backend, RateLimiter::uniform(quota)).await;
+
+    let res = app.call(TestRequest::get().uri("/customers")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(header(&res, "ratelimit-limit"), "1");
+    assert_eq!(header(&res, "ratelimit-remaining"), "0");
+
+    let res = app.call(TestRequest::get().uri("/customers")).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&res, "retry-after"), "60");
+    assert_eq!(header(&res, "ratelimit-limit"), "1");
+    assert_eq!(header(&res, "ratelimit-remaining"), "0");
+    assert_eq!(header(&res, "ratelimit-reset"), "60");
+
+    // health checks are never limited, and each app keeps buckets of its own
+    let res = app.call(TestRequest::get().uri("/health/live")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let other = app_limited(&backend, RateLimiter::uniform(quota)).await;
+    let res = other.call(TestRequest::get().uri("/customers")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
@@ -0,0 +1,158 @@
+use crate::fixtures::{app_on, customer, transfer};
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use bank::database::storage::Backend;
+use bank::webhooks::{Dispatcher, HttpTransport, Transport};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+static SECRET: &str = "0123456789abcdef";
+
+/// A request the stub received: its headers, lowercased, and its body.
+struct Received {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+/// Answers the next request to `listener` with `status`.
+fn stub(listener: &TcpListener, status: u16) -> JoinHandle<Received> {
+    let listener = listener.try_clone().unwrap();
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let mut headers = HashMap::new();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some((name, value)) => headers.insert(name.to_lowercase(), value.to_string()),
+                None => break,
+            };
+        }
+        let mut body = vec![0; headers["content-length"].parse().unwrap()];
+        reader.read_exact(&mut body).unwrap();
+
+        write!(
+            &stream,
+            "HTTP/1.1 {} Stub\r\nContent-Length: 0\r\n\r\n",
+            status
+        )
+        .unwrap();
+        Received {
+            headers,
+            body: String::from_utf8(body).unwrap(),
+        }
+    })
+}
+
+fn run_once(backend: &Backend) -> usize {
+    let dispatcher = Dispatcher::new(HttpTransport {
+        timeout: Duration::from_secs(5),
+    });
+    dispatcher.run_once(&*backend.storage).unwrap()
+}
+
+#[actix_web::test]
+async fn delivers_signed_events_and_retries_with_backoff() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let backend = Backend::memory().unwrap();
+    let app = app_on(&backend).await;
+    let webhook = json!({
+        "url": format!("http://{}/hooks", listener.local_addr().unwrap()),
+        "secret": SECRET,
+        "eventTypes": ["transfer.created"],
+    });
+    let (status, _) = app
+        .admin_send(TestRequest::post().uri("/webhooks"), webhook)
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, webhooks) = app.admin_get("/webhooks").await;
+    let deliveries = format!("/webhooks/{}/deliveries", webhooks[0]["id"]);
+
+    let from = app
+        .create_customer(customer("Ada Lovelace").balance(100.0))
+        .await;
+    let to = app.create_customer(customer("Alan Turing")).await;
+    app.transfer(transfer(from, to).amount(40.0)).await;
+
+    // the first two attempts fail and are spaced 10s, then 20s apart
+    let conn = rusqlite::Connection::open(backend.database.as_ref().unwrap()).unwrap();
+    for (attempt, delay) in [(1, 10), (2, 20)] {
+        let request = stub(&listener, 500);
+        assert_eq!(run_once(&backend), 1);
+        request.join().unwrap();
+
+        let (_, body) = app.admin_get(&deliveries).await;
+        let delivery: &Value = &body[0];
+        assert_eq!(delivery["status"], "pending");
+        assert_eq!(delivery["attempts"], attempt);
+        assert_eq!(delivery["lastStatusCode"], 500);
+        let wait = delivery["nextAttemptAt"].as_i64().unwrap() - Utc::now().timestamp();
+        assert!((delay - 2..=delay).contains(&wait), "waits {}s", wait);
+
+        // nothing is due until then
+        assert_eq!(run_once(&backend), 0);
+        conn.execute("UPDATE webhook_deliveries SET next_attempt_at = 0", [])
+            .unwrap();
+    }
+
+    let request = stub(&listener, 204);
+    assert_eq!(run_once(&backend), 1);
+    let received = request.join().unwrap();
+
+    let (_, body) = app.admin_get(&deliveries).await;
+    assert_eq!(body[0]["status"], "delivered");
+    assert_eq!(body[0]["attempts"], 3);
+    assert_eq!(body[0]["lastStatusCode"], 204);
+    assert!(body[0]["nextAttemptAt"].is_null());
+
+    let event: Value = serde_json::from_str(&received.body).unwrap();
+    assert_eq!(event["type"], "transfer.created");
+    assert_eq!(event["data"]["amount"], 40.0);
+    assert_eq!(received.headers["x-webhook-event"], "transfer.created");
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(
+        format!(
+            "{}.{}",
+            received.headers["x-webhook-timestamp"], received.body
+        )
+        .as_bytes(),
+    );
+    let signature = received.headers["x-webhook-signature"]
+        .strip_prefix("sha256=")
+        .unwrap();
+    let expected: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect();
+    assert_eq!(signature, expected);
+}
+
+#[actix_web::test]
+async fn posts_to_ipv6_addresses() {
+    let listener = TcpListener::bind("[::1]:0").unwrap();
+    let request = stub(&listener, 200);
+    let transport = HttpTransport {
+        timeout: Duration::from_secs(5),
+    };
+
+    let host = format!("[::1]:{}", listener.local_addr().unwrap().port());
+    let url = format!("http://{}/hooks", host);
+    assert_eq!(transport.post(&url, &[], "{}"), Ok(200));
+    let received = request.join().unwrap();
+    assert_eq!(received.headers["host"], host);
+    assert_eq!(received.body, "{}");
+}
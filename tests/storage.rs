@@ -0,0 +1,252 @@
+//! The same checks against every storage backend. The PostgreSQL ones run
+//! when `BANK_TEST_POSTGRES_URL` is set, each in a schema of its own, e.g.
+//! `BANK_TEST_POSTGRES_URL="host=localhost user=postgres" cargo test`.
+
+use bank::database::models::{Batch, Customer, Payment};
+use bank::database::postgres::PostgresStorage;
+use bank::database::storage::{BatchItem, SqliteStorage, Storage, StorageError};
+use chrono::Utc;
+
+fn sqlite() -> Option<Box<dyn Storage>> {
+    let path = std::env::temp_dir().join(format!("bank-test-{}.sqlite", uuid::Uuid::new_v4()));
+    Some(Box::new(
+        SqliteStorage::open(path.to_str().unwrap()).unwrap(),
+    ))
+}
+
+fn memory() -> Option<Box<dyn Storage>> {
+    Some(Box::new(SqliteStorage::memory().unwrap()))
+}
+
+fn postgres() -> Option<Box<dyn Storage>> {
+    let url = match std::env::var("BANK_TEST_POSTGRES_URL") {
+        Ok(x) => x,
+        Err(_) => {
+            eprintln!("BANK_TEST_POSTGRES_URL is not set, skipping");
+            return None;
+        }
+    };
+    let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+    let mut config: ::postgres::Config = url.parse().unwrap();
+
+    let mut client = config.connect(::postgres::NoTls).unwrap();
+    client
+        .batch_execute(&format!("CREATE SCHEMA {}", schema))
+        .unwrap();
+    config.options(&format!("-c search_path={}", schema));
+    Some(Box::new(PostgresStorage::with_config(config).unwrap()))
+}
+
+fn customer(storage: &dyn Storage, name: &str, balance: f64) -> u16 {
+    let customer: Customer = serde_json::from_value(serde_json::json!({
+        "name": name,
+        "balance": balance,
+        "created_at": Utc::now().to_rfc2822(),
+    }))
+    .unwrap();
+    storage.create_customer(&customer).unwrap();
+
+    storage
+        .get_all_customers()
+        .unwrap()
+        .iter()
+        .filter(|x| x.name == name)
+        .filter_map(|x| x.id)
+        .max()
+        .unwrap()
+}
+
+fn report() -> Batch {
+    Batch {
+        id: None,
+        created_at: Some(Utc::now().to_rfc2822()),
+        format: "json".to_string(),
+        mode: "atomic".to_string(),
+        status: "completed".to_string(),
+        succeeded: 2,
+        failed: 0,
+        lines: Vec::new(),
+    }
+}
+
+fn balance(storage: &dyn Storage, id: u16) -> f64 {
+    storage.get_customer(id).unwrap().balance.unwrap()
+}
+
+fn creates_customers(storage: &dyn Storage) {
+    let id = customer(storage, "Ada Lovelace", 250.0);
+    customer(storage, "Alan Turing", 0.0);
+
+    let found = storage.get_customer(id).unwrap();
+    assert_eq!(found.name, "Ada Lovelace");
+    assert_eq!(found.balance, Some(250.0));
+    assert_eq!(found.status.as_deref(), Some("active"));
+    assert_eq!(found.kyc_level.as_deref(), Some("unverified"));
+    assert_eq!(storage.get_all_customers().unwrap().len(), 2);
+}
+
+fn missing_customer_is_not_found(storage: &dyn Storage) {
+    assert!(matches!(
+        storage.get_customer(999),
+        Err(StorageError::NotFound)
+    ));
+}
+
+fn movements_change_the_balance(storage: &dyn Storage) {
+    let id = customer(storage, "Grace Hopper", 100.0);
+
+    storage.create_movement(id, "deposit", 50.0).unwrap();
+    storage.create_movement(id, "withdrawal", -30.0).unwrap();
+    assert_eq!(balance(storage, id), 120.0);
+
+    assert!(matches!(
+        storage.create_movement(id, "withdrawal", -121.0),
+        Err(StorageError::InsufficientFunds)
+    ));
+    assert_eq!(balance(storage, id), 120.0);
+}
+
+fn lists_transfers(storage: &dyn Storage) {
+    let from = customer(storage, "Ada Lovelace", 100.0);
+    let to = customer(storage, "Alan Turing", 0.0);
+
+    storage.create_transfer(from, to, 40.0).unwrap();
+    assert_eq!(balance(storage, from), 60.0);
+    assert_eq!(balance(storage, to), 40.0);
+
+    let all = storage.get_all_transfers().unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].name_from, "Ada Lovelace");
+    assert_eq!(all[0].name_to, "Alan Turing");
+    assert_eq!(all[0].amount, 40.0);
+
+    let sent = storage.get_transfers_by_customer(from).unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].name_from, "");
+    assert_eq!(sent[0].name_to, "Alan Turing");
+    assert!(storage.get_transfers_by_customer(to).unwrap().is_empty());
+}
+
+fn uncovered_transfers_change_nothing(storage: &dyn Storage) {
+    let from = customer(storage, "Ada Lovelace", 100.0);
+    let to = customer(storage, "Alan Turing", 0.0);
+
+    assert!(matches!(
+        storage.create_transfer(from, to, 100.5),
+        Err(StorageError::InsufficientFunds)
+    ));
+    // the credit fails after the debit went through, which is rolled back
+    assert!(storage.create_transfer(from, 999, 10.0).is_err());
+
+    assert_eq!(balance(storage, from), 100.0);
+    assert_eq!(balance(storage, to), 0.0);
+    assert!(storage.get_all_transfers().unwrap().is_empty());
+}
+
+fn payments_set_the_balance(storage: &dyn Storage) {
+    let id = customer(storage, "Ada Lovelace", 100.0);
+    let payment = Payment {
+        id: None,
+        created_at: Some(Utc::now().to_rfc2822()),
+        customer_id: Some(id),
+        amount: 25.0,
+        receiver_code: "12345678".to_string(),
+        reference: "invoice 1".to_string(),
+        note: Some("march".to_string()),
+        payee_id: None,
+    };
+
+    storage.create_payment(&payment).unwrap();
+    assert_eq!(balance(storage, id), 75.0);
+
+    let payments = storage.get_payments_by_customer(id).unwrap();
+    assert_eq!(payments.len(), 1);
+    assert_eq!(payments[0].customer_id, Some(id));
+    assert_eq!(payments[0].amount, 25.0);
+    assert_eq!(payments[0].receiver_code, "12345678");
+    assert_eq!(payments[0].reference, "invoice 1");
+    assert_eq!(payments[0].note.as_deref(), Some("march"));
+
+    let payment = Payment {
+        amount: 76.0,
+        ..payment
+    };
+    assert!(matches!(
+        storage.create_payment(&payment),
+        Err(StorageError::InsufficientFunds)
+    ));
+    assert_eq!(balance(storage, id), 75.0);
+    assert_eq!(storage.get_payments_by_customer(id).unwrap().len(), 1);
+}
+
+fn batches_are_all_or_nothing(storage: &dyn Storage) {
+    let from = customer(storage, "Ada Lovelace", 100.0);
+    let to = customer(storage, "Alan Turing", 0.0);
+    let payment = Payment {
+        id: None,
+        created_at: Some(Utc::now().to_rfc2822()),
+        customer_id: Some(from),
+        amount: 30.0,
+        receiver_code: "12345678".to_string(),
+        reference: "invoice 1".to_string(),
+        note: None,
+        payee_id: None,
+    };
+    let transfer = |amount| BatchItem::Transfer {
+        id_from: from,
+        id_to: to,
+        amount,
+    };
+
+    let id = storage
+        .create_batch(
+            &[BatchItem::Payment(payment.clone()), transfer(50.0)],
+            &report(),
+        )
+        .unwrap();
+    assert!(id > 0);
+    assert_eq!(balance(storage, from), 20.0);
+    assert_eq!(balance(storage, to), 50.0);
+
+    // each item is covered on its own, but not together
+    let result = storage.create_batch(&[transfer(15.0), transfer(15.0)], &report());
+    match result {
+        Err(StorageError::InBatch(1, e)) => {
+            assert!(matches!(*e, StorageError::InsufficientFunds))
+        }
+        _ => panic!("expected the second item to fail"),
+    }
+    assert_eq!(balance(storage, from), 20.0);
+    assert_eq!(balance(storage, to), 50.0);
+    assert_eq!(storage.get_all_transfers().unwrap().len(), 1);
+    assert_eq!(storage.get_payments_by_customer(from).unwrap().len(), 1);
+}
+
+#[test]
+fn memory_databases_are_separate() {
+    let first = memory().unwrap();
+    let second = memory().unwrap();
+
+    customer(&*first, "Ada Lovelace", 100.0);
+    assert_eq!(first.get_all_customers().unwrap().len(), 1);
+    assert!(second.get_all_customers().unwrap().is_empty());
+}
+
+macro_rules! suite {
+    ($backend:ident: $($check:ident),*) => {
+        mod $backend {
+            $(
+                #[test]
+                fn $check() {
+                    if let Some(storage) = super::$backend() {
+                        super::$check(&*storage);
+                    }
+                }
+            )*
+        }
+    };
+}
+
+suite!(sqlite: creates_customers, missing_customer_is_not_found, movements_change_the_balance, lists_transfers, uncovered_transfers_change_nothing, payments_set_the_balance, batches_are_all_or_nothing);
+suite!(memory: creates_customers, missing_customer_is_not_found, movements_change_the_balance, lists_transfers, uncovered_transfers_change_nothing, payments_set_the_balance, batches_are_all_or_nothing);
+suite!(postgres: creates_customers, missing_customer_is_not_found, movements_change_the_balance, lists_transfers, uncovered_transfers_change_nothing, payments_set_the_balance, batches_are_all_or_nothing);